@@ -0,0 +1,16 @@
+#!/bin/sh
+# Downloads the test ROMs the unit tests load from priv/. The ROMs aren't
+# ours to commit, so they come from the nes-test-roms collection.
+set -e
+
+BASE=https://raw.githubusercontent.com/christopherpow/nes-test-roms/master
+cd "$(dirname "$0")/../priv"
+
+curl -fsSL -o nestest.nes "$BASE/other/nestest.nes"
+
+mkdir -p ppu_vbl_nmi
+for rom in 01-vbl_basics 02-vbl_set_time 03-vbl_clear_time 04-nmi_control \
+    05-nmi_timing 06-suppression 07-nmi_on_timing 08-nmi_off_timing \
+    09-even_odd_frames 10-even_odd_timing; do
+    curl -fsSL -o "ppu_vbl_nmi/$rom.nes" "$BASE/ppu_vbl_nmi/rom_singles/$rom.nes"
+done
@@ -1,13 +1,535 @@
-pub struct Apu {}
+mod blip;
+mod dmc;
+mod envelope;
+mod expansion;
+mod filter;
+mod frame_counter;
+mod length_counter;
+mod mixer;
+mod noise;
+mod opll;
+mod pulse;
+mod scope;
+mod triangle;
+mod vrc6;
+mod vrc7;
+
+use blip::BlipBuffer;
+use dmc::Dmc;
+use filter::{Filter, FilterKind};
+use frame_counter::{FrameCounter, FrameStep};
+use noise::Noise;
+use pulse::{Pulse, PulseChannel};
+use triangle::Triangle;
+
+pub use expansion::ExpansionAudio;
+pub use mixer::{Channel, Mixer};
+pub use scope::{Scope, SCOPE_LENGTH};
+pub use vrc6::Vrc6Audio;
+pub use vrc7::Vrc7Audio;
+
+use crate::region::Region;
+
+/// Default rate of the audio samples the APU produces
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// What the APU last saw of a cartridge sound channel
+struct ExpansionChannel {
+    channel: Channel,
+    level: f32,
+    weight: f32,
+    frequency: Option<f64>, // Updated with the scope
+}
+
+pub struct Apu {
+    pulse1: Pulse, // $4000-$4003
+    pulse2: Pulse, // $4004-$4007
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    odd_cycle: bool, // Channel timers run at half the CPU clock
+    region: Region,
+    mixer: Mixer,
+    level: f32, // Mixer output as of the last change
+    blip: BlipBuffer,
+    frame_cycles: u32, // CPU cycles since the samples were last taken
+    sample_rate: u32,
+    rate_adjustment: f64, // From dynamic rate control
+    // The console's high-pass and low-pass filters, in order
+    filters: [Filter; 3],
+    scope: Scope,
+    expansion: Vec<ExpansionChannel>,
+}
 
 impl Apu {
-    pub fn new() -> Apu {
-        Apu {}
+    pub fn new(region: Region) -> Apu {
+        Apu {
+            pulse1: Pulse::new(PulseChannel::One),
+            pulse2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::new(),
+            noise: Noise::new(region),
+            dmc: Dmc::new(region),
+            frame_counter: FrameCounter::new(),
+            odd_cycle: false,
+            region,
+            mixer: Mixer::new(),
+            level: 0.0,
+            blip: BlipBuffer::new(region.cpu_clock_rate(), SAMPLE_RATE),
+            frame_cycles: 0,
+            sample_rate: SAMPLE_RATE,
+            rate_adjustment: 1.0,
+            filters: [
+                Filter::new(FilterKind::HighPass, 90.0, SAMPLE_RATE),
+                Filter::new(FilterKind::HighPass, 440.0, SAMPLE_RATE),
+                Filter::new(FilterKind::LowPass, 14_000.0, SAMPLE_RATE),
+            ],
+            scope: Scope::new(Channel::ALL.len()),
+            expansion: Vec::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.update_rates();
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.update_rates();
+        for filter in self.filters.iter_mut() {
+            filter.set_sample_rate(sample_rate);
+        }
+    }
+
+    /// Scales the number of samples produced per frame slightly, so audio
+    /// output can keep pace with a display running at a different rate. The
+    /// filters still assume the nominal rate, which is close enough.
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.rate_adjustment = ratio;
+        self.update_rates();
+    }
+
+    fn update_rates(&mut self) {
+        let sample_rate = self.sample_rate as f64 * self.rate_adjustment;
+        self.blip
+            .set_rates(self.region.cpu_clock_rate(), sample_rate);
+    }
+
+    /// Reads $4015, the only readable register. It reports which channels
+    /// are still playing and the pending IRQs, acknowledging the frame IRQ.
+    pub fn read(&mut self, address: u16) -> u8 {
+        if address != 0x4015 {
+            return 0;
+        }
+
+        let status = [
+            self.pulse1.length.active(),
+            self.pulse2.length.active(),
+            self.triangle.length.active(),
+            self.noise.length.active(),
+            self.dmc.active(),
+            false,
+            self.frame_counter.irq,
+            self.dmc.irq,
+        ];
+        self.frame_counter.irq = false;
+
+        status
+            .iter()
+            .enumerate()
+            .fold(0, |byte, (bit, &set)| byte | (set as u8) << bit)
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse1.write(address, data),
+            0x4004..=0x4007 => self.pulse2.write(address, data),
+            0x4008..=0x400B => self.triangle.write(address, data),
+            0x400C..=0x400F => self.noise.write(address, data),
+            0x4010..=0x4013 => self.dmc.write(address, data),
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0x01 != 0);
+                self.pulse2.length.set_enabled(data & 0x02 != 0);
+                self.triangle.length.set_enabled(data & 0x04 != 0);
+                self.noise.length.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+            }
+            0x4017 => self.frame_counter.write(data, self.odd_cycle),
+            _ => {}
+        }
+    }
+
+    /// Advances the APU by one CPU cycle
+    pub fn tick(&mut self) {
+        self.tick_with(None);
+    }
+
+    /// Advances the APU by one CPU cycle, mixing in the cartridge's sound
+    /// channels if it has any
+    pub fn tick_with(&mut self, expansion: Option<&dyn ExpansionAudio>) {
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        match self.frame_counter.tick(self.region) {
+            Some(FrameStep::Quarter) => self.clock_quarter_frame(),
+            Some(FrameStep::Half) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            None => {}
+        }
+
+        self.update_expansion(expansion);
+
+        let level = self.output();
+        if level != self.level {
+            self.blip.add_delta(self.frame_cycles, level - self.level);
+            self.level = level;
+        }
+        self.frame_cycles += 1;
+
+        if self.scope.tick() {
+            let mut levels = [0.0; Channel::ALL.len()];
+            for &channel in Channel::APU.iter() {
+                levels[channel.index()] =
+                    self.channel_output(channel) as f32 / channel.max_level() as f32;
+            }
+            let clock_rate = self.region.cpu_clock_rate();
+            for (i, state) in self.expansion.iter_mut().enumerate() {
+                levels[state.channel.index()] = state.level;
+                state.frequency = expansion.and_then(|audio| audio.frequency(i, clock_rate));
+            }
+            self.scope.record(&levels);
+        }
+    }
+
+    /// Takes the cartridge channels' levels for this cycle
+    fn update_expansion(&mut self, expansion: Option<&dyn ExpansionAudio>) {
+        let audio = match expansion {
+            Some(audio) => audio,
+            None => {
+                self.expansion.clear();
+                return;
+            }
+        };
+
+        let channels = audio.channels();
+        let known = self.expansion.iter().map(|state| state.channel);
+        if !known.eq(channels.iter().copied()) {
+            self.expansion = channels
+                .iter()
+                .map(|&channel| ExpansionChannel {
+                    channel,
+                    level: 0.0,
+                    weight: 0.0,
+                    frequency: None,
+                })
+                .collect();
+        }
+        for (i, state) in self.expansion.iter_mut().enumerate() {
+            state.level = audio.level(i);
+            state.weight = audio.weight(i);
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    fn channel_output(&self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Pulse1 => self.pulse1.output(),
+            Channel::Pulse2 => self.pulse2.output(),
+            Channel::Triangle => self.triangle.output(),
+            Channel::Noise => self.noise.output(),
+            Channel::Dmc => self.dmc.output(),
+            _ => 0,
+        }
+    }
+
+    /// Mixed output of all channels, from 0.0 to about 1.0 for the 2A03 and
+    /// more with expansion audio
+    fn output(&self) -> f32 {
+        let expansion: f32 = self
+            .expansion
+            .iter()
+            .map(|state| {
+                self.mixer
+                    .mix_expansion(state.channel, state.level, state.weight)
+            })
+            .sum();
+
+        self.mixer.mix(
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ) + expansion
+    }
+
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    /// For muting, soloing and changing the volume of channels. Changes are
+    /// heard from the next level change on.
+    pub fn mixer_mut(&mut self) -> &mut Mixer {
+        &mut self.mixer
+    }
+
+    /// Each channel's recent output, for the oscilloscope
+    pub fn scope(&self) -> &Scope {
+        &self.scope
+    }
+
+    /// The 2A03's channels and any the cartridge adds
+    pub fn channels(&self) -> Vec<Channel> {
+        let expansion = self.expansion.iter().map(|state| state.channel);
+        Channel::APU.iter().copied().chain(expansion).collect()
+    }
+
+    /// The note a channel is playing in Hz. The noise and DMC have no pitch,
+    /// so are always None, as are channels that are silent.
+    pub fn frequency(&self, channel: Channel) -> Option<f64> {
+        let clock_rate = self.region.cpu_clock_rate();
+        match channel {
+            Channel::Pulse1 => self.pulse1.frequency(clock_rate),
+            Channel::Pulse2 => self.pulse2.frequency(clock_rate),
+            Channel::Triangle => self.triangle.frequency(clock_rate),
+            Channel::Noise | Channel::Dmc => None,
+            _ => self
+                .expansion
+                .iter()
+                .find(|state| state.channel == channel)
+                .and_then(|state| state.frequency),
+        }
+    }
+
+    /// The address of the sample byte the DMC is waiting for, if any. The bus
+    /// fetches it and hands it over with `dmc_fill`.
+    pub fn dmc_dma_address(&self) -> Option<u16> {
+        self.dmc.dma_address()
+    }
+
+    pub fn dmc_fill(&mut self, data: u8) {
+        self.dmc.fill(data);
+    }
+
+    /// Whether the APU is holding the CPU's IRQ line low
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq || self.dmc.irq
+    }
+
+    /// Removes and returns the samples produced since the last call, from
+    /// -1.0 to 1.0. Frontends take them once per emulated frame.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.blip.end_frame(self.frame_cycles);
+        self.frame_cycles = 0;
+
+        let mut samples = self.blip.read_samples();
+        for sample in samples.iter_mut() {
+            for filter in self.filters.iter_mut() {
+                *sample = filter.process(*sample);
+            }
+        }
+
+        samples
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn produces_samples_at_the_output_rate() {
+        let mut apu = Apu::new(Region::Ntsc);
+
+        for _ in 0..Region::Ntsc.cpu_clock_rate() as usize {
+            apu.tick();
+        }
+
+        let samples = apu.take_samples().len() as i64;
+        assert!((samples - SAMPLE_RATE as i64).abs() <= 1);
+        assert!(apu.take_samples().is_empty());
     }
 
-    pub fn read(&mut self, _address: u16) -> u8 {
-        0
+    /// Difference between the highest and lowest sample
+    fn peak_to_peak(samples: &[f32]) -> f32 {
+        let high = samples.iter().copied().fold(f32::MIN, f32::max);
+        let low = samples.iter().copied().fold(f32::MAX, f32::min);
+        high - low
     }
 
-    pub fn write(&mut self, _address: u16, _data: u8) {}
+    #[test]
+    fn plays_pulses_enabled_through_status() {
+        let mut apu = Apu::new(Region::Ntsc);
+        let play = |apu: &mut Apu| {
+            apu.write(0x4004, 0x9F); // 50% duty, constant volume 15
+            apu.write(0x4006, 0xFD); // About 440Hz
+            apu.write(0x4007, 0x08);
+            for _ in 0..Region::Ntsc.cpu_clock_rate() as usize / 60 {
+                apu.tick();
+            }
+            apu.take_samples()
+        };
+
+        // Once the high-pass filters settle, disabled channels are silent
+        for _ in 0..10 {
+            play(&mut apu);
+        }
+        assert!(peak_to_peak(&play(&mut apu)) < 0.001);
+
+        apu.write(0x4015, 0x02);
+        assert!(peak_to_peak(&play(&mut apu)) > 0.2);
+    }
+
+    #[test]
+    fn mixes_in_expansion_audio() {
+        let mut apu = Apu::new(Region::Ntsc);
+        let mut vrc6 = Vrc6Audio::new();
+        vrc6.write(0x9000, 0x3F);
+        vrc6.write(0x9001, 0xFD);
+        vrc6.write(0x9002, 0x80);
+
+        let mut play = |apu: &mut Apu| {
+            for _ in 0..29830 {
+                vrc6.clock();
+                apu.tick_with(Some(&vrc6));
+            }
+            apu.take_samples()
+        };
+        for _ in 0..10 {
+            play(&mut apu);
+        }
+        assert!(peak_to_peak(&play(&mut apu)) > 0.2);
+        assert_eq!(apu.channels().len(), 8);
+        assert!(apu.frequency(Channel::Vrc6Pulse1).is_some());
+
+        apu.mixer_mut().set_muted(Channel::Vrc6Pulse1, true);
+        play(&mut apu);
+        assert!(peak_to_peak(&play(&mut apu)) < 0.001);
+
+        // Lanes go once the cartridge stops offering them
+        apu.tick();
+        assert_eq!(apu.channels(), Channel::APU);
+    }
+
+    #[test]
+    fn muting_leaves_emulation_alone() {
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.mixer_mut().set_muted(Channel::Pulse1, true);
+        apu.write(0x4015, 0x01);
+        apu.write(0x4000, 0x9F);
+        apu.write(0x4002, 0xFD);
+        apu.write(0x4003, 0x08);
+
+        for _ in 0..10 {
+            for _ in 0..29830 {
+                apu.tick();
+            }
+            apu.take_samples();
+        }
+        assert!(apu.frequency(Channel::Pulse1).is_some());
+        assert!(apu.scope().trace(0).any(|level| level == 1.0));
+        for _ in 0..29830 {
+            apu.tick();
+        }
+        assert!(peak_to_peak(&apu.take_samples()) < 0.001);
+    }
+
+    #[test]
+    fn resamples_to_the_chosen_rate() {
+        let count = |apu: &mut Apu| {
+            (0..60)
+                .map(|_| {
+                    for _ in 0..29830 {
+                        apu.tick();
+                    }
+                    apu.take_samples().len()
+                })
+                .sum::<usize>() as f64
+        };
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.set_sample_rate(48_000);
+
+        let expected = 48_000.0 * 60.0 * 29830.0 / Region::Ntsc.cpu_clock_rate();
+        assert!((count(&mut apu) - expected).abs() <= 1.0);
+
+        apu.set_rate_adjustment(1.005);
+        assert!((count(&mut apu) - expected * 1.005).abs() <= 1.0);
+    }
+
+    #[test]
+    fn length_counters_run_out_with_the_frame_counter() {
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.write(0x4015, 0x01);
+        apu.write(0x4000, 0x9F);
+        apu.write(0x4002, 0xFD);
+        apu.write(0x4003, 0x18); // Length 2, two half frames
+
+        for _ in 0..29830 {
+            apu.tick();
+        }
+        assert_eq!(apu.pulse1.output(), 0);
+        assert!(!apu.pulse1.length.active());
+    }
+
+    #[test]
+    fn status_reports_lengths_and_acknowledges_frame_irq() {
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.write(0x4015, 0x0F);
+        apu.write(0x4003, 0x08);
+        apu.write(0x400F, 0x08);
+        assert_eq!(apu.read(0x4015), 0x09);
+
+        for _ in 0..29830 {
+            apu.tick();
+        }
+        assert!(apu.irq());
+        assert_eq!(apu.read(0x4015) & 0x40, 0x40);
+        assert!(!apu.irq());
+        assert_eq!(apu.read(0x4015) & 0x40, 0);
+    }
+
+    #[test]
+    fn five_step_mode_clocks_lengths_on_reset() {
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.write(0x4015, 0x01);
+        apu.write(0x4003, 0x18); // Length 2
+
+        apu.write(0x4017, 0x80);
+        for _ in 0..4 {
+            apu.tick();
+        }
+        assert_eq!(apu.read(0x4015) & 0x01, 0x01);
+        for _ in 0..14913 {
+            apu.tick();
+        }
+        assert_eq!(apu.read(0x4015) & 0x01, 0x00);
+    }
 }
@@ -0,0 +1,160 @@
+use std::f64::consts::PI;
+
+// Positions between output samples the step shapes are tabulated for
+const PHASES: usize = 64;
+// Output samples each step is spread over
+const WIDTH: usize = 16;
+// Band limit as a fraction of the output sample rate, a little below Nyquist
+const CUTOFF: f64 = 0.45;
+
+/// Resamples a signal clocked at the CPU rate down to the output rate with
+/// band-limited step synthesis, after blargg's blip_buf. Rather than
+/// sampling the signal, which aliases its square edges, each change in level
+/// is added to the output as a step with the frequencies above the band
+/// limit removed.
+pub struct BlipBuffer {
+    factor: f64, // Output samples per input clock
+    offset: f64, // Output position of the current frame's first clock
+    deltas: Vec<f32>,
+    integrator: f32,
+    kernel: Vec<[f32; WIDTH]>,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        BlipBuffer {
+            factor: sample_rate as f64 / clock_rate,
+            offset: 0.0,
+            deltas: vec![0.0; WIDTH],
+            integrator: 0.0,
+            kernel: (0..=PHASES).map(kernel_phase).collect(),
+        }
+    }
+
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.factor = sample_rate / clock_rate;
+    }
+
+    /// Adds a change in level at a clock within the current frame
+    pub fn add_delta(&mut self, clock: u32, delta: f32) {
+        let position = self.offset + clock as f64 * self.factor;
+        let index = position as usize;
+        let phase = ((position - index as f64) * PHASES as f64).round() as usize;
+
+        if self.deltas.len() < index + WIDTH {
+            self.deltas.resize(index + WIDTH, 0.0);
+        }
+        for (sample, step) in self.deltas[index..]
+            .iter_mut()
+            .zip(self.kernel[phase].iter())
+        {
+            *sample += delta * step;
+        }
+    }
+
+    /// Ends the frame after some clocks, making the samples they cover
+    /// available
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.offset += clocks as f64 * self.factor;
+    }
+
+    /// Removes and returns the finished samples
+    pub fn read_samples(&mut self) -> Vec<f32> {
+        let count = self.offset as usize;
+        if self.deltas.len() < count + WIDTH {
+            self.deltas.resize(count + WIDTH, 0.0);
+        }
+
+        let integrator = &mut self.integrator;
+        let samples = self
+            .deltas
+            .drain(..count)
+            .map(|delta| {
+                *integrator += delta;
+                *integrator
+            })
+            .collect();
+        self.offset -= count as f64;
+
+        samples
+    }
+}
+
+/// The differences between consecutive output samples of a band-limited
+/// unit step, for a step some fraction of the way past the middle sample
+fn kernel_phase(phase: usize) -> [f32; WIDTH] {
+    let center = (WIDTH / 2 - 1) as f64 + phase as f64 / PHASES as f64;
+    let mut kernel = [0.0; WIDTH];
+
+    for (i, tap) in kernel.iter_mut().enumerate() {
+        let x = i as f64 - center;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * 2.0 * CUTOFF * x).sin() / (PI * 2.0 * CUTOFF * x)
+        };
+        // Blackman window over the kernel's width
+        let t = x / WIDTH as f64;
+        let window = 0.42 + 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos();
+
+        *tap = (sinc * window) as f32;
+    }
+
+    // Normalised so each step rises by exactly its delta
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|tap| *tap /= sum);
+    kernel
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn produces_samples_at_the_output_rate() {
+        let mut blip = BlipBuffer::new(1_789_773.0, 44_100);
+        let mut count = 0;
+        for _ in 0..60 {
+            blip.end_frame(29_830);
+            count += blip.read_samples().len();
+        }
+
+        assert!((count as i64 - 44_100).abs() <= 1);
+    }
+
+    #[test]
+    fn steps_settle_at_their_level() {
+        let mut blip = BlipBuffer::new(1_789_773.0, 44_100);
+        blip.add_delta(1000, 0.5);
+        blip.end_frame(10_000);
+        let samples = blip.read_samples();
+
+        assert_eq!(samples[0], 0.0);
+        assert!((samples.last().unwrap() - 0.5).abs() < 1e-5);
+        // Band limiting rounds the edge off, ringing a little either side
+        let rising: Vec<f32> = samples
+            .iter()
+            .copied()
+            .filter(|&s| s > 0.01 && s < 0.49)
+            .collect();
+        assert!(rising.len() >= 2);
+    }
+
+    #[test]
+    fn square_waves_above_nyquist_cancel_out() {
+        // A 30kHz square wave can't be represented at 44.1kHz, so should come
+        // out as (nearly) its average rather than aliasing
+        let mut blip = BlipBuffer::new(1_789_773.0, 44_100);
+        let half_period = 1_789_773 / 60_000;
+        let mut level = 1.0;
+        for clock in (0..100_000).step_by(half_period as usize) {
+            blip.add_delta(clock, level);
+            level = -level;
+        }
+        blip.end_frame(100_000);
+
+        let samples = blip.read_samples();
+        let settled = &samples[WIDTH..samples.len() - WIDTH];
+        assert!(settled.iter().all(|&s| (s - 0.5).abs() < 0.1));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::region::Region;
+
+/// The delta modulation channel at $4010-$4013. It plays 1-bit delta encoded
+/// samples that the bus fetches from CPU memory a byte at a time, nudging a
+/// 7-bit output level up or down for each bit.
+pub struct Dmc {
+    rates: &'static [u16; 16], // In CPU cycles, depending on the region
+    rate: usize,               // Index into the rates
+    timer: u16,
+    irq_enabled: bool,
+    looping: bool,
+    sample_address: u16,
+    sample_length: u16,
+    address: u16,
+    remaining: u16, // Sample bytes still to fetch
+    buffer: Option<u8>,
+    shift: u8,
+    bits: u8, // Bits left in the shift register
+    silence: bool,
+    level: u8,
+    pub irq: bool,
+}
+
+impl Dmc {
+    pub fn new(region: Region) -> Self {
+        Dmc {
+            rates: region.dmc_rates(),
+            rate: 0,
+            timer: 0,
+            irq_enabled: false,
+            looping: false,
+            sample_address: 0xC000,
+            sample_length: 1,
+            address: 0xC000,
+            remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits: 8,
+            silence: true,
+            level: 0,
+            irq: false,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.rates = region.dmc_rates();
+    }
+
+    /// Writes one of the channel's four registers, by its offset
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register & 0x03 {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = data & 0x40 != 0;
+                self.rate = (data & 0x0F) as usize;
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_address = 0xC000 + data as u16 * 64,
+            _ => self.sample_length = data as u16 * 16 + 1,
+        }
+    }
+
+    /// Starts the sample if it isn't playing, or stops it, from $4015. Either
+    /// way the IRQ is acknowledged.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+
+        if !enabled {
+            self.remaining = 0;
+        } else if self.remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.remaining = self.sample_length;
+    }
+
+    /// Whether sample bytes are still to be fetched
+    pub fn active(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// The address the bus should fetch the next sample byte from, once the
+    /// buffer has emptied
+    pub fn dma_address(&self) -> Option<u16> {
+        match self.buffer {
+            None if self.remaining > 0 => Some(self.address),
+            _ => None,
+        }
+    }
+
+    /// Takes a sample byte fetched from `dma_address`
+    pub fn fill(&mut self, data: u8) {
+        self.buffer = Some(data);
+        self.address = match self.address {
+            0xFFFF => 0x8000,
+            address => address + 1,
+        };
+        self.remaining -= 1;
+
+        if self.remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle, as the rates are in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rates[self.rate] - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits -= 1;
+        if self.bits == 0 {
+            self.bits = 8;
+            match self.buffer.take() {
+                Some(data) => {
+                    self.shift = data;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Current output level, from 0 to 127
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Plays a sample, fetching from `memory` as the bus would
+    fn play(dmc: &mut Dmc, memory: &[u8], cycles: usize) -> Vec<u8> {
+        (0..cycles)
+            .map(|_| {
+                if let Some(address) = dmc.dma_address() {
+                    dmc.fill(memory[(address - 0xC000) as usize]);
+                }
+                dmc.clock_timer();
+                dmc.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steps_the_level_per_bit() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write(0, 0x0F); // 54 cycles per bit
+        dmc.write(1, 0x40);
+        dmc.write(3, 0x00); // One byte
+        dmc.set_enabled(true);
+
+        let levels = play(&mut dmc, &[0x0F], 54 * 24);
+        let per_bit: Vec<u8> = levels.iter().step_by(54).copied().collect();
+
+        // The first byte starts playing after the bits already in the shift
+        // register, which is silent at power on
+        assert_eq!(&per_bit[..8], [0x40; 8]);
+        assert_eq!(
+            &per_bit[8..17],
+            [0x42, 0x44, 0x46, 0x48, 0x46, 0x44, 0x42, 0x40, 0x40]
+        );
+        assert!(!dmc.active());
+    }
+
+    #[test]
+    fn direct_load_clamps_to_seven_bits() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write(1, 0xFF);
+        assert_eq!(dmc.output(), 0x7F);
+    }
+
+    #[test]
+    fn raises_irq_unless_looping() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write(0, 0x80);
+        dmc.write(3, 0x01); // 17 bytes
+        dmc.set_enabled(true);
+        for _ in 0..17 {
+            dmc.fill(0);
+            dmc.buffer = None;
+        }
+        assert!(dmc.irq);
+        assert!(!dmc.active());
+
+        dmc.set_enabled(true);
+        assert!(!dmc.irq);
+        dmc.write(0, 0xC0);
+        for _ in 0..17 {
+            dmc.fill(0);
+            dmc.buffer = None;
+        }
+        assert!(!dmc.irq);
+        assert!(dmc.active());
+        assert_eq!(dmc.dma_address(), Some(0xC000));
+    }
+
+    #[test]
+    fn address_wraps_to_8000() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write(2, 0xFF);
+        dmc.write(3, 0x02);
+        dmc.set_enabled(true);
+        dmc.address = 0xFFFF;
+        dmc.fill(0);
+        dmc.buffer = None;
+
+        assert_eq!(dmc.dma_address(), Some(0x8000));
+    }
+}
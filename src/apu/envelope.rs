@@ -0,0 +1,97 @@
+/// Volume control for the pulse and noise channels: either a constant volume,
+/// or a decay from 15 down to 0 that can loop
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    volume: u8, // The constant volume, or the decay's divider period
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            start: false,
+            looping: false,
+            constant: false,
+            volume: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    /// Takes the --LC VVVV bits of a channel's first register
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0F;
+    }
+
+    /// Restarts the decay on the next quarter frame
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Clocked every quarter frame
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        match self.constant {
+            true => self.volume,
+            false => self.decay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decays_and_loops() {
+        let mut envelope = Envelope::new();
+        envelope.write(0x01); // Decay every second clock
+        envelope.restart();
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        for _ in 0..30 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 0);
+
+        envelope.write(0x21);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+    }
+
+    #[test]
+    fn constant_volume() {
+        let mut envelope = Envelope::new();
+        envelope.write(0x17);
+        envelope.restart();
+        envelope.clock();
+
+        assert_eq!(envelope.output(), 7);
+    }
+}
@@ -0,0 +1,21 @@
+use super::Channel;
+
+/// Sound channels on a cartridge, which the console mixes in with its own
+/// through the cartridge connector. Mappers with sound chips hand these to
+/// the APU every cycle.
+pub trait ExpansionAudio {
+    /// The chip's channels, in the order the other methods index them
+    fn channels(&self) -> &[Channel];
+
+    /// A channel's current output, from 0.0 to 1.0
+    fn level(&self, index: usize) -> f32;
+
+    /// How loud a channel at full level is in the mix, where the 2A03 with
+    /// every channel at full volume comes to about 1.0
+    fn weight(&self, index: usize) -> f32;
+
+    /// The note a channel is playing in Hz, for channels with a pitch
+    fn frequency(&self, _index: usize, _clock_rate: f64) -> Option<f64> {
+        None
+    }
+}
@@ -0,0 +1,83 @@
+use std::f32::consts::PI;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+/// A first order RC filter, like those between the 2A03 and the console's
+/// audio output
+pub struct Filter {
+    kind: FilterKind,
+    cutoff: f32,
+    alpha: f32,
+    input: f32, // Previous input
+    output: f32,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, cutoff: f32, sample_rate: u32) -> Self {
+        let mut filter = Filter {
+            kind,
+            cutoff,
+            alpha: 0.0,
+            input: 0.0,
+            output: 0.0,
+        };
+        filter.set_sample_rate(sample_rate);
+        filter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let rc = 1.0 / (2.0 * PI * self.cutoff);
+        let dt = 1.0 / sample_rate as f32;
+
+        self.alpha = match self.kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.output = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.output + input - self.input),
+            FilterKind::LowPass => self.output + self.alpha * (input - self.output),
+        };
+        self.input = input;
+
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Peak output level for a full scale sine wave, once settled
+    fn response(kind: FilterKind, cutoff: f32, frequency: f32) -> f32 {
+        let mut filter = Filter::new(kind, cutoff, 44_100);
+        (0..44_100)
+            .map(|i| filter.process((2.0 * PI * frequency * i as f32 / 44_100.0).sin()))
+            .skip(22_050)
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn high_pass_removes_offsets() {
+        let mut filter = Filter::new(FilterKind::HighPass, 90.0, 44_100);
+        let settled = (0..4410).map(|_| filter.process(0.5)).last().unwrap();
+
+        assert!(settled.abs() < 0.001);
+        assert!(response(FilterKind::HighPass, 90.0, 1000.0) > 0.95);
+        assert!(response(FilterKind::HighPass, 440.0, 50.0) < 0.2);
+    }
+
+    #[test]
+    fn low_pass_removes_high_frequencies() {
+        assert!(response(FilterKind::LowPass, 14_000.0, 100.0) > 0.99);
+
+        let at_cutoff = response(FilterKind::LowPass, 14_000.0, 14_000.0);
+        assert!(at_cutoff < 0.8 && at_cutoff > 0.5);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::region::Region;
+
+/// Which units a frame sequencer step clocks. Half frames also clock
+/// everything a quarter frame does.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FrameStep {
+    Quarter, // Envelopes and the triangle's linear counter
+    Half,    // Length counters and sweep units too
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    FourStep, // Raises the frame IRQ at the end of each sequence
+    FiveStep, // A longer sequence without the IRQ
+}
+
+/// Divides the CPU clock into the quarter and half frame steps that drive
+/// the channels' envelopes, length counters and sweeps, configured by $4017
+pub struct FrameCounter {
+    cycle: u32,
+    mode: Mode,
+    irq_inhibit: bool,
+    reset_delay: Option<u8>, // Cycles until a $4017 write restarts the sequence
+    pub irq: bool,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter {
+            cycle: 0,
+            mode: Mode::FourStep,
+            irq_inhibit: false,
+            reset_delay: None,
+            irq: false,
+        }
+    }
+
+    /// Takes MI-- ----. The sequence restarts three CPU cycles later when
+    /// written on a cycle that clocks the channel timers, or four otherwise.
+    pub fn write(&mut self, data: u8, timer_cycle: bool) {
+        self.mode = match data & 0x80 {
+            0 => Mode::FourStep,
+            _ => Mode::FiveStep,
+        };
+        self.irq_inhibit = data & 0x40 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+
+        self.reset_delay = Some(if timer_cycle { 3 } else { 4 });
+    }
+
+    /// Advances by one CPU cycle, returning the step taken on it if any
+    pub fn tick(&mut self, region: Region) -> Option<FrameStep> {
+        if let Some(delay) = self.reset_delay.as_mut() {
+            *delay -= 1;
+            if *delay == 0 {
+                self.reset_delay = None;
+                self.cycle = 0;
+
+                // Restarting in five step mode clocks everything at once
+                return match self.mode {
+                    Mode::FourStep => None,
+                    Mode::FiveStep => Some(FrameStep::Half),
+                };
+            }
+        }
+
+        let steps = region.frame_counter_steps();
+        self.cycle += 1;
+
+        match self.mode {
+            Mode::FourStep => {
+                // The IRQ flag is set over three cycles around the last step
+                if self.cycle >= steps[3] - 1 && !self.irq_inhibit {
+                    self.irq = true;
+                }
+                if self.cycle > steps[3] {
+                    self.cycle = 0;
+                }
+            }
+            Mode::FiveStep => {
+                if self.cycle > steps[4] {
+                    self.cycle = 0;
+                }
+            }
+        }
+
+        let last = match self.mode {
+            Mode::FourStep => steps[3],
+            Mode::FiveStep => steps[4],
+        };
+        if self.cycle == steps[0] || self.cycle == steps[2] {
+            Some(FrameStep::Quarter)
+        } else if self.cycle == steps[1] || self.cycle == last {
+            Some(FrameStep::Half)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The steps taken over some cycles, with the cycle each fell on
+    fn steps(counter: &mut FrameCounter, cycles: u32) -> Vec<(u32, FrameStep)> {
+        (1..=cycles)
+            .filter_map(|cycle| counter.tick(Region::Ntsc).map(|step| (cycle, step)))
+            .collect()
+    }
+
+    #[test]
+    fn four_step_sequence() {
+        let mut counter = FrameCounter::new();
+        let steps = steps(&mut counter, 29830 * 2);
+
+        assert_eq!(
+            &steps[..4],
+            [
+                (7457, FrameStep::Quarter),
+                (14913, FrameStep::Half),
+                (22371, FrameStep::Quarter),
+                (29829, FrameStep::Half),
+            ]
+        );
+        assert_eq!(steps[4], (29830 + 7457, FrameStep::Quarter));
+        assert_eq!(steps.len(), 8);
+        assert!(counter.irq);
+    }
+
+    #[test]
+    fn five_step_sequence() {
+        let mut counter = FrameCounter::new();
+        counter.write(0x80, true);
+        let steps = steps(&mut counter, 3 + 37282);
+
+        assert_eq!(steps[0], (3, FrameStep::Half));
+        assert_eq!(steps[3], (3 + 22371, FrameStep::Quarter));
+        assert_eq!(steps[4], (3 + 37281, FrameStep::Half));
+        assert_eq!(steps.len(), 5);
+        assert!(!counter.irq);
+    }
+
+    #[test]
+    fn irq_is_set_around_the_last_step() {
+        let mut counter = FrameCounter::new();
+        for _ in 0..29827 {
+            counter.tick(Region::Ntsc);
+        }
+        assert!(!counter.irq);
+        counter.tick(Region::Ntsc);
+        assert!(counter.irq);
+
+        // Clearing it on the next cycle doesn't stick
+        counter.irq = false;
+        counter.tick(Region::Ntsc);
+        assert!(counter.irq);
+        counter.irq = false;
+        counter.tick(Region::Ntsc);
+        assert!(counter.irq);
+        counter.irq = false;
+        counter.tick(Region::Ntsc);
+        assert!(!counter.irq);
+    }
+
+    #[test]
+    fn inhibit_clears_and_blocks_the_irq() {
+        let mut counter = FrameCounter::new();
+        counter.irq = true;
+        counter.write(0x40, false);
+        assert!(!counter.irq);
+
+        steps(&mut counter, 29830 * 2);
+        assert!(!counter.irq);
+    }
+
+    #[test]
+    fn reset_waits_for_the_write_delay() {
+        let mut counter = FrameCounter::new();
+        for _ in 0..1000 {
+            counter.tick(Region::Ntsc);
+        }
+
+        counter.write(0x00, false);
+        let steps = steps(&mut counter, 4 + 7457);
+        assert_eq!(steps, [(4 + 7457, FrameStep::Quarter)]);
+    }
+}
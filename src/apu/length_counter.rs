@@ -0,0 +1,76 @@
+#[rustfmt::skip]
+static LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Silences a channel once a loaded duration runs out, unless halted
+pub struct LengthCounter {
+    enabled: bool, // From $4015; a disabled counter stays at zero
+    pub halt: bool,
+    count: u8,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        LengthCounter {
+            enabled: false,
+            halt: false,
+            count: 0,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.count = 0;
+        }
+    }
+
+    /// Loads a duration from the lookup table, using the top five bits of a
+    /// channel's last register
+    pub fn load(&mut self, data: u8) {
+        if self.enabled {
+            self.count = LENGTHS[(data >> 3) as usize];
+        }
+    }
+
+    /// Clocked every half frame
+    pub fn clock(&mut self) {
+        if !self.halt && self.count > 0 {
+            self.count -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.count > 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_down_unless_halted() {
+        let mut length = LengthCounter::new();
+        length.load(0x08); // Index 1: 254
+        assert!(!length.active());
+
+        length.set_enabled(true);
+        length.load(0x18); // Index 3: 2
+        length.clock();
+        assert!(length.active());
+        length.clock();
+        assert!(!length.active());
+
+        length.load(0x18);
+        length.halt = true;
+        length.clock();
+        length.clock();
+        assert!(length.active());
+
+        length.set_enabled(false);
+        assert!(!length.active());
+    }
+}
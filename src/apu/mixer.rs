@@ -0,0 +1,263 @@
+use std::{fmt, str::FromStr};
+
+/// Sound channels, as addressed by the mixer: the 2A03's own, then those of
+/// the expansion chips cartridges can carry
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    Vrc6Pulse1,
+    Vrc6Pulse2,
+    Vrc6Saw,
+    Vrc7Fm1,
+    Vrc7Fm2,
+    Vrc7Fm3,
+    Vrc7Fm4,
+    Vrc7Fm5,
+    Vrc7Fm6,
+}
+
+const CHANNELS: usize = 14;
+
+impl Channel {
+    pub const ALL: [Channel; CHANNELS] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+        Channel::Vrc6Pulse1,
+        Channel::Vrc6Pulse2,
+        Channel::Vrc6Saw,
+        Channel::Vrc7Fm1,
+        Channel::Vrc7Fm2,
+        Channel::Vrc7Fm3,
+        Channel::Vrc7Fm4,
+        Channel::Vrc7Fm5,
+        Channel::Vrc7Fm6,
+    ];
+
+    /// The 2A03's channels, which every console has
+    pub const APU: [Channel; 5] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+    ];
+
+    /// Position in `ALL`
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// The highest level one of the 2A03's channels outputs
+    pub fn max_level(&self) -> u8 {
+        match self {
+            Channel::Dmc => 127,
+            _ => 15,
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pulse1" => Ok(Channel::Pulse1),
+            "pulse2" => Ok(Channel::Pulse2),
+            "triangle" => Ok(Channel::Triangle),
+            "noise" => Ok(Channel::Noise),
+            "dmc" => Ok(Channel::Dmc),
+            "vrc6-pulse1" => Ok(Channel::Vrc6Pulse1),
+            "vrc6-pulse2" => Ok(Channel::Vrc6Pulse2),
+            "vrc6-saw" => Ok(Channel::Vrc6Saw),
+            "vrc7-fm1" => Ok(Channel::Vrc7Fm1),
+            "vrc7-fm2" => Ok(Channel::Vrc7Fm2),
+            "vrc7-fm3" => Ok(Channel::Vrc7Fm3),
+            "vrc7-fm4" => Ok(Channel::Vrc7Fm4),
+            "vrc7-fm5" => Ok(Channel::Vrc7Fm5),
+            "vrc7-fm6" => Ok(Channel::Vrc7Fm6),
+            _ => Err(format!("Unknown channel {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+            Channel::Vrc6Pulse1 => "vrc6-pulse1",
+            Channel::Vrc6Pulse2 => "vrc6-pulse2",
+            Channel::Vrc6Saw => "vrc6-saw",
+            Channel::Vrc7Fm1 => "vrc7-fm1",
+            Channel::Vrc7Fm2 => "vrc7-fm2",
+            Channel::Vrc7Fm3 => "vrc7-fm3",
+            Channel::Vrc7Fm4 => "vrc7-fm4",
+            Channel::Vrc7Fm5 => "vrc7-fm5",
+            Channel::Vrc7Fm6 => "vrc7-fm6",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Combines the channel levels the way the 2A03's resistor network does. The
+/// pulses share one output pin and the triangle, noise and DMC another, and
+/// neither adds up linearly.
+///
+/// Expansion channels add to the output linearly, at the level their chip
+/// asks for.
+///
+/// Each channel can also be muted, soloed or have its volume changed. These
+/// only scale the levels going into the mix, so emulation carries on as is.
+pub struct Mixer {
+    volumes: [f32; CHANNELS],
+    muted: [bool; CHANNELS],
+    solo: Option<Channel>,
+    gains: [f32; CHANNELS], // What the above add up to
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Mixer {
+            volumes: [1.0; CHANNELS],
+            muted: [false; CHANNELS],
+            solo: None,
+            gains: [1.0; CHANNELS],
+        }
+    }
+
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.volumes[channel.index()]
+    }
+
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volumes[channel.index()] = volume.max(0.0);
+        self.update_gains();
+    }
+
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.muted[channel.index()]
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel.index()] = muted;
+        self.update_gains();
+    }
+
+    pub fn solo(&self) -> Option<Channel> {
+        self.solo
+    }
+
+    /// Plays only this channel, or all of them again with None
+    pub fn set_solo(&mut self, solo: Option<Channel>) {
+        self.solo = solo;
+        self.update_gains();
+    }
+
+    /// How loud the channel ends up in the mix, from its volume, mute and
+    /// the solo
+    pub fn gain(&self, channel: Channel) -> f32 {
+        self.gains[channel.index()]
+    }
+
+    fn update_gains(&mut self) {
+        for channel in Channel::ALL.iter() {
+            let i = channel.index();
+            let audible = !self.muted[i] && self.solo.is_none_or(|solo| solo == *channel);
+            self.gains[i] = if audible { self.volumes[i] } else { 0.0 };
+        }
+    }
+
+    /// Output from 0.0 to about 1.0, from the pulse, triangle and noise
+    /// levels of 0-15 and the DMC's of 0-127
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let level = |channel: Channel, level: u8| level as f32 * self.gain(channel);
+
+        let pulse = level(Channel::Pulse1, pulse1) + level(Channel::Pulse2, pulse2);
+        let tnd = 3.0 * level(Channel::Triangle, triangle)
+            + 2.0 * level(Channel::Noise, noise)
+            + level(Channel::Dmc, dmc);
+
+        let mut output = 0.0;
+        if pulse > 0.0 {
+            output += 95.52 / (8128.0 / pulse + 100.0);
+        }
+        if tnd > 0.0 {
+            output += 163.67 / (24329.0 / tnd + 100.0);
+        }
+        output
+    }
+
+    /// An expansion channel's contribution to the output, from its level of
+    /// 0.0 to 1.0 and how loud its chip says that is
+    pub fn mix_expansion(&self, channel: Channel, level: f32, weight: f32) -> f32 {
+        level * weight * self.gain(channel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mixes_nonlinearly() {
+        let mixer = Mixer::new();
+
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0), 0.0);
+        assert!((mixer.mix(15, 15, 15, 15, 127) - 1.0).abs() < 0.01);
+
+        // Two pulses at full volume are quieter than twice one
+        let one = mixer.mix(15, 0, 0, 0, 0);
+        let both = mixer.mix(15, 15, 0, 0, 0);
+        assert!(both < one * 2.0);
+        assert_eq!(mixer.mix(15, 0, 0, 0, 0), mixer.mix(0, 15, 0, 0, 0));
+    }
+
+    #[test]
+    fn mutes_and_solos_channels() {
+        let mut mixer = Mixer::new();
+        let all = mixer.mix(15, 15, 15, 15, 127);
+
+        mixer.set_muted(Channel::Noise, true);
+        assert_eq!(mixer.mix(0, 0, 0, 15, 0), 0.0);
+        assert!(mixer.mix(15, 15, 15, 15, 127) < all);
+
+        // Solo overrides everything else
+        mixer.set_solo(Some(Channel::Triangle));
+        assert_eq!(mixer.mix(15, 15, 0, 15, 127), 0.0);
+        assert_eq!(mixer.mix(0, 0, 15, 0, 0), Mixer::new().mix(0, 0, 15, 0, 0));
+
+        assert_eq!(mixer.mix_expansion(Channel::Vrc6Saw, 1.0, 0.3), 0.0);
+
+        mixer.set_solo(None);
+        assert_eq!(mixer.mix_expansion(Channel::Vrc6Saw, 0.5, 0.3), 0.15);
+        assert_eq!(mixer.gain(Channel::Noise), 0.0);
+        assert_eq!(mixer.gain(Channel::Dmc), 1.0);
+    }
+
+    #[test]
+    fn scales_channel_volume() {
+        let mut mixer = Mixer::new();
+        mixer.set_volume(Channel::Pulse1, 0.5);
+
+        assert_eq!(mixer.mix(14, 0, 0, 0, 0), Mixer::new().mix(7, 0, 0, 0, 0));
+    }
+
+    #[test]
+    fn parses_channel_names() {
+        assert_eq!("Triangle".parse::<Channel>(), Ok(Channel::Triangle));
+        assert_eq!(Channel::Pulse2.to_string(), "pulse2");
+        assert_eq!("vrc6-saw".parse::<Channel>(), Ok(Channel::Vrc6Saw));
+        assert_eq!(Channel::Vrc7Fm6.to_string(), "vrc7-fm6");
+        assert!("saw".parse::<Channel>().is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use super::{envelope::Envelope, length_counter::LengthCounter};
+use crate::region::Region;
+
+/// The noise channel at $400C-$400F, a pseudo-random bit stream from a 15-bit
+/// linear feedback shift register
+pub struct Noise {
+    periods: &'static [u16; 16], // In CPU cycles, depending on the region
+    period: usize,               // Index into the periods
+    timer: u16,
+    short_mode: bool, // Feedback from bit 6 rather than 1, for a metallic tone
+    shift: u16,
+    envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Noise {
+    pub fn new(region: Region) -> Self {
+        Noise {
+            periods: region.noise_periods(),
+            period: 0,
+            timer: 0,
+            short_mode: false,
+            shift: 1,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = region.noise_periods();
+    }
+
+    /// Writes one of the channel's four registers, by its offset
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register & 0x03 {
+            0 => {
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.period = (data & 0x0F) as usize;
+            }
+            _ => {
+                self.length.load(data);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle, as the periods are in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.periods[self.period] - 1;
+
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Current output level, from 0 to 15
+    pub fn output(&self) -> u8 {
+        if self.shift & 1 != 0 || !self.length.active() {
+            return 0;
+        }
+
+        self.envelope.output()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn playing(mode: u8) -> Noise {
+        let mut noise = Noise::new(Region::Ntsc);
+        noise.length.set_enabled(true);
+        noise.write(0, 0x1F);
+        noise.write(2, mode);
+        noise.write(3, 0x08);
+        noise
+    }
+
+    /// Cycles before the shift register's sequence repeats
+    fn sequence_length(noise: &mut Noise) -> usize {
+        let start = noise.shift;
+        (1..)
+            .find(|_| {
+                for _ in 0..noise.periods[noise.period] {
+                    noise.clock_timer();
+                }
+                noise.shift == start
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn long_and_short_sequences() {
+        assert_eq!(sequence_length(&mut playing(0x00)), 32767);
+        assert_eq!(sequence_length(&mut playing(0x80)), 93);
+    }
+
+    #[test]
+    fn outputs_the_envelope_on_clear_bits() {
+        let mut noise = playing(0x00);
+        let mut levels = Vec::new();
+        for _ in 0..100 {
+            noise.clock_timer();
+            levels.push(noise.output());
+        }
+
+        assert!(levels.contains(&15));
+        assert!(levels.contains(&0));
+        assert!(levels.iter().all(|&level| level == 0 || level == 15));
+    }
+
+    #[test]
+    fn periods_follow_the_region() {
+        let mut noise = playing(0x02);
+        assert_eq!(noise.periods[noise.period], 16);
+
+        noise.set_region(Region::Pal);
+        assert_eq!(noise.periods[noise.period], 14);
+        noise.write(2, 0x0F);
+        assert_eq!(noise.periods[noise.period], 3778);
+    }
+}
@@ -0,0 +1,601 @@
+use std::f64::consts::PI;
+
+/// Channels the VRC7 has, of the YM2413's nine
+pub const FM_CHANNELS: usize = 6;
+
+// Outputs swing up to about this far either side of zero
+pub const FULL_SCALE: i32 = 4096;
+
+// The VRC7's built-in instruments, 1 to 15, as dumped from the chip. Each is
+// laid out like the custom instrument at registers $00-$07.
+static PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // Buzzy bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // Guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // Wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // Flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // Clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // Synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // Trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // Organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // Bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // Vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // Vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // Tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // Fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // Synth bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06], // Sweep
+];
+
+// Frequency multipliers, doubled so that the first can be a half
+static MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+// Attenuation at the top of each octave for key scaling, by the top four
+// bits of the F-number, in 0.75dB steps
+static KEY_SCALE_LEVELS: [i32; 16] = [
+    0, 32, 40, 45, 48, 51, 53, 55, 56, 58, 59, 60, 61, 62, 63, 64,
+];
+
+// F-number offsets over a vibrato cycle, by the top three bits of the
+// F-number, in halves
+static VIBRATO: [[i8; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0, 0, -1, 0],
+    [0, 1, 2, 1, 0, -1, -2, -1],
+    [0, 1, 3, 1, 0, -1, -3, -1],
+    [0, 2, 4, 2, 0, -2, -4, -2],
+    [0, 2, 5, 2, 0, -2, -5, -2],
+    [0, 3, 6, 3, 0, -3, -6, -3],
+    [0, 3, 7, 3, 0, -3, -7, -3],
+];
+
+// Which of every eight envelope clocks step, for the four rates in an octave
+static ENVELOPE_STEPS: [[u8; 8]; 4] = [
+    [0, 1, 0, 1, 0, 1, 0, 1],
+    [0, 1, 0, 1, 1, 1, 0, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 1],
+];
+
+// Envelope levels are 7 bits of 0.375dB, so this is silence
+const MAX_ATTENUATION: u8 = 127;
+// Where the damp before an attack hands over
+const DAMPED: u8 = 124;
+// The damp's rate and the release rates the sustain flag and percussive
+// instruments fall back on
+const DAMP_RATE: u8 = 12;
+const SUSTAIN_RELEASE_RATE: u8 = 5;
+const PERCUSSIVE_RELEASE_RATE: u8 = 7;
+
+// Tremolo goes up and back down over this many steps of 64 samples, and is
+// at most 4.875dB
+const TREMOLO_STEPS: u32 = 210;
+const TREMOLO_DEPTH: u32 = 13;
+
+/// How one of the two operators in a channel is set up
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Operator {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool, // Holds at the sustain level rather than decaying
+    key_scale_rate: bool,
+    multiple: u8,
+    key_scale_level: u8,
+    half_sine: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl Operator {
+    fn new(flags: u8, ksl_byte: u8, half_sine: bool, rates: u8, levels: u8) -> Self {
+        Operator {
+            tremolo: flags & 0x80 != 0,
+            vibrato: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiple: flags & 0x0F,
+            key_scale_level: ksl_byte >> 6,
+            half_sine,
+            attack: rates >> 4,
+            decay: rates & 0x0F,
+            sustain_level: levels >> 4,
+            release: levels & 0x0F,
+        }
+    }
+}
+
+/// An instrument: a modulator that bends the phase of a carrier
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Patch {
+    modulator: Operator,
+    carrier: Operator,
+    total_level: u8, // The modulator's attenuation, in 0.75dB steps
+    feedback: u8,
+}
+
+impl Patch {
+    fn from_bytes(bytes: &[u8; 8]) -> Self {
+        Patch {
+            modulator: Operator::new(bytes[0], bytes[2], bytes[3] & 0x08 != 0, bytes[4], bytes[6]),
+            carrier: Operator::new(bytes[1], bytes[3], bytes[3] & 0x10 != 0, bytes[5], bytes[7]),
+            total_level: bytes[2] & 0x3F,
+            feedback: bytes[3] & 0x07,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum EnvelopeState {
+    Damp, // Fading out whatever was playing before an attack
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// An operator's running state: its phase, envelope, and last two outputs
+#[derive(Debug, Copy, Clone)]
+struct Slot {
+    phase: u32, // 19 bits to a cycle
+    envelope: u8,
+    state: EnvelopeState,
+    outputs: [i32; 2], // Latest first
+}
+
+impl Slot {
+    fn new() -> Self {
+        Slot {
+            phase: 0,
+            envelope: MAX_ATTENUATION,
+            state: EnvelopeState::Release,
+            outputs: [0; 2],
+        }
+    }
+
+    fn key_on(&mut self) {
+        if self.envelope >= DAMPED {
+            self.start_attack();
+        } else {
+            self.state = EnvelopeState::Damp;
+        }
+    }
+
+    fn start_attack(&mut self) {
+        self.phase = 0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn clock_envelope(&mut self, operator: &Operator, rate: u8, counter: u32) {
+        if self.state == EnvelopeState::Attack && rate >= 60 {
+            self.envelope = 0;
+        }
+        let step = envelope_step(rate, counter);
+
+        match self.state {
+            EnvelopeState::Attack => {
+                if step > 0 && self.envelope > 0 {
+                    let envelope = self.envelope as u32;
+                    let fall = ((envelope + 1) * step as u32 + 7) >> 3;
+                    self.envelope = envelope.saturating_sub(fall) as u8;
+                }
+                if self.envelope == 0 {
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Damp => {
+                self.envelope = (self.envelope + step).min(MAX_ATTENUATION);
+                if self.envelope >= DAMPED {
+                    self.start_attack();
+                }
+            }
+            _ => {
+                self.envelope = (self.envelope + step).min(MAX_ATTENUATION);
+                let sustain_level = operator.sustain_level * 8;
+                if self.state == EnvelopeState::Decay && self.envelope >= sustain_level {
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+        }
+    }
+
+    /// Runs the phase on and works out the slot's output, with the phase
+    /// pushed along by `modulation`
+    fn output(
+        &mut self,
+        tables: &Tables,
+        increment: u32,
+        modulation: i32,
+        attenuation: u32,
+        half_sine: bool,
+    ) -> i32 {
+        self.phase = (self.phase + increment) & 0x7FFFF;
+        let index = ((self.phase >> 9) as i32 + modulation) as u32 & 0x3FF;
+
+        // Fully attenuated operators are switched off
+        let output = match attenuation {
+            attenuation if attenuation >= MAX_ATTENUATION as u32 => 0,
+            attenuation => tables.wave(index, attenuation, half_sine),
+        };
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+/// How far an envelope moves this sample, at a rate from 0 to 63. Rates
+/// four apart move twice as fast, and the three between step on more of
+/// every eight clocks.
+fn envelope_step(rate: u8, counter: u32) -> u8 {
+    let octave = rate >> 2;
+    if octave == 0 {
+        return 0;
+    }
+
+    let steps = &ENVELOPE_STEPS[(rate & 0x03) as usize];
+    if octave < 13 {
+        let shift = 13 - octave;
+        if counter & ((1 << shift) - 1) != 0 {
+            return 0;
+        }
+        steps[(counter >> shift) as usize & 7]
+    } else {
+        steps[counter as usize & 7] << (octave - 13)
+    }
+}
+
+/// The chip's lookup tables: a quarter of a sine wave as attenuation, and the
+/// powers of two that turn attenuation back into a level. Both have eight
+/// fractional bits, where 256 is a halving.
+struct Tables {
+    log_sin: [u16; 256],
+    exp: [u16; 256],
+}
+
+impl Tables {
+    fn new() -> Self {
+        let mut tables = Tables {
+            log_sin: [0; 256],
+            exp: [0; 256],
+        };
+        for i in 0..256 {
+            let sin = ((i as f64 + 0.5) * PI / 512.0).sin();
+            tables.log_sin[i] = (-sin.log2() * 256.0).round() as u16;
+            tables.exp[i] = (2f64.powf((255 - i) as f64 / 256.0) * 1024.0).round() as u16;
+        }
+        tables
+    }
+
+    /// A point on the wave at a 10-bit phase, quietened by an attenuation in
+    /// 0.375dB steps. The half sine wave is silent through the negative half.
+    fn wave(&self, phase: u32, attenuation: u32, half_sine: bool) -> i32 {
+        let negative = phase & 0x200 != 0;
+        if negative && half_sine {
+            return 0;
+        }
+
+        let quarter = if phase & 0x100 != 0 {
+            !phase & 0xFF
+        } else {
+            phase & 0xFF
+        };
+        let level = self.log_sin[quarter as usize] as u32 + (attenuation << 4);
+        let shift = level >> 8;
+        if shift > 12 {
+            return 0;
+        }
+
+        let output = ((self.exp[(level & 0xFF) as usize] as i32) << 1) >> shift;
+        if negative {
+            -output
+        } else {
+            output
+        }
+    }
+}
+
+/// A two-operator FM channel
+struct FmChannel {
+    f_number: u16, // 9 bits
+    block: u8,     // Octave
+    key: bool,
+    sustain: bool, // Slows the release
+    instrument: u8,
+    volume: u8, // Attenuation, in 3dB steps
+    modulator: Slot,
+    carrier: Slot,
+    output: i32,
+}
+
+impl FmChannel {
+    fn new() -> Self {
+        FmChannel {
+            f_number: 0,
+            block: 0,
+            key: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Slot::new(),
+            carrier: Slot::new(),
+            output: 0,
+        }
+    }
+
+    fn set_key(&mut self, key: bool) {
+        if key && !self.key {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key && self.key {
+            self.modulator.state = EnvelopeState::Release;
+            self.carrier.state = EnvelopeState::Release;
+        }
+        self.key = key;
+    }
+
+    /// The block and top bit of the F-number, which faster envelopes and key
+    /// scaling go by
+    fn key_code(&self) -> u8 {
+        (self.block << 1) | (self.f_number >> 8) as u8
+    }
+
+    fn envelope_rate(&self, operator: &Operator, state: EnvelopeState) -> u8 {
+        let rate = match state {
+            EnvelopeState::Damp => DAMP_RATE,
+            EnvelopeState::Attack => operator.attack,
+            EnvelopeState::Decay => operator.decay,
+            EnvelopeState::Sustain if operator.sustained => 0,
+            EnvelopeState::Sustain => operator.release,
+            EnvelopeState::Release if self.sustain => SUSTAIN_RELEASE_RATE,
+            EnvelopeState::Release if operator.sustained => operator.release,
+            EnvelopeState::Release => PERCUSSIVE_RELEASE_RATE,
+        };
+        if rate == 0 {
+            return 0;
+        }
+
+        let key_scaling = match operator.key_scale_rate {
+            true => self.key_code(),
+            false => self.key_code() >> 2,
+        };
+        (rate * 4 + key_scaling).min(63)
+    }
+
+    /// Phase increment per sample, for an operator's multiple
+    fn phase_increment(&self, operator: &Operator, vibrato_step: usize) -> u32 {
+        let mut f_number = self.f_number as i32 * 2;
+        if operator.vibrato {
+            f_number += VIBRATO[(self.f_number >> 6) as usize][vibrato_step] as i32;
+        }
+
+        ((f_number as u32 * MULTIPLIERS[operator.multiple as usize]) << self.block) >> 2
+    }
+
+    fn clock_envelopes(&mut self, patch: &Patch, counter: u32) {
+        let rate = self.envelope_rate(&patch.modulator, self.modulator.state);
+        self.modulator
+            .clock_envelope(&patch.modulator, rate, counter);
+        let rate = self.envelope_rate(&patch.carrier, self.carrier.state);
+        self.carrier.clock_envelope(&patch.carrier, rate, counter);
+    }
+
+    /// Runs both operators for a sample: the modulator, fed back into itself,
+    /// then the carrier with its phase bent by the modulator
+    fn synthesize(
+        &mut self,
+        tables: &Tables,
+        patch: &Patch,
+        vibrato_step: usize,
+        tremolo: u32,
+    ) -> i32 {
+        let (modulator, carrier) = (&patch.modulator, &patch.carrier);
+
+        let feedback = match patch.feedback {
+            0 => 0,
+            feedback => (self.modulator.outputs[0] + self.modulator.outputs[1]) >> (9 - feedback),
+        };
+        let level = patch.total_level as u32 * 2;
+        let attenuation = self.attenuation(self.modulator.envelope, modulator, level, tremolo);
+        let increment = self.phase_increment(modulator, vibrato_step);
+        let modulation = self.modulator.output(
+            tables,
+            increment,
+            feedback,
+            attenuation,
+            modulator.half_sine,
+        );
+
+        let level = self.volume as u32 * 8;
+        let attenuation = self.attenuation(self.carrier.envelope, carrier, level, tremolo);
+        let increment = self.phase_increment(carrier, vibrato_step);
+        self.carrier.output(
+            tables,
+            increment,
+            modulation,
+            attenuation,
+            carrier.half_sine,
+        )
+    }
+
+    /// An operator's total attenuation, in 0.375dB steps: its envelope, its
+    /// level, key scaling and tremolo
+    fn attenuation(&self, envelope: u8, operator: &Operator, level: u32, tremolo: u32) -> u32 {
+        let mut attenuation = envelope as u32 + level + self.key_scale_level(operator);
+        if operator.tremolo {
+            attenuation += tremolo;
+        }
+        attenuation.min(MAX_ATTENUATION as u32)
+    }
+
+    /// Key scaling's attenuation, in 0.375dB steps
+    fn key_scale_level(&self, operator: &Operator) -> u32 {
+        if operator.key_scale_level == 0 {
+            return 0;
+        }
+
+        let level =
+            KEY_SCALE_LEVELS[(self.f_number >> 5) as usize] * 2 - (8 - self.block as i32) * 16;
+        level.max(0) as u32 >> (3 - operator.key_scale_level)
+    }
+}
+
+/// The FM synthesizer in the VRC7, a cut-down YM2413 (OPLL) with six
+/// channels, no rhythm section and its own set of instruments. Each channel
+/// plays one of fifteen built-in instruments or the one custom instrument.
+///
+/// The chip makes a sample for each channel every 72 clocks of its 3.58MHz
+/// crystal, or about 49.7kHz, and `step` moves it on by one.
+pub struct Opll {
+    custom: [u8; 8],
+    channels: [FmChannel; FM_CHANNELS],
+    counter: u32, // Samples so far, which the envelopes and LFOs run from
+    tables: Tables,
+}
+
+impl Opll {
+    pub fn new() -> Self {
+        Opll {
+            custom: [0; 8],
+            channels: [
+                FmChannel::new(),
+                FmChannel::new(),
+                FmChannel::new(),
+                FmChannel::new(),
+                FmChannel::new(),
+                FmChannel::new(),
+            ],
+            counter: 0,
+            tables: Tables::new(),
+        }
+    }
+
+    pub fn write(&mut self, register: u8, data: u8) {
+        let index = (register & 0x0F) as usize;
+        if register >= 0x10 && index >= FM_CHANNELS {
+            return;
+        }
+
+        match register & 0xF0 {
+            0x00 if index < 8 => self.custom[index] = data,
+            0x10 => {
+                let channel = &mut self.channels[index];
+                channel.f_number = (channel.f_number & 0x100) | data as u16;
+            }
+            0x20 => {
+                let channel = &mut self.channels[index];
+                channel.f_number = (channel.f_number & 0xFF) | ((data as u16 & 0x01) << 8);
+                channel.block = (data >> 1) & 0x07;
+                channel.sustain = data & 0x20 != 0;
+                channel.set_key(data & 0x10 != 0);
+            }
+            0x30 => {
+                let channel = &mut self.channels[index];
+                channel.instrument = data >> 4;
+                channel.volume = data & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    /// Makes the next sample for every channel
+    pub fn step(&mut self) {
+        let vibrato_step = (self.counter >> 10) as usize & 7;
+        let tremolo = self.tremolo();
+        let counter = self.counter;
+
+        for i in 0..FM_CHANNELS {
+            let patch = self.patch(self.channels[i].instrument);
+            let channel = &mut self.channels[i];
+            channel.clock_envelopes(&patch, counter);
+            channel.output = channel.synthesize(&self.tables, &patch, vibrato_step, tremolo);
+        }
+
+        self.counter = self.counter.wrapping_add(1);
+    }
+
+    /// A channel's latest sample, from about -4096 to 4096
+    pub fn output(&self, channel: usize) -> i32 {
+        self.channels[channel].output
+    }
+
+    /// The note a channel is playing in Hz, given the chip's sample rate, or
+    /// None when it's keyed off
+    pub fn frequency(&self, channel: usize, sample_rate: f64) -> Option<f64> {
+        let channel = &self.channels[channel];
+        if !channel.key || channel.f_number == 0 {
+            return None;
+        }
+
+        let carrier = self.patch(channel.instrument).carrier;
+        let multiple = MULTIPLIERS[carrier.multiple as usize] as f64 / 2.0;
+        let base =
+            channel.f_number as f64 * sample_rate * (1 << channel.block) as f64 / (1 << 19) as f64;
+        Some(base * multiple)
+    }
+
+    fn patch(&self, instrument: u8) -> Patch {
+        match instrument {
+            0 => Patch::from_bytes(&self.custom),
+            _ => Patch::from_bytes(&PATCHES[instrument as usize - 1]),
+        }
+    }
+
+    /// The tremolo's attenuation this sample, in 0.375dB steps
+    fn tremolo(&self) -> u32 {
+        let step = (self.counter >> 6) % TREMOLO_STEPS;
+        let rise = step.min(TREMOLO_STEPS - 1 - step);
+        rise * TREMOLO_DEPTH / (TREMOLO_STEPS / 2 - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wave_tables_make_a_sine() {
+        let tables = Tables::new();
+
+        for phase in (0..1024).step_by(16) {
+            let expected = (phase as f64 + 0.5) * PI / 512.0;
+            let output = tables.wave(phase, 0, false) as f64 / FULL_SCALE as f64;
+            assert!((output - expected.sin()).abs() < 0.01, "{}", phase);
+        }
+        // 6dB down is half as loud, and the half sine has no negative half
+        assert_eq!(tables.wave(256, 16, false), tables.wave(256, 0, false) / 2);
+        assert_eq!(tables.wave(768, 0, true), 0);
+    }
+
+    #[test]
+    fn lfos_sweep_their_full_range() {
+        let mut opll = Opll::new();
+        let tremolo: Vec<u32> = (0..TREMOLO_STEPS * 64)
+            .map(|counter| {
+                opll.counter = counter;
+                opll.tremolo()
+            })
+            .collect();
+        assert_eq!(tremolo.iter().min(), Some(&0));
+        assert_eq!(tremolo.iter().max(), Some(&TREMOLO_DEPTH));
+
+        let patch = Patch::from_bytes(&PATCHES[13]); // Synth bass, with vibrato
+        let mut channel = FmChannel::new();
+        channel.f_number = 0x1FF;
+        let increments: Vec<u32> = (0..8)
+            .map(|step| channel.phase_increment(&patch.modulator, step))
+            .collect();
+        assert!(increments[2] > increments[0] && increments[6] < increments[0]);
+    }
+
+    #[test]
+    fn envelopes_double_in_speed_every_four_rates() {
+        let steps = |rate| {
+            (0..1 << 14)
+                .map(|counter| envelope_step(rate, counter) as u32)
+                .sum::<u32>()
+        };
+
+        assert_eq!(steps(0), 0);
+        assert_eq!(steps(20) * 2, steps(24));
+        assert!(steps(21) > steps(20) && steps(23) < steps(24));
+    }
+}
@@ -0,0 +1,247 @@
+use super::{envelope::Envelope, length_counter::LengthCounter};
+
+static DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+/// Which of the two pulse channels, as their sweep units negate differently
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PulseChannel {
+    One, // Negates with one's complement, so a step further down
+    Two, // Negates with two's complement
+}
+
+/// Periodically bends a pulse channel's period up or down
+struct Sweep {
+    channel: PulseChannel,
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    divider: u8,
+    reload: bool,
+}
+
+impl Sweep {
+    fn new(channel: PulseChannel) -> Self {
+        Sweep {
+            channel,
+            enabled: false,
+            period: 0,
+            negate: false,
+            shift: 0,
+            divider: 0,
+            reload: false,
+        }
+    }
+
+    /// Takes EPPP NSSS
+    fn write(&mut self, data: u8) {
+        self.enabled = data & 0x80 != 0;
+        self.period = (data >> 4) & 0x07;
+        self.negate = data & 0x08 != 0;
+        self.shift = data & 0x07;
+        self.reload = true;
+    }
+
+    fn target(&self, period: u16) -> u16 {
+        let change = period >> self.shift;
+        match (self.negate, self.channel) {
+            (false, _) => period + change,
+            (true, PulseChannel::One) => period.saturating_sub(change + 1),
+            (true, PulseChannel::Two) => period.saturating_sub(change),
+        }
+    }
+
+    /// Periods too short to hear, or that would sweep out of range, mute the
+    /// channel even when the sweep is disabled
+    fn mutes(&self, period: u16) -> bool {
+        period < 8 || self.target(period) > 0x7FF
+    }
+
+    /// Clocked every half frame
+    fn clock(&mut self, period: &mut u16) {
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.mutes(*period) {
+            *period = self.target(*period);
+        }
+
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+    }
+}
+
+/// One of the 2A03's square wave channels, at $4000-$4003 or $4004-$4007
+pub struct Pulse {
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    envelope: Envelope,
+    sweep: Sweep,
+    pub length: LengthCounter,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Self {
+        Pulse {
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            envelope: Envelope::new(),
+            sweep: Sweep::new(channel),
+            length: LengthCounter::new(),
+        }
+    }
+
+    /// Writes one of the channel's four registers, by its offset
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register & 0x03 {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => self.sweep.write(data),
+            2 => self.period = (self.period & 0x700) | data as u16,
+            _ => {
+                self.period = (self.period & 0xFF) | ((data as u16 & 0x07) << 8);
+                self.length.load(data);
+                self.envelope.restart();
+                self.step = 0;
+            }
+        }
+    }
+
+    /// Clocked every APU cycle, which is every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+        self.sweep.clock(&mut self.period);
+    }
+
+    /// Current output level, from 0 to 15
+    pub fn output(&self) -> u8 {
+        let high = DUTY_CYCLES[self.duty as usize][self.step as usize] != 0;
+        if !high || !self.length.active() || self.sweep.mutes(self.period) {
+            return 0;
+        }
+
+        self.envelope.output()
+    }
+
+    /// The note being played in Hz, given the CPU clock rate, or None if the
+    /// channel is silent
+    pub fn frequency(&self, clock_rate: f64) -> Option<f64> {
+        if !self.length.active() || self.sweep.mutes(self.period) || self.envelope.output() == 0 {
+            return None;
+        }
+
+        Some(clock_rate / (16.0 * (self.period as f64 + 1.0)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn playing(channel: PulseChannel, period: u16) -> Pulse {
+        let mut pulse = Pulse::new(channel);
+        pulse.length.set_enabled(true);
+        pulse.write(0, 0x9F); // 50% duty, constant volume 15
+        pulse.write(2, period as u8);
+        pulse.write(3, 0x08 | (period >> 8) as u8);
+        pulse
+    }
+
+    /// Output over one full cycle of the waveform
+    fn waveform(pulse: &mut Pulse) -> Vec<u8> {
+        (0..8)
+            .map(|_| {
+                let output = pulse.output();
+                for _ in 0..=pulse.period {
+                    pulse.clock_timer();
+                }
+                output
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_the_frequency_while_audible() {
+        let mut pulse = playing(PulseChannel::One, 0xFD);
+        let frequency = pulse.frequency(1_789_773.0).unwrap();
+        assert!((frequency - 440.0).abs() < 1.0);
+
+        pulse.write(0, 0x90); // Volume 0
+        assert_eq!(pulse.frequency(1_789_773.0), None);
+    }
+
+    #[test]
+    fn plays_the_duty_cycle() {
+        let mut pulse = playing(PulseChannel::One, 0x100);
+        assert_eq!(waveform(&mut pulse), [0, 15, 15, 15, 15, 0, 0, 0]);
+
+        pulse.write(0, 0x3F);
+        assert_eq!(waveform(&mut pulse), [0, 15, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn negates_differently_per_channel() {
+        let mut one = playing(PulseChannel::One, 0x100);
+        let mut two = playing(PulseChannel::Two, 0x100);
+        for pulse in [&mut one, &mut two].iter_mut() {
+            pulse.write(1, 0x89); // Enabled, period 0, negate, shift 1
+            pulse.clock_half_frame();
+        }
+
+        assert_eq!(one.period, 0x7F);
+        assert_eq!(two.period, 0x80);
+    }
+
+    #[test]
+    fn mutes_out_of_range_periods() {
+        let mut low = playing(PulseChannel::One, 7);
+        assert!(waveform(&mut low).iter().all(|&level| level == 0));
+
+        // The target overflowing mutes even without the sweep enabled
+        let mut high = playing(PulseChannel::Two, 0x600);
+        high.write(1, 0x01);
+        assert!(waveform(&mut high).iter().all(|&level| level == 0));
+        high.clock_half_frame();
+        assert_eq!(high.period, 0x600);
+
+        high.write(1, 0x02);
+        assert!(waveform(&mut high).contains(&15));
+    }
+
+    #[test]
+    fn stops_when_the_length_runs_out() {
+        let mut pulse = playing(PulseChannel::One, 0x100);
+        pulse.write(3, 0x19); // Length 2, keeping the period
+        pulse.clock_half_frame();
+        assert!(waveform(&mut pulse).contains(&15));
+
+        pulse.clock_half_frame();
+        assert!(!waveform(&mut pulse).contains(&15));
+    }
+}
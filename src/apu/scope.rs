@@ -0,0 +1,71 @@
+/// Points kept for each channel
+pub const SCOPE_LENGTH: usize = 2048;
+// CPU cycles between points, so the history covers about 37ms
+const SCOPE_INTERVAL: u32 = 32;
+
+/// Recent output of each channel, for drawing their waveforms. Levels are
+/// scaled from 0.0 to 1.0 of the channel's range.
+pub struct Scope {
+    traces: Vec<Vec<f32>>,
+    position: usize, // Where the next point goes; the oldest point
+    counter: u32,
+}
+
+impl Scope {
+    pub fn new(channels: usize) -> Self {
+        Scope {
+            traces: vec![vec![0.0; SCOPE_LENGTH]; channels],
+            position: 0,
+            counter: 0,
+        }
+    }
+
+    /// Called every CPU cycle. Returns true when it's time to `record` the
+    /// next point.
+    pub fn tick(&mut self) -> bool {
+        self.counter += 1;
+        if self.counter < SCOPE_INTERVAL {
+            return false;
+        }
+        self.counter = 0;
+        true
+    }
+
+    /// Adds a point with each channel's level
+    pub fn record(&mut self, levels: &[f32]) {
+        for (trace, &level) in self.traces.iter_mut().zip(levels.iter()) {
+            trace[self.position] = level;
+        }
+        self.position = (self.position + 1) % SCOPE_LENGTH;
+    }
+
+    /// A channel's points, oldest first
+    pub fn trace(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        let trace = &self.traces[channel];
+        trace[self.position..]
+            .iter()
+            .chain(trace[..self.position].iter())
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_points_in_order() {
+        let mut scope = Scope::new(2);
+        for cycle in 0..(SCOPE_LENGTH as u32 + 10) * SCOPE_INTERVAL {
+            if scope.tick() {
+                scope.record(&[0.0, (cycle / SCOPE_INTERVAL) as f32]);
+            }
+        }
+
+        let trace: Vec<f32> = scope.trace(1).collect();
+        assert_eq!(trace.len(), SCOPE_LENGTH);
+        assert_eq!(trace[0], 10.0);
+        assert_eq!(trace[SCOPE_LENGTH - 1], (SCOPE_LENGTH + 9) as f32);
+        assert!(scope.trace(0).all(|point| point == 0.0));
+    }
+}
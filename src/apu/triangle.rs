@@ -0,0 +1,166 @@
+use super::length_counter::LengthCounter;
+
+#[rustfmt::skip]
+static SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// The triangle wave channel at $4008-$400B. It has no volume control, only
+/// a linear counter on top of the length counter for finer note lengths.
+pub struct Triangle {
+    step: u8,
+    period: u16,
+    timer: u16,
+    control: bool, // Also halts the length counter
+    linear_reload: u8,
+    linear_counter: u8,
+    reload: bool,
+    pub length: LengthCounter,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            step: 0,
+            period: 0,
+            timer: 0,
+            control: false,
+            linear_reload: 0,
+            linear_counter: 0,
+            reload: false,
+            length: LengthCounter::new(),
+        }
+    }
+
+    /// Writes one of the channel's four registers, by its offset
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register & 0x03 {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload = data & 0x7F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x700) | data as u16,
+            _ => {
+                self.period = (self.period & 0xFF) | ((data as u16 & 0x07) << 8);
+                self.length.load(data);
+                self.reload = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle, unlike the other channels' timers
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.linear_counter > 0 && self.length.active() {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.reload {
+            self.linear_counter = self.linear_reload;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Current output level, from 0 to 15. Stopping the counters holds the
+    /// level where it was rather than silencing the channel.
+    pub fn output(&self) -> u8 {
+        // The hardware plays periods this short at ultrasonic frequencies,
+        // which average out to the middle of the sequence
+        if self.period < 2 && self.linear_counter > 0 && self.length.active() {
+            return 7;
+        }
+
+        SEQUENCE[self.step as usize]
+    }
+
+    /// The note being played in Hz, given the CPU clock rate, or None if the
+    /// sequence is stopped or too fast to hear
+    pub fn frequency(&self, clock_rate: f64) -> Option<f64> {
+        if self.period < 2 || self.linear_counter == 0 || !self.length.active() {
+            return None;
+        }
+
+        Some(clock_rate / (32.0 * (self.period as f64 + 1.0)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn playing(control: u8) -> Triangle {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write(0, control);
+        triangle.write(2, 0x10);
+        triangle.write(3, 0x08);
+        triangle.clock_quarter_frame();
+        triangle
+    }
+
+    fn steps(triangle: &mut Triangle, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                for _ in 0..=triangle.period {
+                    triangle.clock_timer();
+                }
+                triangle.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plays_the_sequence() {
+        let mut triangle = playing(0x7F);
+        let levels = steps(&mut triangle, 32);
+
+        assert_eq!(&levels[..3], [14, 13, 12]);
+        assert_eq!(&levels[14..18], [0, 0, 1, 2]);
+        assert_eq!(levels[31], 15);
+    }
+
+    #[test]
+    fn linear_counter_stops_the_sequence() {
+        let mut triangle = playing(0x02);
+        triangle.clock_quarter_frame();
+        assert_eq!(steps(&mut triangle, 4), [14, 13, 12, 11]);
+
+        // Stopped, the level holds rather than dropping to zero
+        triangle.clock_quarter_frame();
+        assert_eq!(steps(&mut triangle, 2), [11, 11]);
+
+        // With the control flag set the counter keeps reloading
+        let mut held = playing(0x82);
+        for _ in 0..10 {
+            held.clock_quarter_frame();
+        }
+        assert_eq!(steps(&mut held, 2), [14, 13]);
+    }
+
+    #[test]
+    fn ultrasonic_periods_output_the_midpoint() {
+        let mut triangle = playing(0x7F);
+        triangle.write(2, 0x01);
+        triangle.write(3, 0x08);
+
+        assert!(steps(&mut triangle, 8).iter().all(|&level| level == 7));
+    }
+}
@@ -0,0 +1,299 @@
+use super::{Channel, ExpansionAudio};
+
+// One step of a VRC6 channel in the mix, so that a pulse at full volume is as
+// loud as a 2A03 pulse at full volume
+const STEP: f32 = 0.1488 / 15.0;
+
+static CHANNELS: [Channel; 3] = [Channel::Vrc6Pulse1, Channel::Vrc6Pulse2, Channel::Vrc6Saw];
+
+/// A VRC6 pulse, which unlike the 2A03's has eight duty cycles and no
+/// envelope or length counter
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,        // Sixteenths high, less one
+    digitized: bool, // Outputs the volume directly
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8, // Counts down from 15
+}
+
+impl Vrc6Pulse {
+    fn new() -> Self {
+        Vrc6Pulse {
+            volume: 0,
+            duty: 0,
+            digitized: false,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 15,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register & 0x03 {
+            0 => {
+                self.digitized = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0x07;
+                self.volume = data & 0x0F;
+            }
+            1 => self.period = (self.period & 0xF00) | data as u16,
+            _ => {
+                self.period = (self.period & 0xFF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn frequency(&self, shift: u8, clock_rate: f64) -> Option<f64> {
+        if !self.enabled || self.digitized || self.volume == 0 {
+            return None;
+        }
+
+        Some(clock_rate / (16.0 * ((self.period >> shift) as f64 + 1.0)))
+    }
+}
+
+/// The VRC6's sawtooth: an accumulator that adds its rate every other step
+/// and resets after seven additions
+struct Sawtooth {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8, // 0-13
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn new() -> Self {
+        Sawtooth {
+            rate: 0,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register & 0x03 {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0xF00) | data as u16,
+            _ => {
+                self.period = (self.period & 0xFF) | ((data as u16 & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    /// From 0 to 31, the top five bits of the accumulator
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+
+    fn frequency(&self, shift: u8, clock_rate: f64) -> Option<f64> {
+        if !self.enabled || self.rate == 0 {
+            return None;
+        }
+
+        Some(clock_rate / (14.0 * ((self.period >> shift) as f64 + 1.0)))
+    }
+}
+
+/// Konami's VRC6 sound: two pulses and a sawtooth, at $9000-$B002
+pub struct Vrc6Audio {
+    pulses: [Vrc6Pulse; 2],
+    sawtooth: Sawtooth,
+    halt: bool,
+    shift: u8, // Periods can be cut short, for a faster test mode
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Vrc6Audio {
+            pulses: [Vrc6Pulse::new(), Vrc6Pulse::new()],
+            sawtooth: Sawtooth::new(),
+            halt: false,
+            shift: 0,
+        }
+    }
+
+    /// Writes a sound register, by its address as VRC6a wires it up
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address & 0xF003 {
+            0x9003 => {
+                self.halt = data & 0x01 != 0;
+                self.shift = match data & 0x06 {
+                    0 => 0,
+                    0x02 => 4,
+                    _ => 8,
+                };
+            }
+            0x9000..=0x9002 => self.pulses[0].write(address, data),
+            0xA000..=0xA002 => self.pulses[1].write(address, data),
+            0xB000..=0xB002 => self.sawtooth.write(address, data),
+            _ => {}
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+
+        for pulse in self.pulses.iter_mut() {
+            pulse.clock(self.shift);
+        }
+        self.sawtooth.clock(self.shift);
+    }
+}
+
+impl ExpansionAudio for Vrc6Audio {
+    fn channels(&self) -> &[Channel] {
+        &CHANNELS
+    }
+
+    fn level(&self, index: usize) -> f32 {
+        match index {
+            0 | 1 => self.pulses[index].output() as f32 / 15.0,
+            _ => self.sawtooth.output() as f32 / 31.0,
+        }
+    }
+
+    fn weight(&self, index: usize) -> f32 {
+        match index {
+            0 | 1 => 15.0 * STEP,
+            _ => 31.0 * STEP,
+        }
+    }
+
+    fn frequency(&self, index: usize, clock_rate: f64) -> Option<f64> {
+        match index {
+            0 | 1 => self.pulses[index].frequency(self.shift, clock_rate),
+            _ => self.sawtooth.frequency(self.shift, clock_rate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Output over one full cycle of a channel
+    fn waveform(vrc6: &mut Vrc6Audio, index: usize, steps: usize, period: usize) -> Vec<f32> {
+        (0..steps)
+            .map(|_| {
+                let level = vrc6.level(index);
+                for _ in 0..=period {
+                    vrc6.clock();
+                }
+                level
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pulses_have_eight_duty_cycles() {
+        let mut vrc6 = Vrc6Audio::new();
+        vrc6.write(0xA000, 0x3F); // 4/16 duty, volume 15
+        vrc6.write(0xA001, 0x10);
+        vrc6.write(0xA002, 0x80);
+
+        // High for four of the sixteen steps
+        let high = waveform(&mut vrc6, 1, 16, 0x10)
+            .iter()
+            .filter(|&&level| level == 1.0)
+            .count();
+        assert_eq!(high, 4);
+        assert_eq!(vrc6.level(0), 0.0);
+
+        // Digitized mode holds the volume
+        vrc6.write(0xA000, 0x88);
+        assert!(waveform(&mut vrc6, 1, 16, 0x10)
+            .iter()
+            .all(|&level| level == 8.0 / 15.0));
+    }
+
+    #[test]
+    fn sawtooth_ramps_and_resets() {
+        let mut vrc6 = Vrc6Audio::new();
+        vrc6.write(0xB000, 0x20);
+        vrc6.write(0xB001, 0x00);
+        vrc6.write(0xB002, 0x80);
+
+        let levels: Vec<f32> = waveform(&mut vrc6, 2, 14, 0)
+            .iter()
+            .map(|&level| (level * 31.0).round())
+            .collect();
+        assert_eq!(
+            levels,
+            [0.0, 0.0, 4.0, 4.0, 8.0, 8.0, 12.0, 12.0, 16.0, 16.0, 20.0, 20.0, 24.0, 24.0]
+        );
+        assert_eq!(vrc6.level(2), 0.0);
+
+        let frequency = vrc6.frequency(2, 1_789_773.0).unwrap();
+        assert!((frequency - 1_789_773.0 / 14.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn halting_stops_the_channels() {
+        let mut vrc6 = Vrc6Audio::new();
+        vrc6.write(0x9000, 0x0F);
+        vrc6.write(0x9002, 0x80);
+        vrc6.write(0x9003, 0x01);
+
+        assert_eq!(waveform(&mut vrc6, 0, 16, 0), [0.0; 16]);
+    }
+}
@@ -0,0 +1,207 @@
+use super::opll::{Opll, FM_CHANNELS, FULL_SCALE};
+use super::{Channel, ExpansionAudio};
+
+// The FM chip makes a sample every 72 clocks of a 3.58MHz crystal, which is
+// twice the NTSC CPU's rate
+const CYCLES_PER_SAMPLE: u8 = 36;
+
+// How loud a channel swinging from one end to the other is in the mix, about
+// twice a 2A03 pulse at full volume, as the chip's amplifier is hot
+const WEIGHT: f32 = 0.1488 * 2.0;
+
+static CHANNELS: [Channel; FM_CHANNELS] = [
+    Channel::Vrc7Fm1,
+    Channel::Vrc7Fm2,
+    Channel::Vrc7Fm3,
+    Channel::Vrc7Fm4,
+    Channel::Vrc7Fm5,
+    Channel::Vrc7Fm6,
+];
+
+/// Konami's VRC7 sound: six channels of FM synthesis, programmed by writing
+/// a register number to $9010 and then its value to $9030
+pub struct Vrc7Audio {
+    opll: Opll,
+    register: u8,
+    cycles: u8, // Until the next sample
+}
+
+impl Vrc7Audio {
+    pub fn new() -> Self {
+        Vrc7Audio {
+            opll: Opll::new(),
+            register: 0,
+            cycles: CYCLES_PER_SAMPLE,
+        }
+    }
+
+    /// Writes the register select or data port, by its address as VRC7a
+    /// wires them up
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address & 0xF030 {
+            0x9010 => self.register = data,
+            0x9030 => self.opll.write(self.register, data),
+            _ => {}
+        }
+    }
+
+    /// Silences the chip and clears its registers, which games do by holding
+    /// it in reset
+    pub fn reset(&mut self) {
+        *self = Vrc7Audio::new();
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock(&mut self) {
+        self.cycles -= 1;
+        if self.cycles == 0 {
+            self.cycles = CYCLES_PER_SAMPLE;
+            self.opll.step();
+        }
+    }
+}
+
+impl ExpansionAudio for Vrc7Audio {
+    fn channels(&self) -> &[Channel] {
+        &CHANNELS
+    }
+
+    /// FM channels swing either side of a midpoint, which is where they sit
+    /// when silent
+    fn level(&self, index: usize) -> f32 {
+        let output = self.opll.output(index) as f32 / FULL_SCALE as f32;
+        ((output + 1.0) / 2.0).clamp(0.0, 1.0)
+    }
+
+    fn weight(&self, _index: usize) -> f32 {
+        WEIGHT
+    }
+
+    fn frequency(&self, index: usize, clock_rate: f64) -> Option<f64> {
+        let sample_rate = clock_rate / CYCLES_PER_SAMPLE as f64;
+        self.opll.frequency(index, sample_rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLOCK_RATE: f64 = 1_789_773.0;
+
+    fn write(vrc7: &mut Vrc7Audio, register: u8, data: u8) {
+        vrc7.write(0x9010, register);
+        vrc7.write(0x9030, data);
+    }
+
+    /// Plays for a number of chip samples, returning a channel's levels
+    fn play(vrc7: &mut Vrc7Audio, index: usize, samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|_| {
+                for _ in 0..CYCLES_PER_SAMPLE {
+                    vrc7.clock();
+                }
+                vrc7.level(index)
+            })
+            .collect()
+    }
+
+    fn peak_to_peak(levels: &[f32]) -> f32 {
+        let high = levels.iter().copied().fold(f32::MIN, f32::max);
+        let low = levels.iter().copied().fold(f32::MAX, f32::min);
+        high - low
+    }
+
+    /// Keys a channel on with an instrument, at A4
+    fn key_on(vrc7: &mut Vrc7Audio, channel: u8, instrument: u8) {
+        write(vrc7, 0x30 + channel, instrument << 4);
+        write(vrc7, 0x10 + channel, 0x22);
+        write(vrc7, 0x20 + channel, 0x19); // Key on, block 4, F-number $122
+    }
+
+    #[test]
+    fn is_silent_until_keyed_on() {
+        let mut vrc7 = Vrc7Audio::new();
+        assert!(play(&mut vrc7, 0, 1000).iter().all(|&level| level == 0.5));
+        assert_eq!(vrc7.frequency(0, CLOCK_RATE), None);
+    }
+
+    #[test]
+    fn plays_built_in_instruments() {
+        let mut vrc7 = Vrc7Audio::new();
+        key_on(&mut vrc7, 2, 4); // Flute
+
+        play(&mut vrc7, 2, 2000);
+        assert!(peak_to_peak(&play(&mut vrc7, 2, 1000)) > 0.5);
+        assert_eq!(play(&mut vrc7, 0, 1), [0.5]);
+
+        // F-number $122 in block 4 is 440Hz
+        let frequency = vrc7.frequency(2, CLOCK_RATE).unwrap();
+        assert!((frequency - 440.0).abs() < 2.0, "{}", frequency);
+    }
+
+    #[test]
+    fn custom_instrument_follows_its_envelope() {
+        let mut vrc7 = Vrc7Audio::new();
+        // A plain sine carrier with no modulation, fast attack, slow decay
+        // to a sustain at -6dB, and a fast release
+        for (register, &data) in [0x20, 0x21, 0x3F, 0x00, 0xF0, 0xF4, 0x0F, 0x2F]
+            .iter()
+            .enumerate()
+        {
+            write(&mut vrc7, register as u8, data);
+        }
+        key_on(&mut vrc7, 0, 0);
+
+        let attack = peak_to_peak(&play(&mut vrc7, 0, 200));
+        play(&mut vrc7, 0, 20_000);
+        let sustain = peak_to_peak(&play(&mut vrc7, 0, 200));
+        assert!(attack > 0.9, "{}", attack);
+        assert!(
+            (sustain - attack / 2.0).abs() < 0.05,
+            "{} {}",
+            attack,
+            sustain
+        );
+
+        write(&mut vrc7, 0x20, 0x09); // Key off
+        play(&mut vrc7, 0, 2000);
+        assert!(peak_to_peak(&play(&mut vrc7, 0, 200)) < 0.01);
+    }
+
+    #[test]
+    fn plays_in_tune() {
+        let mut vrc7 = Vrc7Audio::new();
+        // A plain sine carrier that holds its level
+        for (register, &data) in [0x20, 0x21, 0x3F, 0x00, 0xF0, 0xF0, 0x00, 0x00]
+            .iter()
+            .enumerate()
+        {
+            write(&mut vrc7, register as u8, data);
+        }
+        key_on(&mut vrc7, 0, 0);
+        play(&mut vrc7, 0, 1000);
+
+        // A tenth of a second of A4 crosses the midpoint upwards 44 times
+        let levels = play(
+            &mut vrc7,
+            0,
+            (CLOCK_RATE / CYCLES_PER_SAMPLE as f64 / 10.0) as usize,
+        );
+        let crossings = levels
+            .windows(2)
+            .filter(|pair| pair[0] < 0.5 && pair[1] >= 0.5)
+            .count();
+        assert!((43..=45).contains(&crossings), "{}", crossings);
+    }
+
+    #[test]
+    fn reset_silences_the_chip() {
+        let mut vrc7 = Vrc7Audio::new();
+        key_on(&mut vrc7, 0, 3);
+        play(&mut vrc7, 0, 1000);
+
+        vrc7.reset();
+        assert_eq!(play(&mut vrc7, 0, 100), [0.5; 100]);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{fmt, str::FromStr, thread, time::Duration};
+
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    AudioSubsystem,
+};
+
+pub const DEFAULT_LATENCY_MS: u32 = 64;
+
+// Largest change to the resampling ratio rate control makes, either way. Small
+// enough that the pitch change can't be heard.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+/// What emulation speed is locked to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SyncMode {
+    // The display's vsync sets the speed, or the frame pacer when the display
+    // runs at a different rate, and the audio is resampled slightly faster or
+    // slower to keep up with it
+    Video,
+    // Emulation waits whenever more audio is queued than the latency
+    Audio,
+}
+
+impl FromStr for SyncMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "video" | "vsync" => Ok(SyncMode::Video),
+            "audio" => Ok(SyncMode::Audio),
+            _ => Err(format!("Unknown sync mode {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SyncMode::Video => "video",
+            SyncMode::Audio => "audio",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Dynamic rate control: steers the amount of queued audio towards a target
+/// by making slightly more samples per frame when the queue runs low, and
+/// fewer when it fills up. With video sync this absorbs the drift between the
+/// console's frame rate and the display's real refresh rate.
+pub struct RateControl {
+    target: usize, // Queued samples to aim for
+}
+
+impl RateControl {
+    pub fn new(target: usize) -> Self {
+        RateControl {
+            target: target.max(1),
+        }
+    }
+
+    /// Multiplier for the output sample rate, given the samples queued
+    pub fn ratio(&self, queued: usize) -> f64 {
+        let error = 1.0 - queued as f64 / self.target as f64;
+        1.0 + MAX_RATE_ADJUSTMENT * error.clamp(-1.0, 1.0)
+    }
+}
+
+/// Plays the APU's samples through an SDL audio queue
+pub struct AudioOutput {
+    queue: AudioQueue<f32>,
+    latency: usize, // In samples
+    rate_control: RateControl,
+}
+
+impl AudioOutput {
+    pub fn new(
+        audio: &AudioSubsystem,
+        sample_rate: u32,
+        latency_ms: u32,
+    ) -> Result<AudioOutput, String> {
+        let spec = AudioSpecDesired {
+            freq: Some(sample_rate as i32),
+            channels: Some(1),
+            samples: Some(512),
+        };
+        let queue = audio.open_queue::<f32, _>(None, &spec)?;
+
+        let latency = (sample_rate as usize * latency_ms as usize / 1000).max(1);
+        // Start half full, so there's room to drift either way
+        queue.queue(&vec![0.0; latency / 2]);
+        queue.resume();
+
+        Ok(AudioOutput {
+            queue,
+            latency,
+            rate_control: RateControl::new(latency / 2),
+        })
+    }
+
+    pub fn queue(&mut self, samples: &[f32]) {
+        self.queue.queue(samples);
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.size() as usize / std::mem::size_of::<f32>()
+    }
+
+    /// Resampling ratio that brings the queue back towards its target
+    pub fn rate_adjustment(&self) -> f64 {
+        self.rate_control.ratio(self.queued())
+    }
+
+    /// Waits until the queue has drained to the latency, for audio sync
+    pub fn throttle(&self) {
+        while self.queued() > self.latency {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn steers_towards_the_target() {
+        let control = RateControl::new(1000);
+
+        assert_eq!(control.ratio(1000), 1.0);
+        assert!(control.ratio(500) > 1.0);
+        assert!(control.ratio(1500) < 1.0);
+
+        // Never more than the limit, however far off
+        assert_eq!(control.ratio(0), 1.0 + MAX_RATE_ADJUSTMENT);
+        assert_eq!(control.ratio(10_000), 1.0 - MAX_RATE_ADJUSTMENT);
+    }
+
+    #[test]
+    fn parses_sync_modes() {
+        assert_eq!("audio".parse::<SyncMode>(), Ok(SyncMode::Audio));
+        assert_eq!(SyncMode::Video.to_string(), "video");
+        assert_eq!("vsync".parse::<SyncMode>(), Ok(SyncMode::Video));
+        assert!("frame".parse::<SyncMode>().is_err());
+    }
+}
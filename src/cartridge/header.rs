@@ -1,9 +1,10 @@
 use std::cmp;
-use std::io::{self, ErrorKind, SeekFrom};
+use std::io::{self, ErrorKind};
 
 use byteorder::{LittleEndian, ReadBytesExt};
 
 use crate::mapper::Mirroring;
+use crate::region::Region;
 
 // NES<EOF>
 const MAGIC_HEADER: u32 = 0x1A53454E;
@@ -11,18 +12,22 @@ const MAGIC_HEADER: u32 = 0x1A53454E;
 const FLAG6: usize = 0;
 const FLAG7: usize = 1;
 const FLAG8: usize = 2;
-const _FLAG9: usize = 3;
+const FLAG9: usize = 3;
 const _FLAG10: usize = 4;
+const FLAG11: usize = 5;
+const FLAG12: usize = 6;
 
 #[derive(Debug)]
 pub struct Header {
     pub prg_rom_pages: usize,
     pub prg_ram_pages: usize,
     pub chr_rom_pages: usize,
-    pub chr_ram_pages: usize,
+    pub chr_ram_size: usize,
     pub mapper: u8,
     pub has_trainer: bool,
     pub mirroring: Mirroring,
+    pub is_nes2: bool,
+    pub region: Region,
 }
 
 impl Header {
@@ -36,36 +41,123 @@ impl Header {
 
         let prg_rom_pages = stream.read_u8()? as usize;
         let chr_rom_pages = stream.read_u8()? as usize;
-        let chr_ram_pages = if chr_rom_pages == 0 { 1 } else { 0 };
 
-        let mut flags = [0; 5];
+        let mut flags = [0; 10];
         stream.read_exact(&mut flags)?;
 
-        let mirroring = if flags[FLAG6] & 1 == 0 {
+        // NES 2.0 is identified by bits 2-3 of flag 7 being 0b10
+        let is_nes2 = flags[FLAG7] & 0x0C == 0x08;
+
+        let mirroring = if flags[FLAG6] & 0b1000 != 0 {
+            Mirroring::FourScreen
+        } else if flags[FLAG6] & 1 == 0 {
             Mirroring::Horizontal
         } else {
             Mirroring::Vertical
         };
 
-        let prg_ram_pages = cmp::max(1, flags[FLAG8]) as usize;
-        let has_trainer = flags[0] & 0b100 > 0;
-        let mapper = (flags[FLAG7] >> 4) | (flags[FLAG8] & 0xF0);
+        // NES 2.0 stores the CHR-RAM size as a shift count (64 << n), while
+        // iNES boards without CHR-ROM are assumed to carry 8 KiB of CHR-RAM
+        let chr_ram_size = match (is_nes2, flags[FLAG11] & 0x0F) {
+            (true, 0) => 0,
+            (true, shift) => 64 << shift,
+            (false, _) if chr_rom_pages == 0 => 0x2000,
+            (false, _) => 0,
+        };
 
-        // Unused part of header
-        stream.seek(SeekFrom::Current(5))?;
+        // NES 2.0 carries the CPU/PPU timing in flag 12, with multi-region
+        // carts treated as NTSC. iNES only has a rarely set PAL bit in flag 9.
+        let region = match (is_nes2, flags[FLAG12] & 0b11, flags[FLAG9] & 1) {
+            (true, 1, _) => Region::Pal,
+            (true, 3, _) => Region::Dendy,
+            (false, _, 1) => Region::Pal,
+            _ => Region::Ntsc,
+        };
+
+        let prg_ram_pages = cmp::max(1, flags[FLAG8]) as usize;
+        let has_trainer = flags[FLAG6] & 0b100 > 0;
+        let mapper = (flags[FLAG6] >> 4) | (flags[FLAG7] & 0xF0);
 
         return Ok(Header {
             prg_rom_pages,
             prg_ram_pages,
             chr_rom_pages,
-            chr_ram_pages,
+            chr_ram_size,
             mapper,
             has_trainer,
             mirroring,
+            is_nes2,
+            region,
         });
     }
 
     pub fn prg_ram_size(&self) -> usize {
         self.prg_ram_pages * 0x2000
     }
+
+    pub fn chr_rom_size(&self) -> usize {
+        self.chr_rom_pages * 0x2000
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn header(flags: [u8; 10]) -> Header {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 2, 0];
+        data.extend_from_slice(&flags);
+
+        Header::new(&mut Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn ines_defaults_to_8k_chr_ram() {
+        let header = header([0; 10]);
+
+        assert!(!header.is_nes2);
+        assert_eq!(header.chr_ram_size, 0x2000);
+    }
+
+    #[test]
+    fn nes2_chr_ram_size() {
+        let header = header([0, 0x08, 0, 0, 0, 0x07, 0, 0, 0, 0]);
+
+        assert!(header.is_nes2);
+        assert_eq!(header.chr_ram_size, 0x2000);
+    }
+
+    #[test]
+    fn mapper_number_spans_flags_6_and_7() {
+        let header = header([0x81, 0x10, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert_eq!(header.mapper, 24);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn four_screen_mirroring() {
+        let header = header([0b1001, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert_eq!(header.mirroring, Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn nes2_timing_selects_region() {
+        assert_eq!(
+            header([0, 0x08, 0, 0, 0, 0, 1, 0, 0, 0]).region,
+            Region::Pal
+        );
+        assert_eq!(
+            header([0, 0x08, 0, 0, 0, 0, 2, 0, 0, 0]).region,
+            Region::Ntsc
+        );
+        assert_eq!(
+            header([0, 0x08, 0, 0, 0, 0, 3, 0, 0, 0]).region,
+            Region::Dendy
+        );
+        assert_eq!(header([0, 0, 0, 1, 0, 0, 0, 0, 0, 0]).region, Region::Pal);
+    }
 }
@@ -4,11 +4,17 @@ use std::{
 };
 
 use super::header::Header;
-use crate::mapper::{Mapper, MapperFactory};
+use crate::apu::ExpansionAudio;
+use crate::mapper::{Mapper, MapperFactory, Mirroring};
+use crate::render::frame::TileId;
 
 pub struct NESRom {
     pub header: Header,
+    pub crc32: u32, // Checksum of PRG-ROM and CHR-ROM, identifies the game
     data: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    nametable_ram: Vec<u8>, // Extra VRAM on four-screen boards
     mapper: Box<dyn Mapper>,
 }
 
@@ -21,49 +27,235 @@ impl NESRom {
             stream.seek(SeekFrom::Current(512))?;
         }
 
-        let length = 0x2000 + header.prg_rom_pages * 0x4000 + header.chr_rom_pages * 0x2000;
+        let length = 0x2000 + header.prg_rom_pages * 0x4000;
 
         let mapper = MapperFactory::create(&header);
         let mut data = vec![0; length];
 
         stream.read_exact(&mut data[0x2000..length])?;
 
+        let chr_is_ram = header.chr_rom_pages == 0;
+        let chr = if chr_is_ram {
+            vec![0; header.chr_ram_size]
+        } else {
+            let mut chr = vec![0; header.chr_rom_size()];
+            stream.read_exact(&mut chr)?;
+            chr
+        };
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&data[0x2000..]);
+        if !chr_is_ram {
+            hasher.update(&chr);
+        }
+
+        let nametable_ram = match header.mirroring {
+            Mirroring::FourScreen => vec![0; 0x1000],
+            _ => Vec::new(),
+        };
+
         Ok(NESRom {
             header,
+            crc32: hasher.finalize(),
             mapper,
             data,
+            chr,
+            chr_is_ram,
+            nametable_ram,
         })
     }
 
-    pub fn chr_rom(&self) -> Vec<u8> {
-        if self.header.chr_rom_pages > 0 {
-            let start = self.header.prg_rom_pages * 0x4000 + 0x2000;
-            let length = self.header.chr_rom_pages * 0x2000;
-
-            self.data[start..start + length].to_vec()
-        } else {
-            vec![0; self.header.chr_ram_pages * 0x2000]
-        }
-    }
-
     pub fn from_file(filename: &str) -> io::Result<NESRom> {
         let mut f = File::open(filename)?;
 
         NESRom::new(&mut f)
     }
 
+    /// Builds a cartridge from PRG-ROM and a mapper directly, for images
+    /// that aren't iNES files. It gets 8 KiB of PRG-RAM and the CHR-RAM the
+    /// header asks for.
+    pub fn with_mapper(header: Header, prg_rom: &[u8], mapper: Box<dyn Mapper>) -> NESRom {
+        let mut data = vec![0; 0x2000];
+        data.extend_from_slice(prg_rom);
+
+        NESRom {
+            crc32: crc32fast::hash(prg_rom),
+            chr: vec![0; header.chr_ram_size],
+            chr_is_ram: true,
+            nametable_ram: Vec::new(),
+            header,
+            mapper,
+            data,
+        }
+    }
+
+    /// Reads PRG memory, or 0 from open bus
     pub fn read(self: &Self, address: u16) -> u8 {
-        let internal_address = self.mapper.map(address);
+        match self.mapper.map(address) {
+            Some(offset) => self.data[offset],
+            None => 0,
+        }
+    }
+
+    pub fn write(self: &mut Self, address: u16, data: u8) {
+        if let Some(offset) = self.mapper.write(address, data) {
+            self.data[offset] = data;
+        }
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock(&mut self) {
+        self.mapper.clock();
+    }
+
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    pub fn audio(&self) -> Option<&dyn ExpansionAudio> {
+        self.mapper.audio()
+    }
+
+    /// Reads a byte from the PPU address space ($0000-$3EFF). Pattern table
+    /// reads go to CHR memory and nametable reads are routed by the mapper
+    /// into either the console's CIRAM or the cartridge's own VRAM.
+    pub fn ppu_read(&mut self, address: u16, ciram: &[u8]) -> u8 {
+        self.mapper.ppu_address(address);
+        self.ppu_peek(address, ciram)
+    }
+
+    /// Reads from the PPU address space without the mapper observing the access
+    pub fn ppu_peek(&self, address: u16, ciram: &[u8]) -> u8 {
+        match address & 0x3FFF {
+            0..=0x1FFF => self.chr_read(address),
+            _ => {
+                let offset = self.mirroring().nametable_offset(address);
+                match self.mirroring() {
+                    Mirroring::FourScreen => self.nametable_ram[offset],
+                    _ => ciram[offset],
+                }
+            }
+        }
+    }
+
+    /// Writes a byte to the PPU address space ($0000-$3EFF). Writes to
+    /// pattern tables only stick when the board carries CHR-RAM.
+    pub fn ppu_write(&mut self, address: u16, data: u8, ciram: &mut [u8]) {
+        self.mapper.ppu_address(address);
+
+        match address & 0x3FFF {
+            0..=0x1FFF => {
+                if self.chr_is_ram && !self.chr.is_empty() {
+                    let offset = self.mapper.map_chr(address) % self.chr.len();
+                    self.chr[offset] = data;
+                }
+            }
+            _ => {
+                let offset = self.mirroring().nametable_offset(address);
+                match self.mirroring() {
+                    Mirroring::FourScreen => self.nametable_ram[offset] = data,
+                    _ => ciram[offset] = data,
+                }
+            }
+        }
+    }
 
-        if internal_address < 0x2000 && self.header.chr_ram_pages == 0 {
-            0
+    /// Identifies the tile a pattern table address falls in, as banked in
+    /// right now
+    pub fn tile_id(&self, address: u16) -> TileId {
+        if self.chr.is_empty() {
+            return TileId::Rom(0);
+        }
+
+        let offset = (self.mapper.map_chr(address & !0x0F) % self.chr.len()) & !0x0F;
+        if self.chr_is_ram {
+            let mut data = [0; 16];
+            data.copy_from_slice(&self.chr[offset..offset + 16]);
+            TileId::Ram(data)
         } else {
-            self.data[internal_address as usize]
+            TileId::Rom((offset / 16) as u32)
         }
     }
 
-    pub fn write(self: &mut Self, address: u16, data: u8) {
-        let internal_address = self.mapper.map(address);
-        self.data[internal_address as usize] = data;
+    fn chr_read(&self, address: u16) -> u8 {
+        if self.chr.is_empty() {
+            return 0;
+        }
+
+        self.chr[self.mapper.map_chr(address) % self.chr.len()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn rom(chr_rom_pages: u8) -> NESRom {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, chr_rom_pages];
+        data.resize(16, 0);
+        data.resize(16 + 0x4000 + chr_rom_pages as usize * 0x2000, 0x55);
+
+        NESRom::new(&mut Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn chr_rom_ignores_writes() {
+        let mut rom = rom(1);
+        let mut ciram = [0; 0x800];
+
+        rom.ppu_write(0x0010, 0xAA, &mut ciram);
+
+        assert_eq!(rom.ppu_read(0x0010, &ciram), 0x55);
+    }
+
+    #[test]
+    fn chr_ram_accepts_writes() {
+        let mut rom = rom(0);
+        let mut ciram = [0; 0x800];
+
+        rom.ppu_write(0x1FFF, 0xAA, &mut ciram);
+
+        assert_eq!(rom.ppu_read(0x1FFF, &ciram), 0xAA);
+    }
+
+    #[test]
+    fn nametables_route_through_mirroring() {
+        let mut rom = rom(1);
+        let mut ciram = [0; 0x800];
+
+        // Horizontal mirroring: $2000 and $2400 share the same table
+        rom.ppu_write(0x2005, 0x12, &mut ciram);
+
+        assert_eq!(ciram[0x005], 0x12);
+        assert_eq!(rom.ppu_read(0x2405, &ciram), 0x12);
+        assert_eq!(rom.ppu_read(0x2805, &ciram), 0x00);
+    }
+
+    #[test]
+    fn tiles_are_identified_by_index_or_data() {
+        assert_eq!(rom(1).tile_id(0x1234), TileId::Rom(0x123));
+
+        let mut rom = rom(0);
+        let mut ciram = [0; 0x800];
+        rom.ppu_write(0x0025, 0xAA, &mut ciram);
+
+        let mut data = [0; 16];
+        data[5] = 0xAA;
+        assert_eq!(rom.tile_id(0x002F), TileId::Ram(data));
+    }
+
+    #[test]
+    fn checksum_covers_prg_and_chr_rom() {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&[0x55; 0x4000]);
+        assert_eq!(rom(0).crc32, hasher.finalize());
+
+        assert_ne!(rom(1).crc32, rom(0).crc32);
     }
 }
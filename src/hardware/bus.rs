@@ -1,48 +1,81 @@
-use crate::{apu::Apu, cartridge::rom::NESRom, ppu::Ppu};
+use crate::{apu::Apu, cartridge::rom::NESRom, ppu::Ppu, region::Region};
 
 use super::interrupt::Interrupt;
 use super::joypad::Joypad;
 
 const RAM_SIZE: usize = 0x800;
+
+/// What the CPU was doing on the cycle a DMC fetch interrupted
+#[derive(Copy, Clone, PartialEq)]
+enum Access {
+    Read(u16),
+    Write,
+}
+
 pub struct Bus {
     ram: [u8; RAM_SIZE],
-    cartridge: Box<NESRom>,
+    pub cartridge: Box<NESRom>,
     pub ppu: Ppu,
-    apu: Apu,
+    pub apu: Apu,
     pub cycles: u64,
     pub nmi: Interrupt,
     pub irq: Interrupt,
+    nmi_line: bool,
+    region: Region,
+    ppu_clock: u32,   // Fractional PPU dots owed to the PPU
+    last_write: bool, // Whether the CPU's last access was a write
 
     pub joypad1: Joypad,
 }
 
 impl Bus {
     pub fn new(rom: Box<NESRom>) -> Bus {
-        let mirroring = rom.header.mirroring;
-        let chr_rom = rom.chr_rom();
+        let region = rom.header.region;
 
         Bus {
             ram: [0; RAM_SIZE],
             cartridge: rom,
-            ppu: Ppu::new(chr_rom, mirroring),
-            apu: Apu::new(),
+            ppu: Ppu::new(region),
+            apu: Apu::new(region),
             cycles: 0,
             nmi: Interrupt::new(),
             irq: Interrupt::new(),
+            nmi_line: false,
+            region,
+            ppu_clock: 0,
+            last_write: false,
             joypad1: Joypad::new(),
         }
     }
 
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Overrides the region detected from the cartridge header
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu_clock = 0;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
     /// Reads a byte from the interface at the given address
     pub fn read(&mut self, address: u16) -> u8 {
         self.tick();
+        self.dmc_dma(Access::Read(address));
+        self.last_write = false;
         self.unclocked_read(address)
     }
 
     pub fn unclocked_read(&mut self, address: u16) -> u8 {
         match address {
             0..=0x1FFF => self.ram[(address & 0x7FF) as usize],
-            0x2000..=0x3FFF => self.ppu.read(address),
+            0x2000..=0x3FFF => {
+                let data = self.ppu.read(address, &mut self.cartridge);
+                self.poll_nmi();
+                data
+            }
             0x4000..=0x4015 => self.apu.read(address),
             0x4016 => self.joypad1.read(),
             0x4017 => 0,
@@ -53,6 +86,15 @@ impl Bus {
         }
     }
 
+    /// Reads RAM or the cartridge without side effects. Registers read as 0.
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0..=0x1FFF => self.ram[(address & 0x7FF) as usize],
+            0x4020..=0xFFFF => self.cartridge.read(address),
+            _ => 0,
+        }
+    }
+
     pub fn read_word(&mut self, address: u16) -> u16 {
         let low_byte = self.read(address) as u16;
         let high_byte = self.read(address + 1) as u16;
@@ -70,9 +112,14 @@ impl Bus {
     /// Writes a byte to the interface at the given address
     pub fn write(&mut self, address: u16, data: u8) {
         self.tick();
+        self.dmc_dma(Access::Write);
+        self.last_write = true;
         match address {
             0..=0x1FFF => self.ram[(address & 0x7FF) as usize] = data,
-            0x2000..=0x3FFF => self.ppu.write(address, data),
+            0x2000..=0x3FFF => {
+                self.ppu.write(address, data, &mut self.cartridge);
+                self.poll_nmi();
+            }
             0x4014 => {
                 let offset = (data as usize) << 8;
                 let buffer = &self.ram[offset..offset + 256];
@@ -80,7 +127,7 @@ impl Bus {
             }
             0x4000..=0x4015 => self.apu.write(address, data),
             0x4016 => self.joypad1.write(data),
-            0x4017 => {}
+            0x4017 => self.apu.write(address, data),
             0x4018..=0x401F => panic!("APU and I/O functionality that is normally disabled."),
             0x4020..=0xFFFF => self.cartridge.write(address, data),
         };
@@ -90,17 +137,87 @@ impl Bus {
         self.cycles = self.cycles.wrapping_add(1);
 
         self.nmi.tick();
+        self.cartridge.clock();
+        self.apu.tick_with(self.cartridge.audio());
+
+        let (dots, cycles) = self.region.ppu_clock_ratio();
+        self.ppu_clock += dots;
+        while self.ppu_clock >= cycles {
+            self.ppu_clock -= cycles;
+            self.ppu.tick(&mut self.cartridge);
+        }
 
-        self.ppu.tick(&mut self.nmi);
-        self.ppu.tick(&mut self.nmi);
-        self.ppu.tick(&mut self.nmi);
+        self.poll_nmi();
+        self.poll_irq();
+    }
+
+    /// Fetches a sample byte for the DMC when it needs one, halting the CPU
+    /// for the cycles it takes: four when it interrupts a read, three on a
+    /// write as the CPU only halts on the next read, or two after a run of
+    /// writes. Fetches wait for the CPU's next read or write, which is never
+    /// more than a few cycles.
+    fn dmc_dma(&mut self, access: Access) {
+        let address = match self.apu.dmc_dma_address() {
+            Some(address) => address,
+            None => return,
+        };
+
+        let stall = match access {
+            Access::Read(_) => 4,
+            Access::Write if self.last_write => 2,
+            Access::Write => 3,
+        };
+        for _ in 0..stall {
+            self.tick();
+        }
+
+        // The halted CPU repeats its read, so a controller read during the
+        // fetch clocks the shift register twice and loses a bit
+        if access == Access::Read(0x4016) {
+            self.joypad1.read();
+        }
+
+        self.apu.dmc_fill(self.peek(address));
+    }
+
+    /// The IRQ line is level triggered: it stays asserted while any source
+    /// holds it, until the handler acknowledges the source
+    fn poll_irq(&mut self) {
+        if !self.apu.irq() && !self.cartridge.irq() {
+            self.irq.acknowledge();
+        } else if !self.irq.ready() {
+            self.irq.schedule(0);
+        }
+    }
+
+    /// Edge detects the PPU's NMI output. A rising edge schedules an NMI for
+    /// the next cycle, and the line dropping before then cancels it again.
+    fn poll_nmi(&mut self) {
+        let line = self.ppu.nmi_line();
+
+        if line && !self.nmi_line {
+            self.nmi.schedule(1);
+        } else if !line && !self.nmi.ready() {
+            self.nmi.acknowledge();
+        }
+
+        self.nmi_line = line;
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::io::Cursor;
+
     use super::*;
 
+    fn cartridge() -> Box<NESRom> {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1];
+        data.resize(16 + 0x4000 + 0x2000, 0);
+
+        Box::new(NESRom::new(&mut Cursor::new(data)).unwrap())
+    }
+
     #[test]
     fn reads_and_writes_to_ram() {
         let cartridge = Box::new(NESRom::from_file("./priv/nestest.nes").unwrap());
@@ -122,4 +239,132 @@ mod test {
         assert_eq!(bus.read(0x1000), 0xff);
         assert_eq!(bus.read(0x1800), 0xff);
     }
+
+    #[test]
+    fn enabling_nmi_during_vblank_raises_nmi() {
+        let mut bus = Bus::new(cartridge());
+
+        // The PPU powers up with the vblank flag set
+        bus.write(0x2000, 0x80);
+        assert!(!bus.nmi.ready());
+
+        bus.tick();
+        assert!(bus.nmi.ready());
+    }
+
+    #[test]
+    fn status_read_at_vblank_start_cancels_nmi() {
+        let mut bus = Bus::new(cartridge());
+        bus.read(0x2002);
+        bus.write(0x2000, 0x80);
+
+        while !bus.ppu.nmi_line() {
+            bus.tick();
+        }
+
+        assert_eq!(bus.unclocked_read(0x2002) & 0x80, 0x80);
+        bus.tick();
+        assert!(!bus.nmi.ready());
+    }
+
+    #[test]
+    fn nmi_fires_once_per_frame() {
+        let mut bus = Bus::new(cartridge());
+        bus.read(0x2002);
+        bus.write(0x2000, 0x80);
+
+        let mut count = 0;
+        for _ in 0..29781 * 2 {
+            bus.tick();
+            if bus.nmi.ready() {
+                bus.nmi.acknowledge();
+                count += 1;
+            }
+        }
+
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn pal_runs_sixteen_dots_every_five_cycles() {
+        let mut bus = Bus::new(cartridge());
+        bus.set_region(Region::Pal);
+
+        let frame = bus.ppu.frame();
+        while bus.ppu.frame() == frame {
+            bus.tick();
+        }
+
+        let start = bus.ppu.frame();
+        let mut cycles = 0;
+        while bus.ppu.frame() == start {
+            bus.tick();
+            cycles += 1;
+        }
+
+        // 312 lines of 341 dots at 3.2 dots per cycle
+        assert!((cycles as f64 - 312.0 * 341.0 / 3.2).abs() < 1.0);
+    }
+
+    /// Starts a one byte DMC sample with its IRQ enabled
+    fn start_sample(bus: &mut Bus) {
+        bus.write(0x4010, 0x8F);
+        bus.write(0x4013, 0x00);
+        bus.write(0x4015, 0x10);
+    }
+
+    #[test]
+    fn dmc_fetch_stalls_the_cpu() {
+        let mut bus = Bus::new(cartridge());
+
+        start_sample(&mut bus);
+        let cycles = bus.cycles;
+        bus.read(0x0000);
+        assert_eq!(bus.cycles - cycles, 5);
+
+        // The next fetch waits for the first byte to leave the buffer
+        start_sample(&mut bus);
+        while bus.apu.dmc_dma_address().is_none() {
+            bus.tick();
+        }
+        bus.last_write = false;
+        let cycles = bus.cycles;
+        bus.write(0x0000, 0);
+        assert_eq!(bus.cycles - cycles, 4);
+
+        // Only the fetch stalls, not the accesses after it
+        let cycles = bus.cycles;
+        bus.write(0x0000, 0);
+        assert_eq!(bus.cycles - cycles, 1);
+    }
+
+    #[test]
+    fn dmc_fetch_during_controller_read_loses_a_bit() {
+        let mut bus = Bus::new(cartridge());
+        bus.joypad1.buttons.set(0b10); // B only
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+
+        start_sample(&mut bus);
+        // Reads B's bit rather than A's
+        assert_eq!(bus.read(0x4016), 1);
+    }
+
+    #[test]
+    fn dmc_raises_irq_at_sample_end() {
+        let mut bus = Bus::new(cartridge());
+        start_sample(&mut bus);
+        bus.read(0x0000);
+        bus.tick();
+        assert!(bus.irq.ready());
+
+        // Held until acknowledged through $4015
+        bus.irq.acknowledge();
+        bus.tick();
+        assert!(bus.irq.ready());
+
+        bus.write(0x4015, 0x00);
+        bus.tick();
+        assert!(!bus.irq.ready());
+    }
 }
@@ -301,6 +301,124 @@ mod test {
         assert_eq!(cpu.pop_stack(), 1);
     }
 
+    // Emulated time a test ROM gets to report a result, about a minute
+    const TEST_ROM_CYCLES: u64 = 100_000_000;
+
+    /// Runs one of blargg's test ROMs, which report through $6000 once the
+    /// bytes after it read DE B0 61: $80 while running, $81 when they want
+    /// the reset button pressed, then a result code, with a message from
+    /// $6004. A code of 0 is a pass.
+    fn run_test_rom(filename: &str) -> Result<(), String> {
+        let cartridge =
+            NESRom::from_file(filename).map_err(|err| format!("{}: {}", filename, err))?;
+        run_test_cartridge(filename, cartridge)
+    }
+
+    fn run_test_cartridge(filename: &str, cartridge: NESRom) -> Result<(), String> {
+        let mut cpu = Cpu::new(Box::new(cartridge));
+        cpu.reset();
+
+        // Results only count once the ROM has said it's running
+        let mut running = false;
+        let mut status = 0;
+        let mut reset_at = None;
+        while cpu.bus.cycles < TEST_ROM_CYCLES {
+            cpu.execute_next_opcode();
+
+            if reset_at.is_some_and(|cycles| cpu.bus.cycles >= cycles) {
+                reset_at = None;
+                cpu.reset();
+            }
+
+            let signature = (0x6001..0x6004).map(|address| cpu.bus.peek(address));
+            if !signature.eq([0xDE, 0xB0, 0x61].iter().copied()) {
+                continue;
+            }
+
+            let previous = status;
+            status = cpu.bus.peek(0x6000);
+            match status {
+                0x80 => running = true,
+                _ if !running => {}
+                // Held for a tenth of a second, as the ROMs ask
+                0x81 if previous != 0x81 => reset_at = Some(cpu.bus.cycles + 180_000),
+                0x81 => {}
+                0 => return Ok(()),
+                code => {
+                    let message: Vec<u8> = (0x6004..0x7000)
+                        .map(|address| cpu.bus.peek(address))
+                        .take_while(|&byte| byte != 0)
+                        .collect();
+                    let message = String::from_utf8_lossy(&message);
+                    return Err(format!(
+                        "{} failed with {}: {}",
+                        filename,
+                        code,
+                        message.trim()
+                    ));
+                }
+            }
+        }
+
+        Err(format!("{} timed out", filename))
+    }
+
+    #[test]
+    fn test_roms_report_through_6000() {
+        // Says it's running, writes the signature and a message, then the
+        // result code
+        let rom = |code: u8| {
+            let writes = [0x80, 0xDE, 0xB0, 0x61, b'B', b'a', b'd', 0, code];
+            let mut program = vec![];
+            for (i, &byte) in writes.iter().enumerate() {
+                let address = match i {
+                    8 => 0x6000,
+                    _ => 0x6000 + i as u16,
+                };
+                program.extend_from_slice(&[0xA9, byte, 0x8D, address as u8, (address >> 8) as u8]);
+            }
+            let end = 0x8000 + program.len() as u16;
+            program.extend_from_slice(&[0x4C, end as u8, (end >> 8) as u8]); // JMP to itself
+
+            let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 0];
+            data.resize(16, 0);
+            data.extend_from_slice(&program);
+            data.resize(16 + 0x3FFC, 0);
+            data.extend_from_slice(&[0x00, 0x80, 0x00, 0x80]); // Reset and IRQ vectors
+            NESRom::new(&mut std::io::Cursor::new(data)).unwrap()
+        };
+
+        assert_eq!(run_test_cartridge("pass", rom(0)), Ok(()));
+        assert_eq!(
+            run_test_cartridge("fail", rom(3)),
+            Err("fail failed with 3: Bad".to_string())
+        );
+    }
+
+    /// Runs blargg's ppu_vbl_nmi suite, which scripts/fetch_test_roms.sh
+    /// downloads into priv/ppu_vbl_nmi
+    #[test]
+    fn ppu_vbl_nmi() {
+        let roms = [
+            "01-vbl_basics",
+            "02-vbl_set_time",
+            "03-vbl_clear_time",
+            "04-nmi_control",
+            "05-nmi_timing",
+            "06-suppression",
+            "07-nmi_on_timing",
+            "08-nmi_off_timing",
+            "09-even_odd_frames",
+            "10-even_odd_timing",
+        ];
+
+        let failures: Vec<String> = roms
+            .iter()
+            .filter_map(|rom| run_test_rom(&format!("./priv/ppu_vbl_nmi/{}.nes", rom)).err())
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
     #[test]
     fn test_stack_16() {
         let cartridge = Box::new(NESRom::from_file("./priv/nestest.nes").unwrap());
@@ -1,17 +1,54 @@
 #![allow(dead_code)]
 mod apu;
+mod audio;
 mod cartridge;
 mod hardware;
 mod mapper;
+mod movie;
+mod nsf;
+mod options;
+mod pacer;
 mod ppu;
+mod record;
+mod region;
 mod render;
 
+use apu::{Channel, Mixer};
+use audio::{AudioOutput, SyncMode};
 use cartridge::rom::NESRom;
 use hardware::cpu::Cpu;
-use render::frame::Frame;
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum, EventPump};
+use movie::Movie;
+use nsf::{Nsf, NsfPlayer};
+use options::Options;
+use pacer::{vsync_matches, FramePacer};
+use record::{wav::WavWriter, Recorder, RecorderFactory, StreamFormat};
+use render::{
+    animation::GifRecorder,
+    blend::FrameBlender,
+    debug::DebugViews,
+    hd_pack::HdPack,
+    oscilloscope,
+    palette::{Palette, PaletteControl},
+    pipeline::Pipeline,
+    presentation::{self, Presentation},
+    screenshot,
+};
+use sdl2::{
+    event::Event,
+    keyboard::Keycode,
+    pixels::{Color, PixelFormatEnum},
+    rect::Rect,
+    render::WindowCanvas,
+    video::{FullscreenType, Window},
+    EventPump,
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
 
-fn handle_key(cpu: &mut Cpu, keycode: Keycode, keydown: bool) {
+fn handle_key(cpu: &mut Cpu, keycode: Keycode, keydown: bool) -> bool {
     let joypad = &mut cpu.bus.joypad1;
     match keycode {
         Keycode::W => joypad.buttons.set_up(keydown),
@@ -22,68 +59,771 @@ fn handle_key(cpu: &mut Cpu, keycode: Keycode, keydown: bool) {
         Keycode::Space => joypad.buttons.set_select(keydown),
         Keycode::Left => joypad.buttons.set_button_a(keydown),
         Keycode::Right => joypad.buttons.set_button_b(keydown),
-        _ => (),
+        _ => return false,
     }
+
+    true
 }
 
-fn handle_user_input(cpu: &mut Cpu, event_pump: &mut EventPump) {
+/// Updates the joypad and returns any other keys that were pressed, or None
+/// when the user quits
+fn handle_user_input(cpu: &mut Cpu, event_pump: &mut EventPump) -> Option<Vec<Keycode>> {
+    let mut hotkeys = Vec::new();
+
     for event in event_pump.poll_iter() {
         match event {
             Event::Quit { .. }
             | Event::KeyDown {
                 keycode: Some(Keycode::Escape),
                 ..
-            } => std::process::exit(0),
+            } => return None,
             Event::KeyDown {
                 keycode: Some(keycode),
+                repeat: false,
                 ..
-            } => handle_key(cpu, keycode, true),
+            } if !handle_key(cpu, keycode, true) => hotkeys.push(keycode),
             Event::KeyUp {
                 keycode: Some(keycode),
                 ..
-            } => handle_key(cpu, keycode, false),
+            } => {
+                handle_key(cpu, keycode, false);
+            }
             _ => (),
         }
     }
+
+    Some(hotkeys)
+}
+
+/// The channel keys 1-9 and 0 mute and F1-F10 solo the channels playing, in
+/// the order the oscilloscope shows them. Also says whether it's a solo.
+fn channel_hotkey(keycode: Keycode, channels: &[Channel]) -> Option<(Channel, bool)> {
+    let (index, solo) = match keycode {
+        Keycode::Num1 => (0, false),
+        Keycode::Num2 => (1, false),
+        Keycode::Num3 => (2, false),
+        Keycode::Num4 => (3, false),
+        Keycode::Num5 => (4, false),
+        Keycode::Num6 => (5, false),
+        Keycode::Num7 => (6, false),
+        Keycode::Num8 => (7, false),
+        Keycode::Num9 => (8, false),
+        Keycode::Num0 => (9, false),
+        Keycode::F1 => (0, true),
+        Keycode::F2 => (1, true),
+        Keycode::F3 => (2, true),
+        Keycode::F4 => (3, true),
+        Keycode::F5 => (4, true),
+        Keycode::F6 => (5, true),
+        Keycode::F7 => (6, true),
+        Keycode::F8 => (7, true),
+        Keycode::F9 => (8, true),
+        Keycode::F10 => (9, true),
+        _ => return None,
+    };
+
+    Some((*channels.get(index)?, solo))
+}
+
+/// Applies the mixer options before emulation starts
+fn apply_mixer_options(mixer: &mut Mixer, options: &Options) {
+    for &channel in options.mute.iter() {
+        mixer.set_muted(channel, true);
+    }
+    for &(channel, volume) in options.volumes.iter() {
+        mixer.set_volume(channel, volume);
+    }
+    mixer.set_solo(options.solo);
+}
+
+/// Toggles a channel's mute or solo, describing what changed
+fn toggle_channel(mixer: &mut Mixer, channel: Channel, solo: bool) -> String {
+    if solo {
+        let solo = Some(channel).filter(|&channel| mixer.solo() != Some(channel));
+        mixer.set_solo(solo);
+        match solo {
+            Some(channel) => format!("{} solo", channel),
+            None => "all channels".to_string(),
+        }
+    } else {
+        let muted = !mixer.muted(channel);
+        mixer.set_muted(channel, muted);
+        let state = if muted { "muted" } else { "unmuted" };
+        format!("{} {}", channel, state)
+    }
+}
+
+/// Work done around every emulated frame in both the windowed and headless
+/// loops: movie playback, rendering, recording and collecting audio
+struct FrameLoop {
+    movie: Option<Movie>,
+    recorder: Option<(Box<dyn Recorder>, StreamFormat)>,
+    gif: Option<GifRecorder<BufWriter<File>>>,
+    frames: usize,
+}
+
+impl FrameLoop {
+    fn new(options: &Options, cpu: &Cpu, pipeline: &Pipeline) -> io::Result<FrameLoop> {
+        let movie = match &options.movie {
+            Some(filename) => Some(Movie::from_file(filename)?),
+            None => None,
+        };
+
+        let recorder = match &options.record {
+            Some(filename) => {
+                let (width, height) = pipeline.output_size();
+                let format = StreamFormat {
+                    width,
+                    height,
+                    frame_rate: cpu.bus.region().frame_rate_ratio(),
+                    pixel_aspect: screenshot::pixel_aspect(
+                        width,
+                        height,
+                        pipeline.display_aspect(),
+                    ),
+                    sample_rate: cpu.bus.apu.sample_rate(),
+                };
+                Some((RecorderFactory::create(filename, format)?, format))
+            }
+            None => None,
+        };
+
+        Ok(FrameLoop {
+            movie,
+            recorder,
+            gif: None,
+            frames: 0,
+        })
+    }
+
+    /// Runs and renders one frame and returns the audio it produced
+    fn step(&mut self, cpu: &mut Cpu, pipeline: &mut Pipeline) -> io::Result<Vec<f32>> {
+        if let Some(movie) = &self.movie {
+            cpu.bus.joypad1.buttons.set(movie.buttons(self.frames));
+        }
+
+        // HD pack conditions see memory as it is while the frame is drawn,
+        // not after the game has moved on to the next one
+        if let Some(hd_pack) = pipeline.hd_pack_mut() {
+            hd_pack.update_memory(|address| cpu.bus.peek(address));
+        }
+        run_frame(cpu);
+        self.frames += 1;
+        let image = pipeline.process(cpu.bus.ppu.frame_buffer(), cpu.bus.ppu.frame());
+
+        if let Some(gif) = &mut self.gif {
+            gif.add_frame(cpu.bus.ppu.frame_buffer())?;
+        }
+
+        let samples = cpu.bus.apu.take_samples();
+        if let Some((recorder, format)) = &mut self.recorder {
+            // The video size is fixed, so switching filters mid recording
+            // resamples to the size it started with
+            if (image.width, image.height) == (format.width, format.height) {
+                recorder.add_frame(image)?;
+            } else {
+                recorder.add_frame(&image.scale_to(format.width, format.height))?;
+            }
+            recorder.add_audio(&samples)?;
+        }
+
+        Ok(samples)
+    }
+
+    /// Starts capturing a GIF, or finishes the one being captured
+    fn toggle_gif(&mut self, options: &Options, cpu: &Cpu, palette: &Palette) -> io::Result<()> {
+        match self.gif.take() {
+            Some(gif) => gif.finish().map(|_| ()),
+            None => {
+                let filename = format!(
+                    "{}/{}-{}.gif",
+                    options.screenshot_dir,
+                    rom_name(options),
+                    cpu.bus.ppu.frame()
+                );
+                self.gif = Some(GifRecorder::new(
+                    BufWriter::new(File::create(filename)?),
+                    palette,
+                    cpu.bus.region().frame_rate(),
+                    options.gif_frame_skip,
+                )?);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(gif) = self.gif.take() {
+            gif.finish()?;
+        }
+
+        match &mut self.recorder {
+            Some((recorder, _)) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn run_frame(cpu: &mut Cpu) {
+    let frame_number = cpu.bus.ppu.frame();
+    while cpu.bus.ppu.frame() == frame_number {
+        cpu.execute_next_opcode();
+    }
+}
+
+fn rom_name(options: &Options) -> String {
+    Path::new(&options.rom)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn is_nsf(filename: &str) -> bool {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    matches!(extension.as_deref(), Some("nsf") | Some("nsfe"))
+}
+
+/// Window title for the NSF player: the game, the track and how far in
+fn nsf_title(player: &NsfPlayer) -> String {
+    let nsf = player.nsf();
+    let track = player.track();
+    let mut title = format!("{} - {}/{}", nsf.title, track + 1, nsf.songs);
+    if let Some(name) = &nsf.tracks[track].name {
+        title.push_str(&format!(" {}", name));
+    }
+
+    let seconds = player.elapsed() as u64;
+    title.push_str(&format!(" ({}:{:02})", seconds / 60, seconds % 60));
+    title
+}
+
+/// Plays a music rip instead of a game. With `--wav` the track is rendered
+/// to a file, otherwise it plays in a window showing the oscilloscope, where
+/// Left and Right move through the playlist.
+fn play_nsf(options: &Options) {
+    let nsf = Nsf::load(&options.rom).unwrap();
+    let mut player = NsfPlayer::new(nsf, options.region, options.sample_rate);
+    apply_mixer_options(player.apu_mut().mixer_mut(), options);
+    if let Some(seconds) = options.track_length {
+        player.default_length = seconds.saturating_mul(1000);
+    }
+
+    let names: Vec<String> = (player.nsf().expansion_chips().iter())
+        .filter(|chip| !chip.emulated())
+        .map(|chip| chip.to_string())
+        .collect();
+    if !names.is_empty() {
+        eprintln!("Expansion audio is not emulated: {}", names.join(", "));
+    }
+
+    // Tracks picked outside the playlist play first
+    let nsf = player.nsf();
+    let first = options.track.map_or(nsf.starting_song, |track| track - 1);
+    if first >= nsf.songs {
+        eprintln!("There are only {} tracks", nsf.songs);
+        std::process::exit(1);
+    }
+    let mut playlist = nsf.playlist.clone();
+    let mut position = playlist
+        .iter()
+        .position(|&track| track == first)
+        .unwrap_or_else(|| {
+            playlist.insert(0, first);
+            0
+        });
+    player.start_track(playlist[position]);
+
+    if let Some(filename) = &options.wav {
+        let file = BufWriter::new(File::create(filename).unwrap());
+        let mut wav = WavWriter::new(file, options.sample_rate).unwrap();
+        while !player.finished() {
+            wav.write_samples(&player.play_frame()).unwrap();
+        }
+        wav.finish().unwrap();
+        return;
+    }
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(
+            "NES-RS",
+            oscilloscope::WIDTH as u32,
+            oscilloscope::HEIGHT as u32,
+        )
+        .position_centered()
+        .build()
+        .unwrap();
+    let mut canvas = window.into_canvas().build().unwrap();
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            oscilloscope::WIDTH as u32,
+            oscilloscope::HEIGHT as u32,
+        )
+        .unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+
+    let mut audio = sdl_context.audio().and_then(|subsystem| {
+        AudioOutput::new(&subsystem, options.sample_rate, options.audio_latency)
+    });
+    if let Err(err) = &audio {
+        eprintln!("Unable to open audio: {}", err);
+    }
+    let mut pacer = FramePacer::new(player.play_rate());
+
+    loop {
+        let samples = player.play_frame();
+        if let Ok(audio) = &mut audio {
+            audio.queue(&samples);
+        }
+
+        let image = oscilloscope::compose(player.apu());
+        texture.update(None, &image.data, image.pitch()).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+        let title = nsf_title(&player);
+        if canvas.window().title() != title {
+            canvas.window_mut().set_title(&title).unwrap();
+        }
+
+        let mut skip = if player.finished() { 1 } else { 0 };
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } => match keycode {
+                    Keycode::Right => skip = 1,
+                    Keycode::Left => skip = -1,
+                    _ => {
+                        let channels = player.apu().channels();
+                        if let Some((channel, solo)) = channel_hotkey(keycode, &channels) {
+                            toggle_channel(player.apu_mut().mixer_mut(), channel, solo);
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        if skip != 0 {
+            // Stop after the last track; going back from the first restarts it
+            position = match (position as isize + skip) as usize {
+                next if next >= playlist.len() && skip > 0 => return,
+                next if next >= playlist.len() => 0,
+                next => next,
+            };
+            player.start_track(playlist[position]);
+        }
+
+        match &audio {
+            Ok(audio) => audio.throttle(),
+            Err(_) => pacer.wait(),
+        }
+    }
+}
+
+fn save_screenshot(
+    options: &Options,
+    cpu: &Cpu,
+    pipeline: &Pipeline,
+    filename: &str,
+) -> io::Result<()> {
+    let metadata = screenshot::Metadata {
+        rom_name: rom_name(options),
+        rom_crc32: cpu.bus.cartridge.crc32,
+        frame: cpu.bus.ppu.frame(),
+    };
+    let screenshot = screenshot::capture(
+        options.screenshot_mode,
+        cpu.bus.ppu.frame_buffer(),
+        pipeline.palette(),
+        pipeline.output(),
+        pipeline.display_aspect(),
+    );
+
+    screenshot::save(&screenshot, &metadata, filename)
 }
+
+fn load_presentation(options: &Options) -> Presentation {
+    Presentation::load(&options.display_config).unwrap_or_else(|err| {
+        eprintln!("Unable to read {}: {}", options.display_config, err);
+        Presentation::default()
+    })
+}
+
+/// The saved presentation settings with this run's command line overrides
+fn override_presentation(saved: &Presentation, options: &Options) -> Presentation {
+    let mut presentation = saved.clone();
+
+    if let Some(aspect) = options.aspect {
+        presentation.aspect = aspect;
+    }
+    if let Some(overscan) = options.overscan {
+        presentation.overscan = Some(overscan);
+    }
+    if let Some(integer_scaling) = options.integer_scaling {
+        presentation.integer_scaling = integer_scaling;
+    }
+    if let Some(fullscreen) = options.fullscreen {
+        presentation.fullscreen = fullscreen;
+    }
+
+    presentation
+}
+
+fn save_presentation(options: &Options, presentation: &Presentation) {
+    if let Err(err) = presentation.save(&options.display_config) {
+        eprintln!("Unable to save {}: {}", options.display_config, err);
+    }
+}
+
+/// The refresh rate of the display a window is on, or 0 if unknown
+fn refresh_rate(window: &Window) -> i32 {
+    window
+        .display_mode()
+        .map(|mode| mode.refresh_rate)
+        .unwrap_or(0)
+}
+
+fn set_fullscreen(canvas: &mut WindowCanvas, fullscreen: bool) {
+    // Borderless at the desktop's resolution, so switching is instant
+    let mode = if fullscreen {
+        FullscreenType::Desktop
+    } else {
+        FullscreenType::Off
+    };
+    if let Err(err) = canvas.window_mut().set_fullscreen(mode) {
+        eprintln!("Unable to switch fullscreen: {}", err);
+    }
+}
+
 fn main() {
+    let options = Options::from_args().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+
+    let palette = match (&options.palette_settings, &options.palette) {
+        (Some(settings), _) => Palette::generate(settings),
+        (None, Some(name)) => Palette::load(name).unwrap(),
+        (None, None) => Palette::default(),
+    };
+
+    if let Some(filename) = &options.export_palette {
+        palette.save(filename).unwrap();
+        return;
+    }
+
+    if is_nsf(&options.rom) {
+        play_nsf(&options);
+        return;
+    }
+
+    let cartridge = Box::new(NESRom::from_file(&options.rom).unwrap());
+
+    let mut cpu = Cpu::new(cartridge);
+    if let Some(region) = options.region {
+        cpu.bus.set_region(region);
+    }
+    cpu.bus.apu.set_sample_rate(options.sample_rate);
+    apply_mixer_options(cpu.bus.apu.mixer_mut(), &options);
+    cpu.reset();
+
+    // Hotkeys change both, but only the saved settings are written back
+    let mut saved_presentation = load_presentation(&options);
+    let mut presentation = override_presentation(&saved_presentation, &options);
+    let mut pipeline = Pipeline::new(palette, options.ntsc, options.filter);
+    pipeline.overscan = presentation.overscan(cpu.bus.region());
+    pipeline.aspect = presentation.aspect;
+    pipeline.blender = FrameBlender::new(options.blend, options.blend_decay);
+    // Adjusting the palette at runtime regenerates it from these, replacing
+    // any palette that was loaded
+    let mut palette_settings = options.palette_settings.unwrap_or_default();
+    let mut palette_control = PaletteControl::Hue;
+    if let Some(directory) = &options.hd_pack {
+        pipeline.set_hd_pack(HdPack::load(directory).unwrap());
+        cpu.bus.ppu.track_sources();
+    }
+    let mut frame_loop = FrameLoop::new(&options, &cpu, &pipeline).unwrap();
+
+    if options.headless || options.dump_ppu.is_some() || options.screenshot.is_some() {
+        // Headless: run the requested number of frames and write the output
+        for _ in 0..options.frames {
+            frame_loop.step(&mut cpu, &mut pipeline).unwrap();
+        }
+        frame_loop.finish().unwrap();
+
+        if let Some(directory) = &options.dump_ppu {
+            DebugViews::new()
+                .dump(
+                    &cpu.bus.ppu,
+                    &cpu.bus.cartridge,
+                    pipeline.palette(),
+                    directory,
+                )
+                .unwrap();
+        }
+        if let Some(filename) = &options.screenshot {
+            save_screenshot(&options, &cpu, &pipeline, filename).unwrap();
+        }
+        return;
+    }
+
     // init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+    // Start at three times the visible lines
+    let (_, lines) = pipeline.overscan.visible_size();
+    let (aspect_width, aspect_height) = pipeline.display_aspect();
     let window = video_subsystem
-        .window("NES-RS", (256.0 * 3.0) as u32, (240.0 * 3.0) as u32)
+        .window(
+            "NES-RS",
+            (lines * 3 * aspect_width / aspect_height) as u32,
+            (lines * 3) as u32,
+        )
         .position_centered()
+        .resizable()
         .build()
         .unwrap();
 
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    // With video sync, the vsync'd present paces emulation when the display
+    // runs at the console's rate, and the frame pacer does otherwise. Rate
+    // control stretches the audio to cover the drift between the two. Audio
+    // sync does its own pacing.
+    let vsync = options.sync == SyncMode::Video
+        && vsync_matches(refresh_rate(&window), cpu.bus.region().frame_rate());
+    let mut canvas = match vsync {
+        true => window.into_canvas().present_vsync().build().unwrap(),
+        false => window.into_canvas().build().unwrap(),
+    };
     let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(3.0, 3.0).unwrap();
+    if presentation.fullscreen {
+        set_fullscreen(&mut canvas, true);
+    }
 
     let creator = canvas.texture_creator();
+    let (width, height) = pipeline.output_size();
     let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 256, 240)
+        .create_texture_target(PixelFormatEnum::RGB24, width as u32, height as u32)
         .unwrap();
 
-    let cartridge = Box::new(NESRom::from_file("priv/mario1.nes").unwrap());
+    let debug_canvas = if options.debug {
+        let window = video_subsystem
+            .window(
+                "NES-RS PPU",
+                DebugViews::WIDTH as u32,
+                DebugViews::HEIGHT as u32,
+            )
+            .build()
+            .unwrap();
+        Some(window.into_canvas().build().unwrap())
+    } else {
+        None
+    };
+    let debug_creator = debug_canvas.as_ref().map(|canvas| canvas.texture_creator());
+    let debug_texture = debug_creator.as_ref().map(|creator| {
+        creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                DebugViews::WIDTH as u32,
+                DebugViews::HEIGHT as u32,
+            )
+            .unwrap()
+    });
+    let mut debug = debug_canvas
+        .zip(debug_texture)
+        .map(|(canvas, texture)| (DebugViews::new(), canvas, texture));
 
-    let mut cpu = Cpu::new(cartridge);
-    cpu.reset();
+    let scope_canvas = if options.scope {
+        let window = video_subsystem
+            .window(
+                "NES-RS APU",
+                oscilloscope::WIDTH as u32,
+                oscilloscope::HEIGHT as u32,
+            )
+            .build()
+            .unwrap();
+        Some(window.into_canvas().build().unwrap())
+    } else {
+        None
+    };
+    let scope_creator = scope_canvas.as_ref().map(|canvas| canvas.texture_creator());
+    let scope_texture = scope_creator.as_ref().map(|creator| {
+        creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                oscilloscope::WIDTH as u32,
+                oscilloscope::HEIGHT as u32,
+            )
+            .unwrap()
+    });
+    let mut scope = scope_canvas.zip(scope_texture);
+
+    let mut audio = sdl_context.audio().and_then(|subsystem| {
+        AudioOutput::new(&subsystem, options.sample_rate, options.audio_latency)
+    });
+    if let Err(err) = &audio {
+        eprintln!("Unable to open audio: {}", err);
+    }
+
+    let mut pacer = FramePacer::new(cpu.bus.region().frame_rate());
 
     // run the game cycle
-    let mut frame = Frame::new();
-    let mut i = 0;
     loop {
-        i += 1;
-        cpu.execute_next_opcode();
+        let samples = frame_loop.step(&mut cpu, &mut pipeline).unwrap();
+        if let Ok(audio) = &mut audio {
+            audio.queue(&samples);
+            if options.sync == SyncMode::Video {
+                cpu.bus.apu.set_rate_adjustment(audio.rate_adjustment());
+            }
+        }
 
-        if i % 10_000 == 0 {
-            render::render(&cpu.bus.ppu, &mut frame);
-            texture.update(None, &frame.data, 256 * 3).unwrap();
+        // Filters can change the image size at runtime
+        let image = pipeline.output();
+        let query = texture.query();
+        if (query.width as usize, query.height as usize) != (image.width, image.height) {
+            texture = creator
+                .create_texture_target(
+                    PixelFormatEnum::RGB24,
+                    image.width as u32,
+                    image.height as u32,
+                )
+                .unwrap();
+        }
+        texture.update(None, &image.data, image.pitch()).unwrap();
+
+        let (x, y, width, height) = presentation::viewport(
+            canvas.output_size().unwrap(),
+            pipeline.display_aspect(),
+            pipeline.overscan.visible_size().1,
+            presentation.integer_scaling,
+        );
+        canvas.set_draw_color(Color::BLACK);
+        canvas.clear();
+        canvas
+            .copy(&texture, None, Rect::new(x, y, width, height))
+            .unwrap();
+        canvas.present();
+
+        if let Some((views, canvas, texture)) = &mut debug {
+            let image = views.compose(&cpu.bus.ppu, &cpu.bus.cartridge, pipeline.palette());
+            texture.update(None, &image.data, image.pitch()).unwrap();
+            canvas.copy(texture, None, None).unwrap();
+            canvas.present();
+        }
 
-            canvas.copy(&texture, None, None).unwrap();
+        if let Some((canvas, texture)) = &mut scope {
+            let image = oscilloscope::compose(&cpu.bus.apu);
+            texture.update(None, &image.data, image.pitch()).unwrap();
+            canvas.copy(texture, None, None).unwrap();
             canvas.present();
-            handle_user_input(&mut cpu, &mut event_pump);
+
+            let title = format!("NES-RS APU {}", oscilloscope::describe_notes(&cpu.bus.apu));
+            if canvas.window().title() != title {
+                canvas.window_mut().set_title(&title).unwrap();
+            }
+        }
+
+        let hotkeys = match handle_user_input(&mut cpu, &mut event_pump) {
+            Some(hotkeys) => hotkeys,
+            None => break,
+        };
+
+        for keycode in hotkeys {
+            let channels = cpu.bus.apu.channels();
+            if let Some((channel, solo)) = channel_hotkey(keycode, &channels) {
+                let change = toggle_channel(cpu.bus.apu.mixer_mut(), channel, solo);
+                let title = format!("NES-RS ({})", change);
+                canvas.window_mut().set_title(&title).unwrap();
+                continue;
+            }
+
+            match (keycode, &mut debug) {
+                (Keycode::F12, _) => {
+                    let filename = format!(
+                        "{}/{}-{}.png",
+                        options.screenshot_dir,
+                        rom_name(&options),
+                        cpu.bus.ppu.frame()
+                    );
+                    if let Err(err) = save_screenshot(&options, &cpu, &pipeline, &filename) {
+                        eprintln!("Unable to save {}: {}", filename, err);
+                    }
+                }
+                (Keycode::G, _) => {
+                    if let Err(err) = frame_loop.toggle_gif(&options, &cpu, pipeline.palette()) {
+                        eprintln!("Unable to capture GIF: {}", err);
+                    }
+                }
+                (Keycode::F, _) => {
+                    pipeline.filter = pipeline.filter.next();
+                    let title = format!("NES-RS ({})", pipeline.filter);
+                    canvas.window_mut().set_title(&title).unwrap();
+                }
+                (Keycode::B, _) => {
+                    pipeline.blender.mode = pipeline.blender.mode.next();
+                    let title = format!("NES-RS (blend {})", pipeline.blender.mode);
+                    canvas.window_mut().set_title(&title).unwrap();
+                }
+                (Keycode::F11, _) => {
+                    presentation.fullscreen = !presentation.fullscreen;
+                    set_fullscreen(&mut canvas, presentation.fullscreen);
+                    saved_presentation.fullscreen = presentation.fullscreen;
+                    save_presentation(&options, &saved_presentation);
+                }
+                (Keycode::R, _) => {
+                    presentation.aspect = presentation.aspect.next();
+                    pipeline.aspect = presentation.aspect;
+                    saved_presentation.aspect = presentation.aspect;
+                    save_presentation(&options, &saved_presentation);
+                }
+                (Keycode::I, _) => {
+                    presentation.integer_scaling = !presentation.integer_scaling;
+                    saved_presentation.integer_scaling = presentation.integer_scaling;
+                    save_presentation(&options, &saved_presentation);
+                }
+                (Keycode::C, _) => {
+                    palette_control = palette_control.next();
+                    let title = format!(
+                        "NES-RS (palette {} {:.2})",
+                        palette_control,
+                        palette_settings.get(palette_control)
+                    );
+                    canvas.window_mut().set_title(&title).unwrap();
+                }
+                (Keycode::Minus, _) | (Keycode::Equals, _) => {
+                    let steps = if keycode == Keycode::Equals { 1 } else { -1 };
+                    palette_settings.adjust(palette_control, steps);
+                    pipeline.set_palette(Palette::generate(&palette_settings));
+                    let title = format!(
+                        "NES-RS (palette {} {:.2})",
+                        palette_control,
+                        palette_settings.get(palette_control)
+                    );
+                    canvas.window_mut().set_title(&title).unwrap();
+                }
+                (Keycode::P, Some((views, _, _))) => views.next_pattern_palette(),
+                _ => (),
+            }
+        }
+
+        match (&audio, options.sync) {
+            (Ok(audio), SyncMode::Audio) => audio.throttle(),
+            _ if vsync => (),
+            _ => pacer.wait(),
         }
     }
+
+    frame_loop.finish().unwrap();
 }
@@ -1,18 +1,83 @@
+use crate::apu::ExpansionAudio;
 use crate::cartridge::header::Header;
 
 use self::nrom::NRomMapper;
+use self::vrc6::{Vrc6Mapper, Vrc6Wiring};
+use self::vrc7::Vrc7Mapper;
 
 pub(crate) mod nrom;
+pub(crate) mod nsf;
+pub(crate) mod vrc6;
+pub(crate) mod vrc7;
+pub(crate) mod vrc_irq;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+}
+
+impl Mirroring {
+    // Horizontal:
+    //   [ A ] [ a ]
+    //   [ B ] [ b ]
+
+    // Vertical:
+    //   [ A ] [ B ]
+    //   [ a ] [ b ]
+
+    /// Maps a nametable address ($2000-$3EFF) to an offset into nametable RAM.
+    /// Only four-screen boards use more than the 2 KiB of console CIRAM.
+    pub fn nametable_offset(&self, address: u16) -> usize {
+        let index = (address & 0x0FFF) as usize;
+        let offset = index & 0x3FF;
+
+        match (self, index / 0x400) {
+            (Mirroring::Vertical, table) => (table & 1) * 0x400 + offset,
+            (Mirroring::Horizontal, table) => (table >> 1) * 0x400 + offset,
+            (Mirroring::SingleScreenLower, _) => offset,
+            (Mirroring::SingleScreenUpper, _) => 0x400 + offset,
+            (Mirroring::FourScreen, _) => index,
+        }
+    }
 }
 
 pub trait Mapper {
-    fn map(&self, address: u16) -> u16;
+    /// Maps a CPU address ($4020-$FFFF) to an offset into PRG memory, or
+    /// None where nothing on the cartridge answers
+    fn map(&self, address: u16) -> Option<usize>;
+
+    /// Handles a CPU write to the cartridge, returning the offset into PRG
+    /// memory the byte is stored at. Mappers with registers take the writes
+    /// meant for them here and return None.
+    fn write(&mut self, address: u16, _data: u8) -> Option<usize> {
+        self.map(address)
+    }
+
+    /// Maps a PPU pattern table address ($0000-$1FFF) to an offset into CHR memory
+    fn map_chr(&self, address: u16) -> usize;
+
     fn mirroring(&self) -> Mirroring;
+
+    /// Observes every address the PPU places on its bus. Mappers that count
+    /// A12 rises or remap nametables on the fly hook in here.
+    fn ppu_address(&mut self, _address: u16) {}
+
+    /// Clocked every CPU cycle, for mappers with IRQ counters or sound
+    fn clock(&mut self) {}
+
+    /// Whether the mapper is holding the CPU's IRQ line low
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Sound channels on the cartridge, which the APU mixes in with its own
+    fn audio(&self) -> Option<&dyn ExpansionAudio> {
+        None
+    }
 }
 
 pub struct MapperFactory;
@@ -21,7 +86,32 @@ impl MapperFactory {
     pub fn create(header: &Header) -> Box<dyn Mapper> {
         match header.mapper {
             0 => Box::new(NRomMapper::new(header)),
+            24 => Box::new(Vrc6Mapper::new(header, Vrc6Wiring::A)),
+            26 => Box::new(Vrc6Mapper::new(header, Vrc6Wiring::B)),
+            85 => Box::new(Vrc7Mapper::new(header)),
             _ => panic!("Unsupported mapper {}", header.mapper),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nametable_offsets() {
+        assert_eq!(Mirroring::Vertical.nametable_offset(0x2400), 0x400);
+        assert_eq!(Mirroring::Vertical.nametable_offset(0x2800), 0x000);
+        assert_eq!(Mirroring::Vertical.nametable_offset(0x2C10), 0x410);
+
+        assert_eq!(Mirroring::Horizontal.nametable_offset(0x2400), 0x000);
+        assert_eq!(Mirroring::Horizontal.nametable_offset(0x2800), 0x400);
+        assert_eq!(Mirroring::Horizontal.nametable_offset(0x2C10), 0x410);
+
+        assert_eq!(Mirroring::SingleScreenUpper.nametable_offset(0x2810), 0x410);
+        assert_eq!(Mirroring::FourScreen.nametable_offset(0x2C10), 0xC10);
+
+        // $3000-$3EFF mirrors $2000-$2EFF
+        assert_eq!(Mirroring::Vertical.nametable_offset(0x3400), 0x400);
+    }
+}
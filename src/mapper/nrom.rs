@@ -1,6 +1,6 @@
 use crate::cartridge::header::Header;
 
-use super::Mapper;
+use super::{Mapper, Mirroring};
 
 const RAM_PAGE_SIZE: u16 = 8192;
 
@@ -13,6 +13,7 @@ enum NRomType {
 pub struct NRomMapper {
     nrom_type: NRomType,
     ram_size: u16,
+    mirroring: Mirroring,
 }
 
 impl NRomMapper {
@@ -28,23 +29,30 @@ impl NRomMapper {
         NRomMapper {
             nrom_type,
             ram_size,
+            mirroring: header.mirroring,
         }
     }
 }
 
 impl Mapper for NRomMapper {
-    fn map(&self, address: u16) -> u16 {
+    fn map(&self, address: u16) -> Option<usize> {
         // NROM-128 has duplicate roms across 0x8000 and 0xC000
-        if self.nrom_type == NRomType::NRom128 && address >= 0xC000 {
+        let offset = if self.nrom_type == NRomType::NRom128 && address >= 0xC000 {
             address - 0xA000
         } else if address >= 0x8000 {
             address - 0x6000
         } else {
             (address - 0x6000) % self.ram_size
-        }
+        };
+
+        Some(offset as usize)
+    }
+
+    fn map_chr(&self, address: u16) -> usize {
+        address as usize
     }
 
-    fn mirroring(&self) -> super::Mirroring {
-        todo!()
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
     }
 }
@@ -0,0 +1,213 @@
+use crate::apu::{Channel, ExpansionAudio, Vrc6Audio, Vrc7Audio};
+
+use super::{Mapper, Mirroring};
+
+const BANK_SIZE: usize = 0x1000;
+
+/// The memory map NSF players give music rips: 8 KiB of RAM at $6000 and
+/// eight 4 KiB ROM banks across $8000-$FFFF, switched by writing bank
+/// numbers to $5FF8-$5FFF. Nothing else on the cartridge answers, bar the
+/// sound chips a rip asks for.
+pub struct NsfMapper {
+    banks: [u8; 8],
+    bank_count: usize,
+    vrc6: Option<Vrc6Audio>,
+    vrc7: Option<Vrc7Audio>,
+    channels: Vec<Channel>, // Every chip's, in turn
+}
+
+impl NsfMapper {
+    /// Takes the power-on banks and the number of banks in the ROM image
+    pub fn new(banks: [u8; 8], bank_count: usize) -> Self {
+        NsfMapper {
+            banks,
+            bank_count: bank_count.max(1),
+            vrc6: None,
+            vrc7: None,
+            channels: Vec::new(),
+        }
+    }
+
+    /// Adds a VRC6, with its sound registers at $9000-$B002
+    pub fn enable_vrc6(&mut self) {
+        self.vrc6 = Some(Vrc6Audio::new());
+        self.update_channels();
+    }
+
+    /// Adds a VRC7, with its sound ports at $9010 and $9030
+    pub fn enable_vrc7(&mut self) {
+        self.vrc7 = Some(Vrc7Audio::new());
+        self.update_channels();
+    }
+
+    fn chips(&self) -> impl Iterator<Item = &dyn ExpansionAudio> + '_ {
+        let vrc6 = self.vrc6.iter().map(|vrc6| vrc6 as &dyn ExpansionAudio);
+        let vrc7 = self.vrc7.iter().map(|vrc7| vrc7 as &dyn ExpansionAudio);
+        vrc6.chain(vrc7)
+    }
+
+    fn update_channels(&mut self) {
+        self.channels = self
+            .chips()
+            .flat_map(|chip| chip.channels().iter().copied())
+            .collect();
+    }
+
+    /// The chip one of our channels belongs to, and its index there
+    fn chip(&self, mut index: usize) -> (&dyn ExpansionAudio, usize) {
+        for chip in self.chips() {
+            let count = chip.channels().len();
+            if index < count {
+                return (chip, index);
+            }
+            index -= count;
+        }
+        panic!("No expansion channel {}", index)
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn map(&self, address: u16) -> Option<usize> {
+        match address {
+            0x6000..=0x7FFF => Some(address as usize - 0x6000),
+            0x8000..=0xFFFF => {
+                let bank = self.banks[(address as usize - 0x8000) / BANK_SIZE] as usize;
+                let offset = (bank % self.bank_count) * BANK_SIZE;
+                Some(0x2000 + offset + (address as usize & (BANK_SIZE - 1)))
+            }
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Option<usize> {
+        match address {
+            0x5FF8..=0x5FFF => {
+                self.banks[address as usize - 0x5FF8] = data;
+                None
+            }
+            0x6000..=0x7FFF => self.map(address),
+            0x9010 | 0x9030 if self.vrc7.is_some() => {
+                if let Some(vrc7) = &mut self.vrc7 {
+                    vrc7.write(address, data);
+                }
+                None
+            }
+            0x9000..=0xB002 => {
+                if let Some(vrc6) = &mut self.vrc6 {
+                    vrc6.write(address, data);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn map_chr(&self, address: u16) -> usize {
+        address as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn clock(&mut self) {
+        if let Some(vrc6) = &mut self.vrc6 {
+            vrc6.clock();
+        }
+        if let Some(vrc7) = &mut self.vrc7 {
+            vrc7.clock();
+        }
+    }
+
+    fn audio(&self) -> Option<&dyn ExpansionAudio> {
+        match self.channels.is_empty() {
+            true => None,
+            false => Some(self),
+        }
+    }
+}
+
+/// Rips can use more than one chip, so the mapper mixes them all
+impl ExpansionAudio for NsfMapper {
+    fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    fn level(&self, index: usize) -> f32 {
+        let (chip, index) = self.chip(index);
+        chip.level(index)
+    }
+
+    fn weight(&self, index: usize) -> f32 {
+        let (chip, index) = self.chip(index);
+        chip.weight(index)
+    }
+
+    fn frequency(&self, index: usize, clock_rate: f64) -> Option<f64> {
+        let (chip, index) = self.chip(index);
+        chip.frequency(index, clock_rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn switches_banks() {
+        let mut mapper = NsfMapper::new([0, 1, 2, 3, 4, 5, 6, 7], 3);
+
+        assert_eq!(mapper.map(0x8123), Some(0x2123));
+        assert_eq!(mapper.map(0x9123), Some(0x3123));
+        // Bank numbers wrap around the image
+        assert_eq!(mapper.map(0xB000), Some(0x2000));
+
+        assert_eq!(mapper.write(0x5FF8, 2), None);
+        assert_eq!(mapper.map(0x8123), Some(0x4123));
+    }
+
+    #[test]
+    fn only_ram_takes_writes() {
+        let mut mapper = NsfMapper::new([0; 8], 1);
+
+        assert_eq!(mapper.write(0x6001, 0xAA), Some(0x0001));
+        assert_eq!(mapper.write(0x8000, 0xAA), None);
+        assert_eq!(mapper.write(0x5000, 0xAA), None);
+        assert_eq!(mapper.map(0x5000), None);
+    }
+
+    #[test]
+    fn routes_vrc6_registers() {
+        let mut mapper = NsfMapper::new([0; 8], 1);
+        assert!(mapper.audio().is_none());
+
+        mapper.enable_vrc6();
+        assert_eq!(mapper.write(0x9000, 0x8F), None);
+        mapper.write(0x9002, 0x80);
+        mapper.clock();
+        assert_eq!(mapper.audio().unwrap().level(0), 1.0);
+    }
+
+    #[test]
+    fn mixes_vrc6_and_vrc7() {
+        let mut mapper = NsfMapper::new([0; 8], 1);
+        mapper.enable_vrc6();
+        mapper.enable_vrc7();
+
+        // Organ on the VRC7's last channel, which doesn't touch the VRC6
+        for &(register, data) in [(0x35, 0x80), (0x15, 0x22), (0x25, 0x1B)].iter() {
+            mapper.write(0x9010, register);
+            mapper.write(0x9030, data);
+        }
+        for _ in 0..100_000 {
+            mapper.clock();
+        }
+
+        let audio = mapper.audio().unwrap();
+        assert_eq!(audio.channels().len(), 9);
+        assert_eq!(audio.channels()[8], Channel::Vrc7Fm6);
+        assert_eq!(audio.level(0), 0.0);
+        assert!(audio.frequency(8, 1_789_773.0).is_some());
+        assert!(audio.frequency(3, 1_789_773.0).is_none());
+    }
+}
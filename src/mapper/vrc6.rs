@@ -0,0 +1,190 @@
+use crate::apu::{ExpansionAudio, Vrc6Audio};
+use crate::cartridge::header::Header;
+
+use super::vrc_irq::VrcIrq;
+use super::{Mapper, Mirroring};
+
+/// The two boards differ only in which of A0 and A1 go to the chip
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Vrc6Wiring {
+    A, // Mapper 24
+    B, // Mapper 26, with A0 and A1 swapped
+}
+
+/// Konami's VRC6 (mappers 24 and 26): a 16 KiB and an 8 KiB PRG bank ahead
+/// of a fixed last 8 KiB, eight 1 KiB CHR banks, an IRQ counter, and three
+/// extra sound channels.
+pub struct Vrc6Mapper {
+    wiring: Vrc6Wiring,
+    prg_size: usize,
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    ram_enabled: bool,
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6Mapper {
+    pub fn new(header: &Header, wiring: Vrc6Wiring) -> Self {
+        Vrc6Mapper {
+            wiring,
+            prg_size: header.prg_rom_pages * 0x4000,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            ram_enabled: false,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    /// The register address as VRC6a sees it
+    fn register(&self, address: u16) -> u16 {
+        match self.wiring {
+            Vrc6Wiring::A => address & 0xF003,
+            Vrc6Wiring::B => (address & 0xF000) | ((address & 0x01) << 1) | ((address & 0x02) >> 1),
+        }
+    }
+
+    fn prg_offset(&self, bank: usize, bank_size: usize, address: u16) -> usize {
+        let offset = (bank * bank_size) % self.prg_size.max(bank_size);
+        0x2000 + offset + (address as usize & (bank_size - 1))
+    }
+}
+
+impl Mapper for Vrc6Mapper {
+    fn map(&self, address: u16) -> Option<usize> {
+        match address {
+            0x6000..=0x7FFF if self.ram_enabled => Some(address as usize - 0x6000),
+            0x8000..=0xBFFF => Some(self.prg_offset(self.prg_16k as usize, 0x4000, address)),
+            0xC000..=0xDFFF => Some(self.prg_offset(self.prg_8k as usize, 0x2000, address)),
+            0xE000..=0xFFFF => {
+                let last = self.prg_size / 0x2000 - 1;
+                Some(self.prg_offset(last, 0x2000, address))
+            }
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Option<usize> {
+        if address < 0x8000 {
+            return self.map(address);
+        }
+
+        let register = self.register(address);
+        match register {
+            0x8000..=0x8003 => self.prg_16k = data & 0x0F,
+            0x9000..=0xB002 => self.audio.write(register, data),
+            0xB003 => {
+                self.ram_enabled = data & 0x80 != 0;
+                self.mirroring = match (data >> 2) & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0xC000..=0xC003 => self.prg_8k = data & 0x1F,
+            0xD000..=0xD003 => self.chr_banks[(register & 0x03) as usize] = data,
+            0xE000..=0xE003 => self.chr_banks[4 + (register & 0x03) as usize] = data,
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+
+        None
+    }
+
+    fn map_chr(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address as usize >> 10) & 0x07] as usize;
+        bank * 0x400 + (address as usize & 0x3FF)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio(&self) -> Option<&dyn ExpansionAudio> {
+        Some(&self.audio)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn mapper(wiring: Vrc6Wiring) -> Vrc6Mapper {
+        // 256 KiB of PRG-ROM
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 16, 16];
+        data.resize(16, 0);
+        let header = Header::new(&mut Cursor::new(data)).unwrap();
+
+        Vrc6Mapper::new(&header, wiring)
+    }
+
+    #[test]
+    fn switches_prg_and_chr_banks() {
+        let mut mapper = mapper(Vrc6Wiring::A);
+
+        mapper.write(0x8000, 3);
+        mapper.write(0xC000, 5);
+        assert_eq!(mapper.map(0x8123), Some(0x2000 + 3 * 0x4000 + 0x123));
+        assert_eq!(mapper.map(0xC123), Some(0x2000 + 5 * 0x2000 + 0x123));
+        assert_eq!(mapper.map(0xE123), Some(0x2000 + 31 * 0x2000 + 0x123));
+
+        mapper.write(0xE002, 0x42);
+        assert_eq!(mapper.map_chr(0x1923), 0x42 * 0x400 + 0x123);
+    }
+
+    #[test]
+    fn ram_and_mirroring_follow_b003() {
+        let mut mapper = mapper(Vrc6Wiring::A);
+
+        assert_eq!(mapper.map(0x6000), None);
+        mapper.write(0xB003, 0x84);
+        assert_eq!(mapper.write(0x6001, 0xAA), Some(0x0001));
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn vrc6b_swaps_the_low_address_lines() {
+        let mut mapper = mapper(Vrc6Wiring::B);
+
+        mapper.write(0xD001, 7); // CHR bank 2 on VRC6a
+        assert_eq!(mapper.map_chr(0x0800), 7 * 0x400);
+
+        mapper.write(0xB003, 0x80); // $B003 either way
+        mapper.write(0xF000, 0xFF);
+        mapper.write(0xF002, 0x06); // Control, counting cycles
+        mapper.clock();
+        assert!(mapper.irq());
+    }
+
+    #[test]
+    fn plays_sound() {
+        let mut mapper = mapper(Vrc6Wiring::A);
+
+        mapper.write(0x9000, 0x8F);
+        mapper.write(0x9002, 0x80);
+        mapper.clock();
+
+        let audio = mapper.audio().unwrap();
+        assert_eq!(audio.channels().len(), 3);
+        assert_eq!(audio.level(0), 1.0);
+    }
+}
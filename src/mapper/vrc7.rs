@@ -0,0 +1,201 @@
+use crate::apu::{ExpansionAudio, Vrc7Audio};
+use crate::cartridge::header::Header;
+
+use super::vrc_irq::VrcIrq;
+use super::{Mapper, Mirroring};
+
+/// Konami's VRC7 (mapper 85): three switchable 8 KiB PRG banks ahead of a
+/// fixed last one, eight 1 KiB CHR banks, the VRC IRQ counter, and six FM
+/// sound channels. Boards pick each second register with A4 (VRC7a) or A3
+/// (VRC7b), and either works here.
+pub struct Vrc7Mapper {
+    prg_size: usize,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    ram_enabled: bool,
+    silenced: bool, // Sound held in reset
+    irq: VrcIrq,
+    audio: Vrc7Audio,
+}
+
+impl Vrc7Mapper {
+    pub fn new(header: &Header) -> Self {
+        Vrc7Mapper {
+            prg_size: header.prg_rom_pages * 0x4000,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            ram_enabled: false,
+            silenced: false,
+            irq: VrcIrq::new(),
+            audio: Vrc7Audio::new(),
+        }
+    }
+
+    /// The register address as VRC7a sees it. A5 only tells the sound ports
+    /// apart.
+    fn register(address: u16) -> u16 {
+        let second = match address & 0x18 {
+            0 => 0,
+            _ => 0x10,
+        };
+        (address & 0xF000) | second | (address & 0x20)
+    }
+
+    fn prg_offset(&self, bank: usize, address: u16) -> usize {
+        let offset = (bank * 0x2000) % self.prg_size.max(0x2000);
+        0x2000 + offset + (address as usize & 0x1FFF)
+    }
+}
+
+impl Mapper for Vrc7Mapper {
+    fn map(&self, address: u16) -> Option<usize> {
+        match address {
+            0x6000..=0x7FFF if self.ram_enabled => Some(address as usize - 0x6000),
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[(address as usize - 0x8000) / 0x2000];
+                Some(self.prg_offset(bank as usize, address))
+            }
+            0xE000..=0xFFFF => Some(self.prg_offset(self.prg_size / 0x2000 - 1, address)),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Option<usize> {
+        if address < 0x8000 {
+            return self.map(address);
+        }
+
+        let register = Vrc7Mapper::register(address);
+        match register {
+            0x8000 => self.prg_banks[0] = data & 0x3F,
+            0x8010 => self.prg_banks[1] = data & 0x3F,
+            0x9000 => self.prg_banks[2] = data & 0x3F,
+            0x9010 | 0x9030 => self.audio.write(register, data),
+            0xA000..=0xD010 if register & 0x20 == 0 => {
+                let bank =
+                    (register as usize - 0xA000) / 0x1000 * 2 + (register as usize & 0x10) / 0x10;
+                self.chr_banks[bank] = data;
+            }
+            0xE000 => {
+                self.ram_enabled = data & 0x80 != 0;
+                self.silenced = data & 0x40 != 0;
+                if self.silenced {
+                    self.audio.reset();
+                }
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0xE010 => self.irq.write_latch(data),
+            0xF000 => self.irq.write_control(data),
+            0xF010 => self.irq.acknowledge(),
+            _ => {}
+        }
+
+        None
+    }
+
+    fn map_chr(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address as usize >> 10) & 0x07] as usize;
+        bank * 0x400 + (address as usize & 0x3FF)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        if !self.silenced {
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio(&self) -> Option<&dyn ExpansionAudio> {
+        Some(&self.audio)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn mapper() -> Vrc7Mapper {
+        // 512 KiB of PRG-ROM and CHR-RAM
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 32, 0];
+        data.resize(16, 0);
+        let header = Header::new(&mut Cursor::new(data)).unwrap();
+
+        Vrc7Mapper::new(&header)
+    }
+
+    #[test]
+    fn switches_banks_on_either_wiring() {
+        let mut mapper = mapper();
+
+        mapper.write(0x8000, 3);
+        mapper.write(0x8010, 4); // VRC7a
+        mapper.write(0x9000, 5);
+        assert_eq!(mapper.map(0x8123), Some(0x2000 + 3 * 0x2000 + 0x123));
+        assert_eq!(mapper.map(0xA123), Some(0x2000 + 4 * 0x2000 + 0x123));
+        assert_eq!(mapper.map(0xC123), Some(0x2000 + 5 * 0x2000 + 0x123));
+        assert_eq!(mapper.map(0xE123), Some(0x2000 + 63 * 0x2000 + 0x123));
+
+        mapper.write(0x8008, 6); // VRC7b
+        assert_eq!(mapper.map(0xA123), Some(0x2000 + 6 * 0x2000 + 0x123));
+
+        mapper.write(0xA000, 0x10);
+        mapper.write(0xD008, 0x42);
+        assert_eq!(mapper.map_chr(0x0123), 0x10 * 0x400 + 0x123);
+        assert_eq!(mapper.map_chr(0x1D23), 0x42 * 0x400 + 0x123);
+    }
+
+    #[test]
+    fn control_register_sets_ram_mirroring_and_irq() {
+        let mut mapper = mapper();
+
+        assert_eq!(mapper.write(0x6000, 1), None);
+        mapper.write(0xE000, 0x81);
+        assert_eq!(mapper.write(0x6000, 1), Some(0));
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        mapper.write(0xE010, 0xFF);
+        mapper.write(0xF000, 0x06);
+        mapper.clock();
+        assert!(mapper.irq());
+        mapper.write(0xF010, 0);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn sound_can_be_held_in_reset() {
+        let mut mapper = mapper();
+        let audio_level = |mapper: &mut Vrc7Mapper| {
+            for _ in 0..100_000 {
+                mapper.clock();
+            }
+            mapper.audio().unwrap().level(0)
+        };
+
+        // Organ on channel 1, keyed on at A4
+        for &(register, data) in [(0x30, 0x80), (0x10, 0x22), (0x20, 0x19)].iter() {
+            mapper.write(0x9010, register);
+            mapper.write(0x9030, data);
+        }
+        assert_ne!(audio_level(&mut mapper), 0.5);
+
+        mapper.write(0xE000, 0x40);
+        assert_eq!(audio_level(&mut mapper), 0.5);
+    }
+}
@@ -0,0 +1,123 @@
+// CPU cycles per scanline, in thirds of a cycle
+const SCANLINE: i16 = 341;
+
+/// The IRQ counter Konami's VRC mappers share. It counts up from a latched
+/// value to $FF, either every CPU cycle or every scanline, which it times
+/// with a prescaler rather than by watching the PPU.
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_on_acknowledge: bool,
+    cycle_mode: bool,
+    pub pending: bool,
+}
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: SCANLINE,
+            enabled: false,
+            enable_on_acknowledge: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    /// Takes .....MEA: cycle mode, enable, and enable again on acknowledge
+    pub fn write_control(&mut self, data: u8) {
+        self.enable_on_acknowledge = data & 0x01 != 0;
+        self.enabled = data & 0x02 != 0;
+        self.cycle_mode = data & 0x04 != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = SCANLINE;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_on_acknowledge;
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += SCANLINE;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_scanlines() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xFE);
+        irq.write_control(0x02);
+
+        // Two scanlines of 113 2/3 cycles
+        for _ in 0..227 {
+            irq.clock();
+        }
+        assert!(!irq.pending);
+        irq.clock();
+        assert!(irq.pending);
+
+        irq.acknowledge();
+        assert!(!irq.pending);
+        for _ in 0..1000 {
+            irq.clock();
+        }
+        assert!(!irq.pending);
+    }
+
+    #[test]
+    fn counts_cycles_and_reloads() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xFD);
+        irq.write_control(0x07);
+
+        for _ in 0..3 {
+            irq.clock();
+        }
+        assert!(irq.pending);
+
+        // Enabled again on acknowledge, counting from the latch
+        irq.acknowledge();
+        for _ in 0..3 {
+            irq.clock();
+        }
+        assert!(irq.pending);
+    }
+}
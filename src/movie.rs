@@ -0,0 +1,75 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+};
+
+// Button order of an FM2 input field, from bit 7 down to bit 0 of the joypad
+const BUTTONS: &str = "RLDUTSBA";
+
+/// Joypad input recorded one frame at a time, read from FCEUX's FM2 text
+/// format. Only the first controller is used.
+pub struct Movie {
+    frames: Vec<u8>,
+}
+
+impl Movie {
+    pub fn parse(text: &str) -> io::Result<Movie> {
+        let mut frames = Vec::new();
+
+        // Header lines are "key value" pairs, input lines look like
+        // |commands|RLDUTSBA|port 1|expansion|
+        for line in text.lines().filter(|line| line.starts_with('|')) {
+            let port = line.split('|').nth(2).ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidData, format!("Bad input line {}", line))
+            })?;
+
+            let buttons = port
+                .chars()
+                .take(BUTTONS.len())
+                .enumerate()
+                .filter(|&(_, c)| c != '.' && c != ' ')
+                .fold(0, |buttons, (i, _)| buttons | 0x80 >> i);
+
+            frames.push(buttons);
+        }
+
+        Ok(Movie { frames })
+    }
+
+    pub fn from_file(filename: &str) -> io::Result<Movie> {
+        Movie::parse(&fs::read_to_string(filename)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Joypad state for a frame, with no buttons held once the movie ends
+    pub fn buttons(&self, frame: usize) -> u8 {
+        self.frames.get(frame).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_fm2_input() {
+        let movie = Movie::parse(
+            "version 3\nromFilename game\n|0|........|||\n|0|R..UT..A|||\n|0|.L....B.|||\n",
+        )
+        .unwrap();
+
+        assert_eq!(movie.len(), 3);
+        assert_eq!(movie.buttons(0), 0);
+        assert_eq!(movie.buttons(1), 0b1001_1001);
+        assert_eq!(movie.buttons(2), 0b0100_0010);
+        assert_eq!(movie.buttons(3), 0);
+    }
+
+    #[test]
+    fn rejects_truncated_lines() {
+        assert!(Movie::parse("|0").is_err());
+    }
+}
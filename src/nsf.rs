@@ -0,0 +1,261 @@
+mod nsfe;
+mod player;
+
+use std::{
+    fmt, fs,
+    io::{self, Cursor, ErrorKind, Read},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::region::Region;
+
+pub use player::NsfPlayer;
+
+const NSF_MAGIC: &[u8; 5] = b"NESM\x1A";
+const NSFE_MAGIC: &[u8; 4] = b"NSFE";
+const NSF_HEADER_SIZE: usize = 0x80;
+
+// Microseconds between PLAY calls when a rip doesn't say
+const DEFAULT_NTSC_SPEED: u16 = 16_639;
+const DEFAULT_PAL_SPEED: u16 = 19_997;
+
+/// Sound chips a rip can use on top of the 2A03, by their bit in the header
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExpansionChip {
+    Vrc6,
+    Vrc7,
+    Fds,
+    Mmc5,
+    Namco163,
+    Sunsoft5B,
+}
+
+impl ExpansionChip {
+    pub const ALL: [ExpansionChip; 6] = [
+        ExpansionChip::Vrc6,
+        ExpansionChip::Vrc7,
+        ExpansionChip::Fds,
+        ExpansionChip::Mmc5,
+        ExpansionChip::Namco163,
+        ExpansionChip::Sunsoft5B,
+    ];
+
+    /// Whether the chip's sound is emulated, rather than left silent
+    pub fn emulated(&self) -> bool {
+        matches!(self, ExpansionChip::Vrc6 | ExpansionChip::Vrc7)
+    }
+}
+
+impl fmt::Display for ExpansionChip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExpansionChip::Vrc6 => "VRC6",
+            ExpansionChip::Vrc7 => "VRC7",
+            ExpansionChip::Fds => "FDS",
+            ExpansionChip::Mmc5 => "MMC5",
+            ExpansionChip::Namco163 => "Namco 163",
+            ExpansionChip::Sunsoft5B => "Sunsoft 5B",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What NSFe metadata says about a track. Lengths are in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackInfo {
+    pub name: Option<String>,
+    pub length: Option<u32>,
+    pub fade: Option<u32>,
+}
+
+/// A music rip: the game's sound driver and data, with the addresses to call
+/// to start a song and to play it frame by frame. Loaded from either an NSF
+/// or the chunked NSFe format, which adds track names, lengths and a
+/// playlist.
+#[derive(Debug)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub songs: usize,
+    pub starting_song: usize, // From 0
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub ntsc_speed: u16, // Microseconds between PLAY calls
+    pub pal_speed: u16,
+    pub banks: Option<[u8; 8]>, // Initial banks, for bankswitched rips
+    pub timing: u8,             // Bit 0 set for PAL, bit 1 for either
+    pub expansion: u8,          // Bits for the `ExpansionChip`s used
+    pub data: Vec<u8>,
+    pub tracks: Vec<TrackInfo>,
+    pub playlist: Vec<usize>, // Track order, every track by default
+}
+
+impl Nsf {
+    pub fn load(filename: &str) -> io::Result<Nsf> {
+        Nsf::parse(&fs::read(filename)?)
+    }
+
+    /// Reads either format, telling them apart by their magic numbers
+    pub fn parse(data: &[u8]) -> io::Result<Nsf> {
+        let mut nsf = if data.starts_with(NSF_MAGIC) {
+            Nsf::parse_nsf(data)?
+        } else if data.starts_with(NSFE_MAGIC) {
+            nsfe::parse(&data[NSFE_MAGIC.len()..])?
+        } else {
+            return Err(invalid("Not an NSF or NSFe file"));
+        };
+
+        if nsf.songs == 0 {
+            return Err(invalid("No songs"));
+        }
+        if nsf.load_address < 0x8000 {
+            return Err(invalid("Load address below $8000"));
+        }
+        if nsf.ntsc_speed == 0 {
+            nsf.ntsc_speed = DEFAULT_NTSC_SPEED;
+        }
+        if nsf.pal_speed == 0 {
+            nsf.pal_speed = DEFAULT_PAL_SPEED;
+        }
+        nsf.starting_song = nsf.starting_song.min(nsf.songs - 1);
+        nsf.tracks.resize(nsf.songs, TrackInfo::default());
+        let songs = nsf.songs;
+        nsf.playlist.retain(|&track| track < songs);
+        if nsf.playlist.is_empty() {
+            nsf.playlist = (0..nsf.songs).collect();
+        }
+
+        Ok(nsf)
+    }
+
+    fn parse_nsf(data: &[u8]) -> io::Result<Nsf> {
+        if data.len() < NSF_HEADER_SIZE {
+            return Err(invalid("Truncated NSF header"));
+        }
+        let mut header = Cursor::new(&data[NSF_MAGIC.len() + 1..NSF_HEADER_SIZE]);
+
+        let songs = header.read_u8()? as usize;
+        let starting_song = (header.read_u8()? as usize).saturating_sub(1);
+        let load_address = header.read_u16::<LittleEndian>()?;
+        let init_address = header.read_u16::<LittleEndian>()?;
+        let play_address = header.read_u16::<LittleEndian>()?;
+        let title = read_fixed_string(&mut header)?;
+        let artist = read_fixed_string(&mut header)?;
+        let copyright = read_fixed_string(&mut header)?;
+        let ntsc_speed = header.read_u16::<LittleEndian>()?;
+        let mut banks = [0; 8];
+        header.read_exact(&mut banks)?;
+        let pal_speed = header.read_u16::<LittleEndian>()?;
+        let timing = header.read_u8()?;
+        let expansion = header.read_u8()?;
+
+        Ok(Nsf {
+            title,
+            artist,
+            copyright,
+            songs,
+            starting_song,
+            load_address,
+            init_address,
+            play_address,
+            ntsc_speed,
+            pal_speed,
+            // Any non-zero bank turns on bankswitching
+            banks: Some(banks).filter(|banks| banks.iter().any(|&bank| bank != 0)),
+            timing,
+            expansion,
+            data: data[NSF_HEADER_SIZE..].to_vec(),
+            tracks: Vec::new(),
+            playlist: Vec::new(),
+        })
+    }
+
+    /// The region the rip was made for, preferring NTSC when it plays on both
+    pub fn region(&self) -> Region {
+        match self.timing & 0b11 {
+            0b01 => Region::Pal,
+            _ => Region::Ntsc,
+        }
+    }
+
+    pub fn expansion_chips(&self) -> Vec<ExpansionChip> {
+        ExpansionChip::ALL
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| self.expansion & (1 << bit) != 0)
+            .map(|(_, &chip)| chip)
+            .collect()
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads one of the NSF header's 32 byte, null padded strings
+fn read_fixed_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut bytes = [0; 32];
+    reader.read_exact(&mut bytes)?;
+
+    let length = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// An NSF header for a rip of `data` loaded at $8000
+    pub fn nsf_file(init: u16, play: u16, banks: [u8; 8], data: &[u8]) -> Vec<u8> {
+        let mut file = NSF_MAGIC.to_vec();
+        file.extend_from_slice(&[1, 3, 2]); // Version, songs, starting song
+        file.extend_from_slice(&0x8000u16.to_le_bytes());
+        file.extend_from_slice(&init.to_le_bytes());
+        file.extend_from_slice(&play.to_le_bytes());
+        for text in ["Song", "Composer", "1986 Company"].iter() {
+            let mut field = text.as_bytes().to_vec();
+            field.resize(32, 0);
+            file.extend_from_slice(&field);
+        }
+        file.extend_from_slice(&16_639u16.to_le_bytes());
+        file.extend_from_slice(&banks);
+        file.extend_from_slice(&19_997u16.to_le_bytes());
+        file.extend_from_slice(&[0b10, 0b01, 0, 0, 0, 0]);
+        file.extend_from_slice(data);
+        file
+    }
+
+    #[test]
+    fn parses_nsf_headers() {
+        let nsf = Nsf::parse(&nsf_file(0x8000, 0x8003, [0; 8], &[0x60])).unwrap();
+
+        assert_eq!(nsf.title, "Song");
+        assert_eq!(nsf.artist, "Composer");
+        assert_eq!(nsf.copyright, "1986 Company");
+        assert_eq!(nsf.songs, 3);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!((nsf.init_address, nsf.play_address), (0x8000, 0x8003));
+        assert_eq!(nsf.banks, None);
+        assert_eq!(nsf.region(), Region::Ntsc);
+        assert_eq!(nsf.expansion_chips(), [ExpansionChip::Vrc6]);
+        assert_eq!(nsf.data, [0x60]);
+        assert_eq!(nsf.tracks.len(), 3);
+        assert_eq!(nsf.playlist, [0, 1, 2]);
+
+        let banked = Nsf::parse(&nsf_file(0x8000, 0x8003, [0, 1, 0, 0, 0, 0, 0, 0], &[])).unwrap();
+        assert_eq!(banked.banks, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(Nsf::parse(b"NES\x1A").is_err());
+        assert!(Nsf::parse(&NSF_MAGIC[..]).is_err());
+
+        let mut no_songs = nsf_file(0x8000, 0x8003, [0; 8], &[]);
+        no_songs[6] = 0;
+        assert!(Nsf::parse(&no_songs).is_err());
+    }
+}
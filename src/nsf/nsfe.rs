@@ -0,0 +1,214 @@
+use std::io::{self, Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::{invalid, Nsf, TrackInfo};
+
+/// Reads the chunks of an NSFe file, after its magic number. Each chunk is
+/// a length, a four letter ID and its data. Chunks whose ID starts with a
+/// capital are required to play the file, so unknown ones are an error, while
+/// unknown metadata chunks are skipped.
+pub fn parse(data: &[u8]) -> io::Result<Nsf> {
+    let mut nsf = Nsf {
+        title: String::new(),
+        artist: String::new(),
+        copyright: String::new(),
+        songs: 1,
+        starting_song: 0,
+        load_address: 0,
+        init_address: 0,
+        play_address: 0,
+        ntsc_speed: 0,
+        pal_speed: 0,
+        banks: None,
+        timing: 0,
+        expansion: 0,
+        data: Vec::new(),
+        tracks: Vec::new(),
+        playlist: Vec::new(),
+    };
+    let (mut has_info, mut has_data) = (false, false);
+
+    let mut stream = Cursor::new(data);
+    loop {
+        let length = stream
+            .read_u32::<LittleEndian>()
+            .map_err(|_| invalid("NSFe is missing its NEND chunk"))? as usize;
+        let mut id = [0; 4];
+        stream.read_exact(&mut id)?;
+
+        let start = stream.position() as usize;
+        let chunk = data
+            .get(start..start + length)
+            .ok_or_else(|| invalid("Truncated NSFe chunk"))?;
+        stream.set_position((start + length) as u64);
+        let mut reader = Cursor::new(chunk);
+
+        match &id {
+            b"INFO" => {
+                nsf.load_address = reader.read_u16::<LittleEndian>()?;
+                nsf.init_address = reader.read_u16::<LittleEndian>()?;
+                nsf.play_address = reader.read_u16::<LittleEndian>()?;
+                nsf.timing = reader.read_u8()?;
+                nsf.expansion = reader.read_u8()?;
+                nsf.songs = reader.read_u8().unwrap_or(1) as usize;
+                nsf.starting_song = reader.read_u8().unwrap_or(0) as usize;
+                has_info = true;
+            }
+            b"DATA" => {
+                nsf.data = chunk.to_vec();
+                has_data = true;
+            }
+            b"BANK" => {
+                let mut banks = [0; 8];
+                let count = chunk.len().min(8);
+                banks[..count].copy_from_slice(&chunk[..count]);
+                nsf.banks = Some(banks);
+            }
+            b"RATE" => {
+                nsf.ntsc_speed = reader.read_u16::<LittleEndian>()?;
+                nsf.pal_speed = reader.read_u16::<LittleEndian>().unwrap_or(0);
+            }
+            b"NEND" => break,
+            b"auth" => {
+                let mut strings = strings(chunk).into_iter();
+                nsf.title = strings.next().unwrap_or_default();
+                nsf.artist = strings.next().unwrap_or_default();
+                nsf.copyright = strings.next().unwrap_or_default();
+            }
+            b"tlbl" => {
+                for (track, name) in tracks(&mut nsf.tracks, strings(chunk)) {
+                    track.name = Some(name);
+                }
+            }
+            b"time" => {
+                for (track, length) in tracks(&mut nsf.tracks, milliseconds(chunk)) {
+                    track.length = length;
+                }
+            }
+            b"fade" => {
+                for (track, fade) in tracks(&mut nsf.tracks, milliseconds(chunk)) {
+                    track.fade = fade;
+                }
+            }
+            b"plst" => nsf.playlist = chunk.iter().map(|&track| track as usize).collect(),
+            _ if id[0].is_ascii_uppercase() => {
+                return Err(invalid(&format!(
+                    "Unsupported NSFe chunk {}",
+                    String::from_utf8_lossy(&id)
+                )))
+            }
+            _ => {}
+        }
+    }
+
+    if !has_info || !has_data {
+        return Err(invalid("NSFe is missing its INFO or DATA chunk"));
+    }
+    Ok(nsf)
+}
+
+/// Pairs values with the tracks they describe, adding tracks as needed
+fn tracks<T>(
+    tracks: &mut Vec<TrackInfo>,
+    values: Vec<T>,
+) -> impl Iterator<Item = (&mut TrackInfo, T)> {
+    if tracks.len() < values.len() {
+        tracks.resize(values.len(), TrackInfo::default());
+    }
+    tracks.iter_mut().zip(values)
+}
+
+/// Splits null terminated strings
+fn strings(chunk: &[u8]) -> Vec<String> {
+    let chunk = chunk.strip_suffix(&[0]).unwrap_or(chunk);
+    chunk
+        .split(|&b| b == 0)
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+        .collect()
+}
+
+/// Reads signed 32 bit times, where negative ones mean the default
+fn milliseconds(chunk: &[u8]) -> Vec<Option<u32>> {
+    chunk
+        .chunks_exact(4)
+        .map(|bytes| {
+            let time = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            Some(time as u32).filter(|_| time >= 0)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Nsf;
+    use crate::region::Region;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    fn nsfe(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = b"NSFE".to_vec();
+        for data in chunks {
+            file.extend_from_slice(data);
+        }
+        file.extend_from_slice(&chunk(b"NEND", &[]));
+        file
+    }
+
+    fn info() -> Vec<u8> {
+        // Load $8000, init $8000, play $8003, PAL, no expansion, 3 songs
+        chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 1, 0, 3, 2])
+    }
+
+    #[test]
+    fn parses_chunks() {
+        let mut time = Vec::new();
+        for ms in [90_000i32, -1].iter() {
+            time.extend_from_slice(&ms.to_le_bytes());
+        }
+        let file = nsfe(&[
+            info(),
+            chunk(b"DATA", &[0x60]),
+            chunk(b"BANK", &[0, 1]),
+            chunk(b"auth", b"Game\0Composer\0Company\0Ripper\0"),
+            chunk(b"tlbl", b"Title\0Ending\0"),
+            chunk(b"time", &time),
+            chunk(b"fade", &5_000i32.to_le_bytes()),
+            chunk(b"plst", &[2, 0]),
+            chunk(b"xtra", &[1, 2, 3]),
+        ]);
+        let nsf = Nsf::parse(&file).unwrap();
+
+        assert_eq!((nsf.load_address, nsf.play_address), (0x8000, 0x8003));
+        assert_eq!(nsf.region(), Region::Pal);
+        assert_eq!((nsf.songs, nsf.starting_song), (3, 2));
+        assert_eq!(nsf.data, [0x60]);
+        assert_eq!(nsf.banks, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.title, "Game");
+        assert_eq!(nsf.copyright, "Company");
+        assert_eq!(nsf.tracks[1].name.as_deref(), Some("Ending"));
+        assert_eq!(nsf.tracks[0].length, Some(90_000));
+        assert_eq!(nsf.tracks[1].length, None);
+        assert_eq!(nsf.tracks[0].fade, Some(5_000));
+        assert_eq!(nsf.tracks[2], Default::default());
+        assert_eq!(nsf.playlist, [2, 0]);
+        // Defaults for the missing RATE chunk
+        assert_eq!((nsf.ntsc_speed, nsf.pal_speed), (16_639, 19_997));
+    }
+
+    #[test]
+    fn requires_known_chunks() {
+        assert!(Nsf::parse(&nsfe(&[info()])).is_err());
+        assert!(Nsf::parse(&nsfe(&[info(), chunk(b"DATA", &[]), chunk(b"VRC7", &[])])).is_err());
+
+        // Without NEND
+        let mut file = nsfe(&[info(), chunk(b"DATA", &[])]);
+        file.truncate(file.len() - 8);
+        assert!(Nsf::parse(&file).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use super::{ExpansionChip, Nsf};
+use crate::apu::Apu;
+use crate::cartridge::{header::Header, rom::NESRom};
+use crate::hardware::cpu::Cpu;
+use crate::mapper::{nsf::NsfMapper, Mirroring};
+use crate::region::Region;
+
+/// Where INIT and PLAY return to. Nothing is ever run here: a call is over
+/// as soon as the program counter reaches it.
+const RETURN_ADDRESS: u16 = 0x4100;
+// Cycles a call may take before it's abandoned, as some drivers never return
+// from INIT. About a second.
+const CALL_LIMIT: u64 = 1_800_000;
+
+pub const DEFAULT_TRACK_LENGTH_MS: u32 = 150_000;
+pub const DEFAULT_FADE_MS: u32 = 5_000;
+
+/// Plays the songs in an NSF by running its driver on the emulated CPU and
+/// APU. The rip's data sits in a cartridge with the NSF memory map, INIT is
+/// called to start a song and PLAY at the rate the rip asks for.
+pub struct NsfPlayer {
+    nsf: Nsf,
+    cpu: Cpu,
+    region: Region,
+    track: usize,
+    play_period: f64, // CPU cycles between PLAY calls
+    next_play: f64,   // Cycle count of the next PLAY call
+    samples: u64,     // Played since the track started
+    // Used when the NSFe metadata gives no length or fade
+    pub default_length: u32,
+    pub default_fade: u32,
+}
+
+impl NsfPlayer {
+    /// Sets up the rip to play in the given region, or the one it was made
+    /// for. Call `start_track` to begin playing.
+    pub fn new(nsf: Nsf, region: Option<Region>, sample_rate: u32) -> Self {
+        let region = region.unwrap_or_else(|| nsf.region());
+
+        // Rips that don't bankswitch are loaded at their load address, which
+        // works out the same as being banked in from a padded image
+        let padding = match nsf.banks {
+            Some(_) => nsf.load_address as usize & 0xFFF,
+            None => nsf.load_address as usize - 0x8000,
+        };
+        let mut image = vec![0; padding];
+        image.extend_from_slice(&nsf.data);
+        image.resize((image.len() + 0xFFF) & !0xFFF, 0);
+
+        let banks = nsf.banks.unwrap_or([0, 1, 2, 3, 4, 5, 6, 7]);
+        let header = Header {
+            prg_rom_pages: image.len() / 0x4000,
+            prg_ram_pages: 1,
+            chr_rom_pages: 0,
+            chr_ram_size: 0x2000,
+            mapper: 0,
+            has_trainer: false,
+            mirroring: Mirroring::Horizontal,
+            is_nes2: false,
+            region,
+        };
+        let mut mapper = NsfMapper::new(banks, image.len() / 0x1000);
+        for chip in nsf.expansion_chips() {
+            match chip {
+                ExpansionChip::Vrc6 => mapper.enable_vrc6(),
+                ExpansionChip::Vrc7 => mapper.enable_vrc7(),
+                _ => {}
+            }
+        }
+        let cartridge = NESRom::with_mapper(header, &image, Box::new(mapper));
+
+        let mut cpu = Cpu::new(Box::new(cartridge));
+        cpu.bus.set_region(region);
+        cpu.bus.apu.set_sample_rate(sample_rate);
+
+        let speed = match region {
+            Region::Ntsc => nsf.ntsc_speed,
+            Region::Pal | Region::Dendy => nsf.pal_speed,
+        };
+
+        NsfPlayer {
+            play_period: region.cpu_clock_rate() * speed as f64 / 1_000_000.0,
+            nsf,
+            cpu,
+            region,
+            track: 0,
+            next_play: 0.0,
+            samples: 0,
+            default_length: DEFAULT_TRACK_LENGTH_MS,
+            default_fade: DEFAULT_FADE_MS,
+        }
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.cpu.bus.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.cpu.bus.apu
+    }
+
+    /// PLAY calls per second
+    pub fn play_rate(&self) -> f64 {
+        self.region.cpu_clock_rate() / self.play_period
+    }
+
+    /// The track playing, from 0
+    pub fn track(&self) -> usize {
+        self.track
+    }
+
+    /// Resets the machine the way the NSF spec asks and calls INIT with the
+    /// track number in A and the region in X
+    pub fn start_track(&mut self, track: usize) {
+        self.track = track.min(self.nsf.songs - 1);
+
+        for address in (0..0x800).chain(0x6000..0x8000) {
+            self.cpu.bus.write(address, 0);
+        }
+        for address in 0x4000..0x4014 {
+            self.cpu.bus.write(address, 0);
+        }
+        self.cpu.bus.write(0x4015, 0x00);
+        self.cpu.bus.write(0x4015, 0x0F);
+        self.cpu.bus.write(0x4017, 0x40);
+        if let Some(banks) = self.nsf.banks {
+            for (i, &bank) in banks.iter().enumerate() {
+                self.cpu.bus.write(0x5FF8 + i as u16, bank);
+            }
+        }
+
+        self.cpu.a = self.track as u8;
+        self.cpu.x = (self.region != Region::Ntsc) as u8;
+        self.cpu.y = 0;
+        self.call(self.nsf.init_address);
+
+        // The reset is not part of the song
+        self.cpu.bus.apu.take_samples();
+        self.samples = 0;
+        self.next_play = self.cpu.bus.cycles as f64;
+    }
+
+    /// Calls PLAY and runs until the next call is due, returning the samples
+    /// produced in the meantime, faded out at the end of the track
+    pub fn play_frame(&mut self) -> Vec<f32> {
+        self.next_play += self.play_period;
+        self.call(self.nsf.play_address);
+        while (self.cpu.bus.cycles as f64) < self.next_play {
+            self.cpu.bus.tick();
+        }
+
+        let mut samples = self.cpu.bus.apu.take_samples();
+        for sample in samples.iter_mut() {
+            *sample *= self.gain(self.samples);
+            self.samples += 1;
+        }
+        samples
+    }
+
+    /// Seconds played of the current track
+    pub fn elapsed(&self) -> f64 {
+        self.samples as f64 / self.cpu.bus.apu.sample_rate() as f64
+    }
+
+    /// Whether the track has played for its length and faded out
+    pub fn finished(&self) -> bool {
+        let (length, fade) = self.length();
+        self.samples >= length + fade
+    }
+
+    /// The track's length and fade in samples
+    fn length(&self) -> (u64, u64) {
+        let info = &self.nsf.tracks[self.track];
+        let to_samples = |ms: u32| ms as u64 * self.cpu.bus.apu.sample_rate() as u64 / 1000;
+
+        (
+            to_samples(info.length.unwrap_or(self.default_length)),
+            to_samples(info.fade.unwrap_or(self.default_fade)),
+        )
+    }
+
+    /// Volume at a sample, falling linearly to nothing over the fade
+    fn gain(&self, sample: u64) -> f32 {
+        let (length, fade) = self.length();
+        match sample.checked_sub(length) {
+            None => 1.0,
+            Some(_) if fade == 0 => 0.0,
+            Some(faded) => 1.0 - (faded as f32 / fade as f32).min(1.0),
+        }
+    }
+
+    /// Runs a subroutine until it returns
+    fn call(&mut self, address: u16) {
+        self.cpu.sp = 0xFD;
+        self.cpu.push_stack_16(RETURN_ADDRESS - 1);
+        self.cpu.pc = address;
+
+        let start = self.cpu.bus.cycles;
+        while self.cpu.pc != RETURN_ADDRESS && self.cpu.bus.cycles - start < CALL_LIMIT {
+            self.cpu.execute_next_opcode();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nsf::test::nsf_file;
+
+    // INIT stores A and X in $00 and $02 and plays a pulse; PLAY counts its
+    // calls in $01
+    #[rustfmt::skip]
+    const DRIVER: [u8; 25] = [
+        0x85, 0x00,       // $8000 STA $00
+        0x86, 0x02,       //       STX $02
+        0xA9, 0x9F,       //       LDA #$9F
+        0x8D, 0x00, 0x40, //       STA $4000
+        0xA9, 0xFD,       //       LDA #$FD
+        0x8D, 0x02, 0x40, //       STA $4002
+        0xA9, 0x08,       //       LDA #$08
+        0x8D, 0x03, 0x40, //       STA $4003
+        0x60,             //       RTS
+        0xE6, 0x01,       // $8014 INC $01
+        0x60,             //       RTS
+        0x00, 0x00,
+    ];
+
+    fn player(region: Option<Region>) -> NsfPlayer {
+        let nsf = Nsf::parse(&nsf_file(0x8000, 0x8014, [0; 8], &DRIVER)).unwrap();
+        NsfPlayer::new(nsf, region, 44_100)
+    }
+
+    #[test]
+    fn calls_init_and_play() {
+        let mut player = player(None);
+        player.start_track(2);
+        assert_eq!(player.cpu.bus.peek(0x00), 2);
+        assert_eq!(player.cpu.bus.peek(0x02), 0);
+
+        let samples: usize = (0..60).map(|_| player.play_frame().len()).sum();
+        assert_eq!(player.cpu.bus.peek(0x01), 60);
+        // One PLAY call every 16639us
+        assert!((samples as f64 - 44_100.0 * 0.99834).abs() < 2.0);
+        assert!((player.elapsed() - 0.99834).abs() < 0.001);
+
+        // Starting another track resets RAM
+        player.start_track(0);
+        assert_eq!(player.cpu.bus.peek(0x01), 0);
+    }
+
+    #[test]
+    fn tells_init_the_region() {
+        let mut player = player(Some(Region::Pal));
+        player.start_track(0);
+
+        assert_eq!(player.cpu.bus.peek(0x02), 1);
+    }
+
+    #[test]
+    fn bankswitched_rips_start_in_their_banks() {
+        // Bank 1 holds the driver, bank 0 is empty and starts at $9000
+        let mut data = vec![0; 0x1000];
+        data.extend_from_slice(&DRIVER);
+        let file = nsf_file(0x8000, 0x8014, [1, 0, 0, 0, 0, 0, 0, 0], &data);
+        let mut player = NsfPlayer::new(Nsf::parse(&file).unwrap(), None, 44_100);
+        player.start_track(1);
+
+        assert_eq!(player.cpu.bus.peek(0x8000), 0x85);
+        assert_eq!(player.cpu.bus.peek(0x9000), 0x00);
+        assert_eq!(player.cpu.bus.peek(0x00), 1);
+    }
+
+    #[test]
+    fn fades_out_at_the_end() {
+        let mut player = player(None);
+        player.default_length = 1000;
+        player.default_fade = 500;
+        player.start_track(0);
+
+        assert_eq!(player.gain(0), 1.0);
+        assert_eq!(player.gain(44_100), 1.0);
+        assert_eq!(player.gain(44_100 + 11_025), 0.5);
+        assert_eq!(player.gain(88_200), 0.0);
+
+        let mut frames = 0;
+        while !player.finished() {
+            assert!(!player.play_frame().is_empty());
+            frames += 1;
+        }
+        assert_eq!(frames, 91);
+    }
+}
@@ -0,0 +1,413 @@
+use std::{env, str::FromStr};
+
+use crate::apu::{Channel, SAMPLE_RATE};
+use crate::audio::{SyncMode, DEFAULT_LATENCY_MS};
+use crate::region::Region;
+use crate::render::screenshot::ScreenshotMode;
+use crate::render::{
+    blend::{BlendMode, DEFAULT_DECAY},
+    ntsc::NtscSettings,
+    palette::PaletteSettings,
+    presentation::{AspectMode, Overscan},
+    scale::ScaleFilter,
+};
+
+const DEFAULT_ROM: &str = "priv/mario1.nes";
+const DEFAULT_FRAMES: u64 = 60;
+const DEFAULT_DISPLAY_CONFIG: &str = "display.cfg";
+
+/// Command line options
+pub struct Options {
+    pub rom: String,
+    pub region: Option<Region>,
+    pub palette: Option<String>, // Built-in palette name or .pal file
+    pub palette_settings: Option<PaletteSettings>, // Generate the palette
+    pub export_palette: Option<String>,
+    pub ntsc: Option<NtscSettings>,
+    pub filter: ScaleFilter,
+    pub hd_pack: Option<String>, // Directory with a hires.txt
+    pub blend: BlendMode,
+    pub blend_decay: f32,
+    pub display_config: String, // Where presentation settings persist
+    // Presentation overrides for this run, which aren't saved
+    pub aspect: Option<AspectMode>,
+    pub integer_scaling: Option<bool>,
+    pub overscan: Option<Overscan>,
+    pub fullscreen: Option<bool>,
+    pub debug: bool,                // Show the PPU debug window
+    pub dump_ppu: Option<String>,   // Headless PPU view dump directory
+    pub frames: u64,                // Frames to run in headless modes
+    pub screenshot: Option<String>, // Headless screenshot file
+    pub screenshot_dir: String,     // Also where GIF captures go
+    pub gif_frame_skip: u64,
+    pub screenshot_mode: ScreenshotMode,
+    pub record: Option<String>, // .avi, or .y4m with a .wav beside it
+    pub movie: Option<String>,  // FM2 input to play back
+    pub sample_rate: u32,       // Audio output rate, usually 44100 or 48000
+    pub audio_latency: u32,     // Milliseconds of audio to queue
+    pub sync: SyncMode,
+    // Mixer settings, applied before emulation starts
+    pub mute: Vec<Channel>,
+    pub solo: Option<Channel>,
+    pub volumes: Vec<(Channel, f32)>,
+    pub scope: bool, // Show the APU oscilloscope window
+    // NSF playback
+    pub track: Option<usize>,      // From 1, instead of the rip's first song
+    pub track_length: Option<u32>, // Seconds, for tracks without one
+    pub wav: Option<String>,       // Render the track here instead
+    pub headless: bool,            // Run for `frames` frames without a window
+}
+
+impl Options {
+    pub fn from_args() -> Result<Options, String> {
+        Options::parse(env::args().skip(1))
+    }
+
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options {
+            rom: DEFAULT_ROM.to_string(),
+            region: None,
+            palette: None,
+            palette_settings: None,
+            export_palette: None,
+            ntsc: None,
+            filter: ScaleFilter::None,
+            hd_pack: None,
+            blend: BlendMode::Off,
+            blend_decay: DEFAULT_DECAY,
+            display_config: DEFAULT_DISPLAY_CONFIG.to_string(),
+            aspect: None,
+            integer_scaling: None,
+            overscan: None,
+            fullscreen: None,
+            debug: false,
+            dump_ppu: None,
+            frames: DEFAULT_FRAMES,
+            screenshot: None,
+            screenshot_dir: ".".to_string(),
+            gif_frame_skip: 0,
+            screenshot_mode: ScreenshotMode::Filtered,
+            record: None,
+            movie: None,
+            sample_rate: SAMPLE_RATE,
+            audio_latency: DEFAULT_LATENCY_MS,
+            sync: SyncMode::Video,
+            mute: Vec::new(),
+            solo: None,
+            volumes: Vec::new(),
+            scope: false,
+            track: None,
+            track_length: None,
+            wav: None,
+            headless: false,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--region" => options.region = Some(value(&mut args, &arg)?.parse()?),
+                "--palette" => options.palette = Some(value(&mut args, &arg)?),
+                "--palette-hue" => generated(&mut options).hue = number(&mut args, &arg)?,
+                "--palette-saturation" => {
+                    generated(&mut options).saturation = number(&mut args, &arg)?
+                }
+                "--palette-contrast" => generated(&mut options).contrast = number(&mut args, &arg)?,
+                "--palette-brightness" => {
+                    generated(&mut options).brightness = number(&mut args, &arg)?
+                }
+                "--palette-gamma" => generated(&mut options).gamma = number(&mut args, &arg)?,
+                "--export-palette" => options.export_palette = Some(value(&mut args, &arg)?),
+                "--debug" => options.debug = true,
+                "--dump-ppu" => options.dump_ppu = Some(value(&mut args, &arg)?),
+                "--frames" => options.frames = number(&mut args, &arg)?,
+                "--gif-frame-skip" => options.gif_frame_skip = number(&mut args, &arg)?,
+                "--screenshot" => options.screenshot = Some(value(&mut args, &arg)?),
+                "--screenshot-dir" => options.screenshot_dir = value(&mut args, &arg)?,
+                "--screenshot-mode" => options.screenshot_mode = value(&mut args, &arg)?.parse()?,
+                "--record" => options.record = Some(value(&mut args, &arg)?),
+                "--movie" => options.movie = Some(value(&mut args, &arg)?),
+                "--sample-rate" => match number(&mut args, &arg)? {
+                    0 => return Err("Sample rate must be above 0".to_string()),
+                    rate => options.sample_rate = rate,
+                },
+                "--audio-latency" => options.audio_latency = number(&mut args, &arg)?,
+                "--sync" => options.sync = value(&mut args, &arg)?.parse()?,
+                "--mute" => options.mute.push(value(&mut args, &arg)?.parse()?),
+                "--solo" => options.solo = Some(value(&mut args, &arg)?.parse()?),
+                "--volume" => options
+                    .volumes
+                    .push(channel_volume(&value(&mut args, &arg)?)?),
+                "--scope" => options.scope = true,
+                "--track" => match number(&mut args, &arg)? {
+                    0 => return Err("Tracks are numbered from 1".to_string()),
+                    track => options.track = Some(track),
+                },
+                "--track-length" => match number::<_, u32>(&mut args, &arg)? {
+                    // The player times tracks in milliseconds
+                    seconds if seconds.checked_mul(1000).is_none() => {
+                        return Err(format!("Track length {} is too long", seconds))
+                    }
+                    seconds => options.track_length = Some(seconds),
+                },
+                "--wav" => options.wav = Some(value(&mut args, &arg)?),
+                "--headless" => options.headless = true,
+                "--ntsc" => {
+                    options.ntsc.get_or_insert_with(NtscSettings::default);
+                }
+                "--ntsc-sharpness" => ntsc(&mut options).sharpness = number(&mut args, &arg)?,
+                "--ntsc-hue" => ntsc(&mut options).hue = number(&mut args, &arg)?,
+                "--ntsc-saturation" => ntsc(&mut options).saturation = number(&mut args, &arg)?,
+                "--ntsc-merge-fields" => ntsc(&mut options).merge_fields = true,
+                "--filter" => options.filter = value(&mut args, &arg)?.parse()?,
+                "--hd-pack" => options.hd_pack = Some(value(&mut args, &arg)?),
+                "--blend" => options.blend = value(&mut args, &arg)?.parse()?,
+                "--blend-decay" => options.blend_decay = number(&mut args, &arg)?,
+                "--display-config" => options.display_config = value(&mut args, &arg)?,
+                "--aspect" => options.aspect = Some(value(&mut args, &arg)?.parse()?),
+                "--integer-scaling" => options.integer_scaling = Some(true),
+                "--no-integer-scaling" => options.integer_scaling = Some(false),
+                "--overscan" => options.overscan = Some(value(&mut args, &arg)?.parse()?),
+                "--fullscreen" => options.fullscreen = Some(true),
+                "--windowed" => options.fullscreen = Some(false),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => options.rom = arg,
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, name: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Missing value for {}", name))
+}
+
+fn number<I: Iterator<Item = String>, T: FromStr>(args: &mut I, name: &str) -> Result<T, String> {
+    let text = value(args, name)?;
+    text.parse()
+        .map_err(|_| format!("Invalid value {} for {}", text, name))
+}
+
+// Takes CHANNEL=LEVEL, where 1.0 is the normal volume
+fn channel_volume(text: &str) -> Result<(Channel, f32), String> {
+    let mut parts = text.splitn(2, '=');
+    let channel = parts.next().unwrap_or_default().parse()?;
+    let level = parts
+        .next()
+        .and_then(|level| level.parse::<f32>().ok())
+        .filter(|level| *level >= 0.0)
+        .ok_or_else(|| format!("Invalid volume {}", text))?;
+
+    Ok((channel, level))
+}
+
+// Any palette adjustment switches to the generated palette
+fn generated(options: &mut Options) -> &mut PaletteSettings {
+    options
+        .palette_settings
+        .get_or_insert_with(PaletteSettings::default)
+}
+
+// Any NTSC setting turns the filter on
+fn ntsc(options: &mut Options) -> &mut NtscSettings {
+    options.ntsc.get_or_insert_with(NtscSettings::default)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_rom_and_region() {
+        let options = parse(&["game.nes", "--region", "pal"]).unwrap();
+
+        assert_eq!(options.rom, "game.nes");
+        assert_eq!(options.region, Some(Region::Pal));
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert!(parse(&["--turbo"]).is_err());
+        assert!(parse(&["--region"]).is_err());
+    }
+
+    #[test]
+    fn ntsc_settings_enable_the_filter() {
+        assert_eq!(parse(&[]).unwrap().ntsc, None);
+
+        let options = parse(&["--ntsc-hue", "-15", "--ntsc-merge-fields"]).unwrap();
+        let settings = options.ntsc.unwrap();
+
+        assert_eq!(settings.hue, -15.0);
+        assert!(settings.merge_fields);
+        assert!(parse(&["--ntsc-sharpness", "soft"]).is_err());
+    }
+
+    #[test]
+    fn scale_filter() {
+        assert_eq!(parse(&[]).unwrap().filter, ScaleFilter::None);
+        assert_eq!(
+            parse(&["--filter", "xbr3x"]).unwrap().filter,
+            ScaleFilter::Xbr3x
+        );
+        assert!(parse(&["--filter", "blur"]).is_err());
+    }
+
+    #[test]
+    fn hd_pack() {
+        assert_eq!(parse(&[]).unwrap().hd_pack, None);
+        assert_eq!(
+            parse(&["--hd-pack", "packs/smb"]).unwrap().hd_pack,
+            Some("packs/smb".to_string())
+        );
+        assert!(parse(&["--hd-pack"]).is_err());
+    }
+
+    #[test]
+    fn frame_blending() {
+        assert_eq!(parse(&[]).unwrap().blend, BlendMode::Off);
+
+        let options = parse(&["--blend", "ghost", "--blend-decay", "0.75"]).unwrap();
+        assert_eq!(options.blend, BlendMode::Ghost);
+        assert_eq!(options.blend_decay, 0.75);
+        assert!(parse(&["--blend", "average"]).is_err());
+    }
+
+    #[test]
+    fn presentation_overrides() {
+        let options = parse(&["--aspect", "4:3", "--overscan", "8,8,8,8", "--fullscreen"]).unwrap();
+
+        assert_eq!(options.display_config, DEFAULT_DISPLAY_CONFIG);
+        assert_eq!(options.aspect, Some(AspectMode::Display));
+        assert_eq!(options.overscan.unwrap().visible_size(), (240, 224));
+        assert_eq!(options.fullscreen, Some(true));
+        assert_eq!(options.integer_scaling, None);
+        assert!(parse(&["--overscan", "8"]).is_err());
+
+        let options = parse(&["--no-integer-scaling", "--windowed"]).unwrap();
+        assert_eq!(options.integer_scaling, Some(false));
+        assert_eq!(options.fullscreen, Some(false));
+    }
+
+    #[test]
+    fn palette_adjustments_generate_the_palette() {
+        let options = parse(&["--palette-gamma", "2.2", "--export-palette", "out.pal"]).unwrap();
+
+        assert_eq!(options.palette_settings.unwrap().gamma, 2.2);
+        assert_eq!(options.export_palette, Some("out.pal".to_string()));
+    }
+
+    #[test]
+    fn headless_dump_runs_for_frames() {
+        assert_eq!(parse(&[]).unwrap().frames, DEFAULT_FRAMES);
+
+        let options = parse(&["--dump-ppu", "out", "--frames", "120"]).unwrap();
+
+        assert_eq!(options.dump_ppu, Some("out".to_string()));
+        assert_eq!(options.frames, 120);
+        assert!(parse(&["--frames", "-1"]).is_err());
+    }
+
+    #[test]
+    fn screenshot_options() {
+        let options = parse(&["--screenshot", "shot.png", "--screenshot-mode", "aspect"]).unwrap();
+
+        assert_eq!(options.screenshot, Some("shot.png".to_string()));
+        assert_eq!(options.screenshot_mode, ScreenshotMode::AspectCorrected);
+        assert!(parse(&["--screenshot-mode", "jpeg"]).is_err());
+        assert_eq!(parse(&["--gif-frame-skip", "1"]).unwrap().gif_frame_skip, 1);
+    }
+
+    #[test]
+    fn headless_recording_from_movie() {
+        let options = parse(&[
+            "--record",
+            "out.avi",
+            "--movie",
+            "run.fm2",
+            "--headless",
+            "--frames",
+            "600",
+        ])
+        .unwrap();
+
+        assert_eq!(options.record, Some("out.avi".to_string()));
+        assert_eq!(options.movie, Some("run.fm2".to_string()));
+        assert!(options.headless);
+        assert_eq!(options.frames, 600);
+    }
+
+    #[test]
+    fn sample_rate() {
+        assert_eq!(parse(&[]).unwrap().sample_rate, 44_100);
+        assert_eq!(
+            parse(&["--sample-rate", "48000"]).unwrap().sample_rate,
+            48_000
+        );
+        assert!(parse(&["--sample-rate", "0"]).is_err());
+    }
+
+    #[test]
+    fn audio_sync() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.sync, SyncMode::Video);
+        assert_eq!(options.audio_latency, DEFAULT_LATENCY_MS);
+
+        let options = parse(&["--sync", "audio", "--audio-latency", "100"]).unwrap();
+        assert_eq!(options.sync, SyncMode::Audio);
+        assert_eq!(options.audio_latency, 100);
+        let options = parse(&["--sync", "vsync"]).unwrap();
+        assert_eq!(options.sync, SyncMode::Video);
+        assert!(parse(&["--sync", "frame"]).is_err());
+    }
+
+    #[test]
+    fn channel_mixing() {
+        let options = parse(&[]).unwrap();
+        assert!(options.mute.is_empty() && options.volumes.is_empty());
+        assert_eq!(options.solo, None);
+        assert!(!options.scope);
+
+        let options = parse(&[
+            "--mute",
+            "noise",
+            "--mute",
+            "dmc",
+            "--solo",
+            "pulse2",
+            "--volume",
+            "triangle=1.5",
+            "--scope",
+        ])
+        .unwrap();
+        assert_eq!(options.mute, [Channel::Noise, Channel::Dmc]);
+        assert_eq!(options.solo, Some(Channel::Pulse2));
+        assert_eq!(options.volumes, [(Channel::Triangle, 1.5)]);
+        assert!(options.scope);
+
+        assert!(parse(&["--mute", "saw"]).is_err());
+        assert!(parse(&["--volume", "triangle"]).is_err());
+        assert!(parse(&["--volume", "triangle=-1"]).is_err());
+        assert!(parse(&["--volume", "bass=1"]).is_err());
+    }
+
+    #[test]
+    fn nsf_playback() {
+        let options = parse(&["music.nsfe", "--track", "3", "--wav", "out.wav"]).unwrap();
+        assert_eq!(options.track, Some(3));
+        assert_eq!(options.wav.as_deref(), Some("out.wav"));
+        assert_eq!(options.track_length, None);
+
+        assert_eq!(
+            parse(&["--track-length", "90"]).unwrap().track_length,
+            Some(90)
+        );
+        assert!(parse(&["--track", "0"]).is_err());
+        assert!(parse(&["--track-length", "1:30"]).is_err());
+        assert!(parse(&["--track-length", "4294968"]).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// How far apart, in Hz, a display's refresh rate and the console's frame rate
+// can be for vsync to pace emulation. SDL rounds refresh rates to whole Hz,
+// often down, so a 59.94Hz display reports 59.
+const VSYNC_TOLERANCE: f64 = 1.5;
+
+/// Whether vsync on a display refreshing at `refresh_rate` Hz runs emulation
+/// close enough to `frame_rate`. SDL reports 0 when it doesn't know.
+pub fn vsync_matches(refresh_rate: i32, frame_rate: f64) -> bool {
+    refresh_rate > 0 && (refresh_rate as f64 - frame_rate).abs() <= VSYNC_TOLERANCE
+}
+
+/// Throttles emulation to the console's native frame rate, independent of
+/// the host display's refresh rate. Used when vsync would run the game at
+/// the wrong speed, such as PAL on a 60Hz display.
+pub struct FramePacer {
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl FramePacer {
+    pub fn new(frame_rate: f64) -> Self {
+        FramePacer {
+            frame_duration: Duration::from_secs_f64(1.0 / frame_rate),
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// Sleeps until the next frame is due
+    pub fn wait(&mut self) {
+        self.next_frame += self.frame_duration;
+
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > self.frame_duration * 4 {
+            // Too far behind to catch up, so start pacing from here
+            self.next_frame = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vsync_only_paces_matching_displays() {
+        assert!(vsync_matches(60, 60.0988));
+        assert!(vsync_matches(59, 60.0988));
+        assert!(vsync_matches(50, 50.007));
+        assert!(!vsync_matches(60, 50.007));
+        assert!(!vsync_matches(144, 60.0988));
+        assert!(!vsync_matches(0, 60.0988));
+    }
+}
@@ -1,7 +1,8 @@
 mod address_register;
 mod control_register;
 mod mask_register;
-mod scroll_register;
+mod render;
+mod sprite_evaluation;
 mod status_register;
 
 use std::fmt::Debug;
@@ -9,35 +10,54 @@ use std::fmt::Debug;
 use address_register::AddressRegister;
 use control_register::ControlRegister;
 use mask_register::MaskRegister;
-use scroll_register::ScrollRegister;
+use render::RenderState;
+pub use render::{attribute_location, pattern_pixel};
+use sprite_evaluation::SpriteEvaluation;
 use status_register::StatusRegister;
 
-use crate::hardware::interrupt::Interrupt;
-use crate::mapper::Mirroring;
+use crate::cartridge::rom::NESRom;
+use crate::region::Region;
+use crate::render::frame::Frame;
+
+const DOTS_PER_SCANLINE: usize = 341;
+
+// Frames the open bus latch holds its value for before decaying (~600ms)
+const OPEN_BUS_DECAY_FRAMES: u8 = 36;
 
 pub struct Ppu {
     pub palette_table: [u8; 0x20],
     pub vram: [u8; 0x800],
     pub oam_data: [u8; 256],
     oam_addr: u8,
-    pub chr_rom: Vec<u8>,
-    internal: u8,         // Internal bus data buffer
-    mirroring: Mirroring, // Mirroring mode
+    internal: u8, // Internal bus data buffer
+    open_bus: u8, // Last value driven onto the CPU-PPU data bus
+    open_bus_decay: u8,
+    sprites: SpriteEvaluation,
+    render: RenderState,
+    frame_buffer: Frame,
 
     pub ctrl: ControlRegister, // 0x2000
     pub mask: MaskRegister,    // 0x2001
     status: StatusRegister,    // 0x2002
-    scroll: ScrollRegister,    // 0x2005
-    addr: AddressRegister,     // 0x2006
+    addr: AddressRegister,     // 0x2005/0x2006
 
     cycle: usize,
     scanline: usize,
+    odd_frame: bool,
+    frame: u64,
+    suppress_vblank: bool,
+    region: Region,
 }
 
 impl Ppu {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Ppu {
+    pub fn new(region: Region) -> Ppu {
         Ppu {
             internal: 0,
+            open_bus: 0,
+            open_bus_decay: 0,
+            sprites: SpriteEvaluation::new(),
+            render: RenderState::new(),
+            frame_buffer: Frame::new(),
             palette_table: [0; 32],
             vram: [0; 2048],
             oam_addr: 0,
@@ -46,146 +66,273 @@ impl Ppu {
             mask: MaskRegister::new(),
             status: StatusRegister::new(),
             addr: AddressRegister::new(),
-            scroll: ScrollRegister::new(),
-            mirroring,
             cycle: 0,
             scanline: 241,
-            chr_rom,
+            odd_frame: false,
+            frame: 0,
+            suppress_vblank: false,
+            region,
         }
     }
 
-    pub fn read(&mut self, address: u16) -> u8 {
-        match address {
-            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => {
-                // panic!(
-                //     "Attempted to read from write only register at address: {:04X}",
-                //     address
-                // );
-                0
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Number of frames completed since power on
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The most recently completed frame
+    pub fn frame_buffer(&self) -> &Frame {
+        &self.frame_buffer
+    }
+
+    /// Records which tile and palette produced each pixel in the frame buffer
+    pub fn track_sources(&mut self) {
+        self.frame_buffer.track_sources();
+    }
+
+    /// Scroll position for the next frame as pixel coordinates within the
+    /// 512x480 area of all four nametables
+    pub fn scroll(&self) -> (usize, usize) {
+        let t = self.addr.temp() as usize;
+        let x = (t & 0x1F) * 8 + self.addr.fine_x as usize + ((t >> 10) & 1) * 256;
+        let y = ((t >> 5) & 0x1F) * 8 + ((t >> 12) & 0x07) + ((t >> 11) & 1) * 240;
+
+        (x, y)
+    }
+
+    pub fn read(&mut self, address: u16, cartridge: &mut NESRom) -> u8 {
+        let data = match address {
+            // Write only registers return whatever is left on the bus
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => return self.open_bus,
+            0x2002 => {
+                let data = self.read_status();
+                // Only the top three bits are driven, the rest is open bus
+                self.refresh_open_bus((data & 0xE0) | (self.open_bus & 0x1F));
+                return self.open_bus;
             }
-            0x2002 => self.status.read(),
-            0x2004 => self.oam_data[self.oam_addr as usize],
-            0x2007 => self.read_data(),
-            _ => self.read(address & 0x2007),
-        }
+            0x2004 => self.read_oam_data(),
+            0x2007 => self.read_data(cartridge),
+            _ => return self.read(address & 0x2007, cartridge),
+        };
+
+        self.refresh_open_bus(data);
+        data
     }
 
-    pub fn write(&mut self, address: u16, data: u8) {
+    pub fn write(&mut self, address: u16, data: u8, cartridge: &mut NESRom) {
+        self.refresh_open_bus(data);
+
         match address {
-            0x2000 => self.ctrl.write(data),
+            0x2000 => {
+                self.ctrl.write(data);
+                self.addr.write_nametable(data);
+            }
             0x2001 => self.mask.write(data),
-            0x2002 => panic!("Attempted to write to PPU status register"),
+            0x2002 => (), // Read only, the write only lands on the bus
             0x2003 => self.oam_addr = data,
             0x2004 => self.write_oam_data(data),
-            0x2005 => self.scroll.write(data),
+            0x2005 => self.addr.write_scroll(data),
             0x2006 => self.addr.write(data),
-            0x2007 => self.write_data(data),
+            0x2007 => self.write_data(data, cartridge),
+
+            _ => self.write(address & 0x2007, data, cartridge),
+        }
+    }
+
+    fn refresh_open_bus(&mut self, data: u8) {
+        self.open_bus = data;
+        self.open_bus_decay = OPEN_BUS_DECAY_FRAMES;
+    }
+
+    fn read_status(&mut self) -> u8 {
+        // Reading one dot before vblank starts returns it clear and keeps the
+        // flag (and its NMI) from being raised this frame
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 0 {
+            self.suppress_vblank = true;
+        }
 
-            _ => self.write(address & 0x2007, data),
+        self.addr.reset_latch();
+        self.status.read()
+    }
+
+    fn read_oam_data(&mut self) -> u8 {
+        if self.is_rendering() && self.is_render_scanline() {
+            return self.sprites.bus();
         }
+
+        self.oam_data[self.oam_addr as usize]
     }
 
     fn write_oam_data(&mut self, value: u8) {
+        // Bits 2-4 of the sprite attribute byte don't exist in OAM
+        let value = match self.oam_addr & 3 {
+            2 => value & 0xE3,
+            _ => value,
+        };
+
         self.oam_data[self.oam_addr as usize] = value;
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
-    pub fn read_data(&mut self) -> u8 {
+    pub fn read_data(&mut self, cartridge: &mut NESRom) -> u8 {
         let address = self.addr.get();
-        self.addr.increment(self.ctrl.vram_address_increment());
+        self.increment_vram_address();
 
         match address {
-            0..=0x1FFF => {
-                let result = self.internal;
-                self.internal = self.chr_rom[address as usize];
-                result
-            }
-            0x2000..=0x2FFF => {
+            0..=0x3EFF => {
                 let result = self.internal;
-                self.internal = self.vram[self.mirror_vram_addr(address) as usize];
+                self.internal = cartridge.ppu_read(address, &self.vram);
                 result
             }
-            0x3F00..=0x3FFF => {
-                // Side effect: When reading from palette, it also reads from VRAM into buffer
-                self.internal = self.vram[self.mirror_vram_addr(address) as usize];
-                self.palette_table[self.mirror_palette(address)]
+            _ => {
+                // Side effect: the buffer is filled from the nametable "under" the palette
+                self.internal = cartridge.ppu_read(address, &self.vram);
+
+                // Palette entries are 6 bits wide, the top two bits come from open bus
+                (self.read_palette(address) & 0x3F) | (self.open_bus & 0xC0)
             }
-            _ => unimplemented!("Attempted to read from #{:04X}", address),
         }
     }
 
-    pub fn write_data(&mut self, data: u8) {
+    pub fn write_data(&mut self, data: u8, cartridge: &mut NESRom) {
         let address = self.addr.get();
-        self.addr.increment(self.ctrl.vram_address_increment());
+        self.increment_vram_address();
 
         match address {
-            0..=0x1FFF => self.chr_rom[address as usize] = data,
-            0x2000..=0x3EFF => self.vram[self.mirror_vram_addr(address) as usize] = data,
-            0x3F00..=0x3FFF => self.palette_table[self.mirror_palette(address)] = data,
-            _ => unimplemented!("Attempted to write to #{:04X}", address),
+            0..=0x3EFF => cartridge.ppu_write(address, data, &mut self.vram),
+            _ => self.palette_table[Self::mirror_palette(address)] = data,
+        }
+    }
+
+    fn increment_vram_address(&mut self) {
+        if self.is_rendering() && self.is_render_scanline() {
+            // Accessing $2007 mid-render bumps both scroll counters instead
+            self.addr.increment_x();
+            self.addr.increment_y();
+        } else {
+            self.addr.increment(self.ctrl.vram_address_increment());
+        }
+    }
+
+    fn read_palette(&self, address: u16) -> u8 {
+        let color = self.palette_table[Self::mirror_palette(address)];
+
+        if self.mask.grayscale() {
+            color & 0x30
+        } else {
+            color
         }
     }
 
-    pub fn tick(&mut self, nmi: &mut Interrupt) {
+    fn is_rendering(&self) -> bool {
+        self.mask.show_background() || self.mask.show_sprites()
+    }
+
+    fn is_render_scanline(&self) -> bool {
+        self.scanline < 240 || self.scanline == self.region.pre_render_scanline()
+    }
+
+    pub fn tick(&mut self, cartridge: &mut NESRom) {
         self.cycle += 1;
 
-        if self.cycle == 341 {
+        // The pre-render line is one dot shorter on odd frames while rendering
+        let skip_dot = self.scanline == self.region.pre_render_scanline()
+            && self.cycle == DOTS_PER_SCANLINE - 1
+            && self.odd_frame
+            && self.is_rendering()
+            && self.region.skips_odd_frame_dot();
+
+        if self.cycle == DOTS_PER_SCANLINE || skip_dot {
             self.cycle = 0;
             self.scanline += 1;
 
+            if self.scanline == self.region.scanlines_per_frame() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+                self.frame += 1;
+            }
+        }
+
+        if self.is_rendering() {
+            self.tick_sprites();
+            self.tick_render(cartridge);
+        } else {
+            self.render_backdrop();
+        }
+
+        if self.cycle == 1 {
             match self.scanline {
-                30 => self.status.set_sprite_0_hit(true),
-                241 => self.start_vblank(nmi),
-                261 => {
+                s if s == self.region.vblank_scanline() => self.start_vblank(),
+                s if s == self.region.pre_render_scanline() => {
                     // No longer in vblank
                     self.status.set_vblank(false);
-                    self.scanline = 0;
                     self.status.set_sprite_0_hit(false);
+                    self.status.set_sprite_overflow(false);
+                    self.sprites.overflow = false;
                 }
                 _ => (),
             }
         }
     }
 
-    fn start_vblank(&mut self, nmi: &mut Interrupt) {
-        self.status.set_vblank(true);
+    /// State of the PPU's /NMI output. The CPU is interrupted on its rising
+    /// edge, so clearing vblank or disabling NMIs before the CPU samples the
+    /// line cancels the interrupt.
+    pub fn nmi_line(&self) -> bool {
+        self.status.vblank() && self.ctrl.generate_nmi()
+    }
+
+    fn tick_sprites(&mut self) {
+        if self.scanline < 240 {
+            let sprite_height = self.sprite_height();
+            self.sprites
+                .tick(self.cycle, self.scanline, sprite_height, &self.oam_data);
 
-        if self.ctrl.generate_nmi() {
-            nmi.schedule(1);
+            if self.sprites.overflow {
+                self.status.set_sprite_overflow(true);
+            }
         }
-    }
 
-    pub fn write_oam_dma(&mut self, data: &[u8]) {
-        for i in 0..256 {
-            self.write_oam_data(data[i]);
+        // OAMADDR is reset during the sprite tile fetches
+        if self.is_render_scanline() && (257..=320).contains(&self.cycle) {
+            self.oam_addr = 0;
         }
     }
 
-    // Horizontal:
-    //   [ A ] [ a ]
-    //   [ B ] [ b ]
+    fn start_vblank(&mut self) {
+        if self.open_bus_decay > 0 {
+            self.open_bus_decay -= 1;
+            if self.open_bus_decay == 0 {
+                self.open_bus = 0;
+            }
+        }
+
+        if self.suppress_vblank {
+            self.suppress_vblank = false;
+            return;
+        }
 
-    // Vertical:
-    //   [ A ] [ B ]
-    //   [ a ] [ b ]
-    fn mirror_vram_addr(&self, addr: u16) -> usize {
-        let vram_index = (addr & 0x0FFF) as usize;
-        let name_table = vram_index / 0x400;
+        self.status.set_vblank(true);
+    }
 
-        match (&self.mirroring, name_table) {
-            (Mirroring::Vertical, 2) => vram_index - 0x800,
-            (Mirroring::Vertical, 3) => vram_index - 0x800,
-            (Mirroring::Horizontal, 1) => vram_index - 0x400,
-            (Mirroring::Horizontal, 2) => vram_index - 0x400,
-            (Mirroring::Horizontal, 3) => vram_index - 0x800,
-            _ => vram_index,
+    pub fn write_oam_dma(&mut self, data: &[u8]) {
+        for i in 0..256 {
+            self.write_oam_data(data[i]);
         }
     }
 
-    fn mirror_palette(&self, addr: u16) -> usize {
-        (match addr {
-            0x3F10 | 0x3F14 | 0x3F18 | 0x3F1C => addr & 0x0F,
-            _ => addr & 0x1F,
+    // $3F10/$3F14/$3F18/$3F1C mirror the backdrop entries below them, and
+    // the whole 32 byte table repeats up to $3FFF
+    fn mirror_palette(addr: u16) -> usize {
+        let index = addr & 0x1F;
+
+        (match index {
+            0x10 | 0x14 | 0x18 | 0x1C => index & 0x0F,
+            _ => index,
         }) as usize
     }
 }
@@ -201,3 +348,323 @@ impl Debug for Ppu {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::render::frame::TileId;
+
+    fn cartridge() -> NESRom {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 0];
+        data.resize(16 + 0x4000, 0);
+
+        NESRom::new(&mut Cursor::new(data)).unwrap()
+    }
+
+    fn set_address(ppu: &mut Ppu, cartridge: &mut NESRom, address: u16) {
+        ppu.write(0x2006, (address >> 8) as u8, cartridge);
+        ppu.write(0x2006, address as u8, cartridge);
+    }
+
+    #[test]
+    fn palette_mirrors() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+
+        set_address(&mut ppu, &mut cartridge, 0x3F10);
+        ppu.write(0x2007, 0x21, &mut cartridge);
+
+        set_address(&mut ppu, &mut cartridge, 0x3F20);
+        assert_eq!(ppu.read(0x2007, &mut cartridge), 0x21);
+
+        // $3F34 mirrors $3F14, which in turn mirrors $3F04
+        set_address(&mut ppu, &mut cartridge, 0x3F34);
+        ppu.write(0x2007, 0x15, &mut cartridge);
+        assert_eq!(ppu.palette_table[0x04], 0x15);
+    }
+
+    #[test]
+    fn palette_reads_keep_open_bus_bits() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+
+        set_address(&mut ppu, &mut cartridge, 0x3F00);
+        ppu.write(0x2007, 0x3F, &mut cartridge);
+        set_address(&mut ppu, &mut cartridge, 0x3FC0);
+
+        assert_eq!(ppu.read(0x2007, &mut cartridge), 0xFF);
+    }
+
+    #[test]
+    fn reads_are_buffered_through_nametable_mirrors() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+
+        set_address(&mut ppu, &mut cartridge, 0x2123);
+        ppu.write(0x2007, 0x42, &mut cartridge);
+
+        set_address(&mut ppu, &mut cartridge, 0x3123);
+        ppu.read(0x2007, &mut cartridge);
+        set_address(&mut ppu, &mut cartridge, 0x3123);
+
+        assert_eq!(ppu.read(0x2007, &mut cartridge), 0x42);
+    }
+
+    #[test]
+    fn status_read_resets_write_latch() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+
+        ppu.write(0x2006, 0x3F, &mut cartridge);
+        ppu.read(0x2002, &mut cartridge);
+        ppu.write(0x2002, 0xFF, &mut cartridge);
+        set_address(&mut ppu, &mut cartridge, 0x2000);
+
+        assert_eq!(ppu.addr.get(), 0x2000);
+    }
+
+    #[test]
+    fn odd_frames_skip_a_dot_when_rendering() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.mask.write(0b1000);
+
+        let mut frame_lengths = vec![];
+        for _ in 0..3 {
+            let mut dots = 0;
+            loop {
+                ppu.tick(&mut cartridge);
+                dots += 1;
+                if ppu.scanline == 241 && ppu.cycle == 0 {
+                    break;
+                }
+            }
+            frame_lengths.push(dots);
+        }
+
+        assert_eq!(frame_lengths[1] + frame_lengths[2], 341 * 262 * 2 - 1);
+    }
+
+    fn run_to(ppu: &mut Ppu, cartridge: &mut NESRom, scanline: usize, cycle: usize) {
+        while ppu.scanline != scanline || ppu.cycle != cycle {
+            ppu.tick(cartridge);
+        }
+    }
+
+    #[test]
+    fn vblank_sets_and_clears_on_dot_1() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.tick(&mut cartridge);
+        ppu.read(0x2002, &mut cartridge);
+
+        run_to(&mut ppu, &mut cartridge, 241, 0);
+        assert!(!ppu.status.vblank());
+        ppu.tick(&mut cartridge);
+        assert!(ppu.status.vblank());
+
+        run_to(&mut ppu, &mut cartridge, 261, 0);
+        assert!(ppu.status.vblank());
+        ppu.tick(&mut cartridge);
+        assert!(!ppu.status.vblank());
+    }
+
+    #[test]
+    fn status_read_before_vblank_suppresses_flag() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.ctrl.write(0x80);
+        ppu.tick(&mut cartridge);
+        ppu.read(0x2002, &mut cartridge);
+
+        run_to(&mut ppu, &mut cartridge, 241, 0);
+        assert_eq!(ppu.read(0x2002, &mut cartridge) & 0x80, 0);
+
+        ppu.tick(&mut cartridge);
+        assert!(!ppu.status.vblank());
+        assert!(!ppu.nmi_line());
+    }
+
+    #[test]
+    fn region_frame_lengths() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy].iter() {
+            let mut cartridge = cartridge();
+            let mut ppu = Ppu::new(*region);
+            run_to(&mut ppu, &mut cartridge, 0, 0);
+
+            let mut dots = 0;
+            let mut vblank_start = 0;
+            loop {
+                ppu.tick(&mut cartridge);
+                dots += 1;
+                if ppu.scanline == region.vblank_scanline() && ppu.cycle == 1 {
+                    vblank_start = dots;
+                }
+                if ppu.scanline == 0 && ppu.cycle == 0 {
+                    break;
+                }
+            }
+
+            assert_eq!(dots, region.scanlines_per_frame() * 341);
+            assert_eq!(vblank_start, region.vblank_scanline() * 341 + 1);
+        }
+    }
+
+    fn write_bytes(ppu: &mut Ppu, cartridge: &mut NESRom, address: u16, data: &[u8]) {
+        set_address(ppu, cartridge, address);
+        for &byte in data {
+            ppu.write(0x2007, byte, cartridge);
+        }
+    }
+
+    fn run_frame(ppu: &mut Ppu, cartridge: &mut NESRom) {
+        let frame = ppu.frame();
+        while ppu.frame() == frame {
+            ppu.tick(cartridge);
+        }
+    }
+
+    #[test]
+    fn renders_background_and_sprites() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+
+        // Tile 1 is solid color 1, tile 2 is solid color 3
+        write_bytes(&mut ppu, &mut cartridge, 0x0010, &[0xFF; 8]);
+        write_bytes(&mut ppu, &mut cartridge, 0x0020, &[0xFF; 16]);
+        write_bytes(&mut ppu, &mut cartridge, 0x2000, &[1]);
+        write_bytes(&mut ppu, &mut cartridge, 0x3F00, &[0x0F, 0x16]);
+        write_bytes(&mut ppu, &mut cartridge, 0x3F10, &[0x0F, 0x00, 0x00, 0x2A]);
+
+        ppu.write(0x2003, 0, &mut cartridge);
+        for &byte in [20, 2, 0, 100].iter() {
+            ppu.write(0x2004, byte, &mut cartridge);
+        }
+
+        ppu.write(0x2000, 0, &mut cartridge);
+        ppu.write(0x2005, 0, &mut cartridge);
+        ppu.write(0x2005, 0, &mut cartridge);
+        ppu.write(0x2001, 0b0001_1110, &mut cartridge);
+
+        run_frame(&mut ppu, &mut cartridge);
+        run_frame(&mut ppu, &mut cartridge);
+
+        let frame = ppu.frame_buffer();
+        assert_eq!(frame.pixel(0, 0), 0x16);
+        assert_eq!(frame.pixel(8, 0), 0x0F);
+        assert_eq!(frame.pixel(100, 21), 0x2A);
+        assert_eq!(frame.pixel(107, 28), 0x2A);
+        assert_eq!(frame.pixel(108, 21), 0x0F);
+        assert!(!ppu.status.sprite_0_hit());
+    }
+
+    #[test]
+    fn tracks_pixel_sources() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.track_sources();
+
+        write_bytes(&mut ppu, &mut cartridge, 0x0010, &[0xFF; 8]);
+        write_bytes(&mut ppu, &mut cartridge, 0x0020, &[0x80; 16]);
+        write_bytes(&mut ppu, &mut cartridge, 0x2000, &[1, 1]);
+        write_bytes(&mut ppu, &mut cartridge, 0x3F00, &[0x0F, 0x16, 0x27, 0x38]);
+        write_bytes(&mut ppu, &mut cartridge, 0x3F14, &[0x0F, 0x01, 0x02, 0x03]);
+
+        // A horizontally flipped sprite using tile 2 and palette 5
+        ppu.oam_data[..4].copy_from_slice(&[20, 2, 0x41, 100]);
+        ppu.write(0x2000, 0, &mut cartridge);
+        ppu.write(0x2005, 3, &mut cartridge);
+        ppu.write(0x2005, 0, &mut cartridge);
+        ppu.write(0x2001, 0b0001_1110, &mut cartridge);
+
+        run_frame(&mut ppu, &mut cartridge);
+        run_frame(&mut ppu, &mut cartridge);
+
+        // The board has CHR-RAM, so tiles are identified by their data
+        let mut solid = [0; 16];
+        solid[..8].copy_from_slice(&[0xFF; 8]);
+
+        let frame = ppu.frame_buffer();
+        let background = frame.source(2, 5).unwrap().background.unwrap();
+        assert_eq!(background.tile, TileId::Ram(solid));
+        assert_eq!(background.palette, [0x0F, 0x16, 0x27, 0x38]);
+        // Fine X scroll shifts the columns
+        assert_eq!(
+            (background.column, background.row, background.pixel),
+            (5, 5, 1)
+        );
+        let blank = frame.source(13, 5).unwrap().background.unwrap();
+        assert_eq!((blank.tile, blank.column), (TileId::Ram([0; 16]), 0));
+
+        // The flipped sprite's single opaque column is drawn at its right edge
+        let sprite = frame.source(107, 23).unwrap().sprite.unwrap();
+        assert_eq!(sprite.tile, TileId::Ram([0x80; 16]));
+        assert_eq!(sprite.palette, [0x0F, 0x01, 0x02, 0x03]);
+        assert_eq!((sprite.column, sprite.row, sprite.pixel), (0, 2, 3));
+        assert_eq!(sprite.origin(107, 23), (100, 21));
+        assert!(frame.source(100, 23).unwrap().sprite.is_none());
+    }
+
+    #[test]
+    fn sprite_0_hit() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+
+        write_bytes(&mut ppu, &mut cartridge, 0x0010, &[0xFF; 8]);
+        write_bytes(&mut ppu, &mut cartridge, 0x2000, &[1; 64]);
+        ppu.oam_data[..4].copy_from_slice(&[10, 1, 0, 40]);
+        ppu.write(0x2001, 0b0001_1110, &mut cartridge);
+
+        run_to(&mut ppu, &mut cartridge, 11, 40);
+        assert!(!ppu.status.sprite_0_hit());
+        run_to(&mut ppu, &mut cartridge, 11, 50);
+        assert!(ppu.status.sprite_0_hit());
+    }
+
+    #[test]
+    fn emphasis_bits_are_part_of_the_output() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+
+        write_bytes(&mut ppu, &mut cartridge, 0x3F00, &[0x21]);
+        set_address(&mut ppu, &mut cartridge, 0x2000);
+        ppu.write(0x2001, 0b1010_0001, &mut cartridge);
+
+        run_frame(&mut ppu, &mut cartridge);
+        run_frame(&mut ppu, &mut cartridge);
+
+        // Greyscale keeps only the luma bits of the color
+        assert_eq!(ppu.frame_buffer().pixel(0, 0), 0x20 | 0b101 << 6);
+    }
+
+    #[test]
+    fn decodes_attributes_and_patterns() {
+        // The last tile of nametable 3 is in the top right quadrant of its
+        // last attribute byte, as the bottom row of attributes is half height
+        assert_eq!(attribute_location(0x0FBF), (0x2FFF, 2));
+        assert_eq!(attribute_location(0x0000), (0x23C0, 0));
+        assert_eq!(attribute_location(0x0042), (0x23C0, 6));
+
+        assert_eq!(pattern_pixel(0b1000_0001, 0b0000_0001, 0), 1);
+        assert_eq!(pattern_pixel(0b1000_0001, 0b0000_0001, 7), 3);
+        assert_eq!(pattern_pixel(0b1000_0001, 0b0100_0001, 1), 2);
+    }
+
+    #[test]
+    fn tile_palettes_share_the_backdrop() {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+
+        set_address(&mut ppu, &mut cartridge, 0x3F00);
+        ppu.write(0x2007, 0x0F, &mut cartridge);
+        set_address(&mut ppu, &mut cartridge, 0x3F08);
+        for &color in [0x21, 0x16, 0x27, 0x38].iter() {
+            ppu.write(0x2007, color, &mut cartridge);
+        }
+
+        assert_eq!(ppu.tile_palette(2), [0x0F, 0x16, 0x27, 0x38]);
+        assert_eq!(ppu.tile_palette(6)[0], 0x0F);
+    }
+}
@@ -1,36 +1,124 @@
+/// The PPU's internal scroll and address registers, usually called v, t, x
+/// and w. $2005 and $2006 share the temporary address and write latch, and
+/// rendering walks the current address through the nametables.
+///
+///   yyy NN YYYYY XXXXX
+///   ||| || ||||| +++++-- coarse X scroll
+///   ||| || +++++-------- coarse Y scroll
+///   ||| ++-------------- nametable select
+///   +++----------------- fine Y scroll
 pub struct AddressRegister {
-    value: u16,
-    high_pointer: bool,
+    value: u16, // v
+    temp: u16,  // t
+    pub fine_x: u8,
+    high_pointer: bool, // !w
 }
 
 impl AddressRegister {
     pub fn new() -> Self {
         AddressRegister {
             value: 0,
+            temp: 0,
+            fine_x: 0,
             high_pointer: true,
         }
     }
 
     pub fn get(&self) -> u16 {
+        self.value & 0x3FFF
+    }
+
+    /// The full 15 bit current address, including fine Y
+    pub fn current(&self) -> u16 {
         self.value
     }
 
+    /// The temporary address, which holds the scroll for the next frame
+    pub fn temp(&self) -> u16 {
+        self.temp
+    }
+
     pub fn write(&mut self, data: u8) {
         match self.high_pointer {
-            true => self.value = self.value & 0x00FF | (data as u16) << 8,
-            false => self.value = self.value & 0xFF00 | (data as u16),
+            true => self.temp = self.temp & 0x00FF | ((data & 0x3F) as u16) << 8,
+            false => {
+                self.temp = self.temp & 0xFF00 | (data as u16);
+                self.value = self.temp;
+            }
         }
 
         self.high_pointer = !self.high_pointer;
     }
 
+    pub fn write_scroll(&mut self, data: u8) {
+        match self.high_pointer {
+            true => {
+                self.temp = self.temp & !0x001F | (data >> 3) as u16;
+                self.fine_x = data & 0x07;
+            }
+            false => {
+                self.temp = self.temp & !0x73E0
+                    | ((data & 0x07) as u16) << 12
+                    | ((data & 0xF8) as u16) << 2;
+            }
+        }
+
+        self.high_pointer = !self.high_pointer;
+    }
+
+    pub fn write_nametable(&mut self, data: u8) {
+        self.temp = self.temp & !0x0C00 | ((data & 0x03) as u16) << 10;
+    }
+
     pub fn increment(&mut self, inc: u8) {
-        self.value = (self.value + (inc as u16)) % 0x4000;
+        self.value = (self.value + (inc as u16)) & 0x7FFF;
     }
 
     pub fn reset_latch(&mut self) {
         self.high_pointer = true;
     }
+
+    pub fn increment_x(&mut self) {
+        if self.value & 0x001F == 31 {
+            // Wrap into the horizontally adjacent nametable
+            self.value &= !0x001F;
+            self.value ^= 0x0400;
+        } else {
+            self.value += 1;
+        }
+    }
+
+    pub fn increment_y(&mut self) {
+        if self.value & 0x7000 != 0x7000 {
+            self.value += 0x1000;
+            return;
+        }
+
+        self.value &= !0x7000;
+        let mut coarse_y = (self.value & 0x03E0) >> 5;
+
+        match coarse_y {
+            // Row 29 is the last row of tiles, so wrap into the vertically
+            // adjacent nametable
+            29 => {
+                coarse_y = 0;
+                self.value ^= 0x0800;
+            }
+            // Rows 30 and 31 hold attributes, and wrap without switching
+            31 => coarse_y = 0,
+            _ => coarse_y += 1,
+        }
+
+        self.value = self.value & !0x03E0 | (coarse_y << 5);
+    }
+
+    pub fn copy_x(&mut self) {
+        self.value = self.value & !0x041F | self.temp & 0x041F;
+    }
+
+    pub fn copy_y(&mut self) {
+        self.value = self.value & !0x7BE0 | self.temp & 0x7BE0;
+    }
 }
 
 #[cfg(test)]
@@ -80,4 +168,56 @@ mod test {
 
         assert_eq!(register.get(), 0x3456);
     }
+
+    #[test]
+    fn test_write_scroll() {
+        let mut register = AddressRegister::new();
+
+        register.write_scroll(0xFF);
+        register.write_scroll(0x11);
+
+        assert_eq!(register.temp() & 0x001F, 0x1F);
+        assert_eq!(register.fine_x, 0x07);
+        assert_eq!((register.temp() & 0x03E0) >> 5, 0x11 >> 3);
+        assert_eq!(register.temp() >> 12, 0x11 & 0x07);
+    }
+
+    #[test]
+    fn test_scroll_round_trips() {
+        let mut register = AddressRegister::new();
+        let scroll_x = |r: &AddressRegister| ((r.temp() & 0x001F) << 3) as u8 | r.fine_x;
+        let scroll_y = |r: &AddressRegister| ((r.temp() & 0x03E0) >> 2 | r.temp() >> 12) as u8;
+
+        assert_eq!(scroll_x(&register), 0);
+        assert_eq!(scroll_y(&register), 0);
+
+        register.write_scroll(0xFF);
+        register.write_scroll(0x11);
+
+        assert_eq!(scroll_x(&register), 0xFF);
+        assert_eq!(scroll_y(&register), 0x11);
+    }
+
+    #[test]
+    fn test_shared_latch() {
+        let mut register = AddressRegister::new();
+
+        // A $2005 write flips the latch used by $2006
+        register.write_scroll(0x00);
+        register.write(0x20);
+
+        assert_eq!(register.temp() & 0x00FF, 0x20);
+    }
+
+    #[test]
+    fn test_increment_y_wraps_nametable() {
+        let mut register = AddressRegister::new();
+        register.write_scroll(0x00);
+        register.write_scroll(239);
+        register.copy_y();
+
+        register.increment_y();
+
+        assert_eq!(register.current(), 0x0800);
+    }
 }
@@ -0,0 +1,420 @@
+use super::Ppu;
+use crate::cartridge::rom::NESRom;
+use crate::region::Region;
+use crate::render::frame::{PixelSource, TileSource};
+
+/// A sprite fetched for the scanline being drawn
+#[derive(Clone, Copy, Default)]
+pub struct SpriteUnit {
+    low: u8, // Pattern bits, already flipped horizontally if needed
+    high: u8,
+    attributes: u8,
+    x: u8,
+    source: Option<TileSource>,
+}
+
+/// Background and sprite state of the dot-by-dot rendering pipeline
+pub struct RenderState {
+    nametable_byte: u8,
+    attribute: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+    // Four bits per pixel (palette and pattern) for the current and next tile
+    tile_data: u64,
+    // Shifted alongside tile_data when tracking pixel sources: a nibble per
+    // pixel with the tile's slot in tile_sources in bit 3 and its column
+    tile_tags: u64,
+    tile_sources: [Option<TileSource>; 2],
+    tile_slot: usize,
+
+    sprites: [SpriteUnit; 8],
+    sprite_count: usize,
+    sprite_0_on_line: bool,
+}
+
+impl RenderState {
+    pub fn new() -> Self {
+        RenderState {
+            nametable_byte: 0,
+            attribute: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+            tile_data: 0,
+            tile_tags: 0,
+            tile_sources: [None; 2],
+            tile_slot: 0,
+            sprites: [SpriteUnit::default(); 8],
+            sprite_count: 0,
+            sprite_0_on_line: false,
+        }
+    }
+}
+
+/// The attribute byte covering the tile a VRAM address points at, and the
+/// shift that picks the tile's 2 bit palette out of it
+pub fn attribute_location(address: u16) -> (u16, u8) {
+    let location = 0x23C0 | (address & 0x0C00) | ((address >> 4) & 0x38) | ((address >> 2) & 0x07);
+    let shift = ((address >> 4) & 4) | (address & 2);
+
+    (location, shift as u8)
+}
+
+/// The 2 bit color of a column in a row of a tile, from its two pattern planes
+pub fn pattern_pixel(low: u8, high: u8, column: usize) -> u8 {
+    let shift = 7 - column;
+    (low >> shift) & 1 | ((high >> shift) & 1) << 1
+}
+
+impl Ppu {
+    /// Runs the fetch, scroll and pixel output work for the current dot while
+    /// rendering is enabled
+    pub(super) fn tick_render(&mut self, cartridge: &mut NESRom) {
+        let visible_line = self.scanline < 240;
+        let pre_render_line = self.scanline == self.region.pre_render_scanline();
+
+        if !visible_line && !pre_render_line {
+            return;
+        }
+
+        let visible_cycle = (1..=256).contains(&self.cycle);
+        let fetch_cycle = visible_cycle || (321..=336).contains(&self.cycle);
+
+        if visible_line && visible_cycle {
+            self.render_pixel();
+        }
+
+        if fetch_cycle {
+            self.render.tile_data <<= 4;
+            self.render.tile_tags <<= 4;
+
+            match self.cycle % 8 {
+                1 => self.fetch_nametable_byte(cartridge),
+                3 => self.fetch_attribute(cartridge),
+                5 => self.render.pattern_low = self.fetch_pattern(cartridge, 0),
+                7 => self.render.pattern_high = self.fetch_pattern(cartridge, 8),
+                0 => {
+                    self.store_tile_data(cartridge);
+                    self.addr.increment_x();
+                }
+                _ => (),
+            }
+        }
+
+        match self.cycle {
+            256 => self.addr.increment_y(),
+            257 => self.addr.copy_x(),
+            // Unused nametable fetches at the end of the line
+            337 | 339 => self.fetch_nametable_byte(cartridge),
+            _ => (),
+        }
+
+        if (257..=320).contains(&self.cycle) {
+            self.fetch_sprite(cartridge, pre_render_line);
+        }
+
+        if pre_render_line && (280..=304).contains(&self.cycle) {
+            self.addr.copy_y();
+        }
+    }
+
+    /// With rendering disabled the PPU outputs the backdrop color, or the
+    /// palette entry the VRAM address points at if it is inside the palette
+    pub(super) fn render_backdrop(&mut self) {
+        if self.scanline >= 240 || !(1..=256).contains(&self.cycle) {
+            return;
+        }
+
+        let address = match self.addr.get() {
+            address @ 0x3F00..=0x3FFF => address,
+            _ => 0x3F00,
+        };
+
+        let index = self.output_color(address);
+        self.frame_buffer
+            .set_pixel(self.cycle - 1, self.scanline, index);
+        self.frame_buffer
+            .set_source(self.cycle - 1, self.scanline, PixelSource::default());
+    }
+
+    fn fetch_nametable_byte(&mut self, cartridge: &mut NESRom) {
+        let address = 0x2000 | (self.addr.current() & 0x0FFF);
+        self.render.nametable_byte = cartridge.ppu_read(address, &self.vram);
+    }
+
+    fn fetch_attribute(&mut self, cartridge: &mut NESRom) {
+        let (address, shift) = attribute_location(self.addr.current());
+        self.render.attribute = (cartridge.ppu_read(address, &self.vram) >> shift) & 0b11;
+    }
+
+    fn fetch_pattern(&mut self, cartridge: &mut NESRom, plane: u16) -> u8 {
+        let fine_y = (self.addr.current() >> 12) & 0x07;
+        let address = self.ctrl.background_pattern_address()
+            + self.render.nametable_byte as u16 * 16
+            + fine_y
+            + plane;
+
+        cartridge.ppu_read(address, &self.vram)
+    }
+
+    fn store_tile_data(&mut self, cartridge: &NESRom) {
+        let mut data: u32 = 0;
+
+        for column in 0..8 {
+            let pixel = pattern_pixel(self.render.pattern_low, self.render.pattern_high, column);
+
+            data <<= 4;
+            data |= (self.render.attribute << 2 | pixel) as u32;
+        }
+
+        self.render.tile_data |= data as u64;
+
+        if self.frame_buffer.sources.is_some() {
+            // A tile leaves the shift register before the one two fetches
+            // later replaces its slot
+            let slot = self.render.tile_slot ^ 1;
+            let address =
+                self.ctrl.background_pattern_address() + self.render.nametable_byte as u16 * 16;
+
+            self.render.tile_slot = slot;
+            self.render.tile_sources[slot] = Some(TileSource {
+                tile: cartridge.tile_id(address),
+                palette: self.tile_palette(self.render.attribute),
+                column: 0,
+                row: ((self.addr.current() >> 12) & 0x07) as u8,
+                pixel: 0,
+                h_flip: false,
+                v_flip: false,
+                behind: false,
+            });
+
+            let tags = (0..8).fold(0, |tags, column| tags << 4 | (slot << 3 | column) as u64);
+            self.render.tile_tags |= tags;
+        }
+    }
+
+    /// The 16 bytes of a tile's two pattern planes, read without side effects
+    pub fn peek_tile(&self, cartridge: &NESRom, address: u16) -> [u8; 16] {
+        let mut tile = [0; 16];
+        for (i, byte) in tile.iter_mut().enumerate() {
+            *byte = cartridge.ppu_peek(address + i as u16, &self.vram);
+        }
+
+        tile
+    }
+
+    /// The four colors of a palette, sprite palettes being 4-7. The first is
+    /// always the backdrop, as the PPU never shows the other palettes' first
+    /// entries.
+    pub fn tile_palette(&self, palette: u8) -> [u8; 4] {
+        let mut colors = [self.read_palette(0x3F00) & 0x3F; 4];
+        for (i, color) in colors.iter_mut().enumerate().skip(1) {
+            *color = self.read_palette(0x3F00 | (palette as u16) << 2 | i as u16) & 0x3F;
+        }
+        colors
+    }
+
+    fn fetch_sprite(&mut self, cartridge: &mut NESRom, pre_render_line: bool) {
+        let slot = (self.cycle - 257) / 8;
+        let phase = (self.cycle - 257) % 8;
+
+        if self.cycle == 257 {
+            self.render.sprite_count = if pre_render_line {
+                0
+            } else {
+                self.sprites.sprite_count
+            };
+            self.render.sprite_0_on_line = !pre_render_line && self.sprites.sprite_0_in_range;
+        }
+
+        match phase {
+            // Garbage nametable fetches keep the PPU's bus timing intact
+            0 | 2 => self.fetch_nametable_byte(cartridge),
+            4 | 6 => {
+                let address = self.sprite_pattern_address(slot) + if phase == 6 { 8 } else { 0 };
+                let mut data = cartridge.ppu_read(address, &self.vram);
+
+                let attributes = self.sprites.secondary_oam[slot * 4 + 2];
+                if attributes & 0x40 != 0 {
+                    data = data.reverse_bits();
+                }
+
+                if slot >= self.render.sprite_count {
+                    // Empty slots fetch tile $FF but never draw
+                    data = 0;
+                }
+
+                let source = if phase == 6
+                    && slot < self.render.sprite_count
+                    && self.frame_buffer.sources.is_some()
+                {
+                    Some(TileSource {
+                        tile: cartridge.tile_id(address),
+                        palette: self.tile_palette(4 + (attributes & 0b11)),
+                        column: 0,
+                        row: (address & 0x07) as u8,
+                        pixel: 0,
+                        h_flip: attributes & 0x40 != 0,
+                        v_flip: attributes & 0x80 != 0,
+                        behind: attributes & 0x20 != 0,
+                    })
+                } else {
+                    None
+                };
+
+                let sprite = &mut self.render.sprites[slot];
+                if phase == 4 {
+                    sprite.low = data;
+                } else {
+                    sprite.high = data;
+                    sprite.attributes = attributes;
+                    sprite.x = self.sprites.secondary_oam[slot * 4 + 3];
+                    sprite.source = source;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn sprite_pattern_address(&self, slot: usize) -> u16 {
+        let (y, tile, attributes) = if slot < self.render.sprite_count {
+            let oam = &self.sprites.secondary_oam[slot * 4..slot * 4 + 3];
+            (oam[0], oam[1], oam[2])
+        } else {
+            (0xFF, 0xFF, 0xFF)
+        };
+
+        let height = self.sprite_height();
+        let mut row = (self.scanline as u16).wrapping_sub(y as u16) % height as u16;
+        if attributes & 0x80 != 0 {
+            row = height as u16 - 1 - row;
+        }
+
+        if height == 16 {
+            // 8x16 sprites pick their bank from bit 0 of the tile index
+            let bank = (tile as u16 & 1) * 0x1000;
+            let tile = (tile & 0xFE) as u16 + row / 8;
+            bank + tile * 16 + row % 8
+        } else {
+            self.ctrl.sprite_pattern_address() + tile as u16 * 16 + row
+        }
+    }
+
+    pub(super) fn sprite_height(&self) -> usize {
+        if self.ctrl.sprite_size() {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn background_pixel(&self, x: usize) -> u8 {
+        if !self.mask.show_background() || (x < 8 && !self.mask.leftmost_8_background()) {
+            return 0;
+        }
+
+        let data = (self.render.tile_data >> 32) as u32;
+        ((data >> ((7 - self.addr.fine_x) * 4)) & 0x0F) as u8
+    }
+
+    /// The tile the background pixel at `x` comes from
+    fn background_source(&self, x: usize) -> Option<TileSource> {
+        if !self.mask.show_background() || (x < 8 && !self.mask.leftmost_8_background()) {
+            return None;
+        }
+
+        let tags = (self.render.tile_tags >> 32) as u32;
+        let tag = (tags >> ((7 - self.addr.fine_x) * 4)) & 0x0F;
+
+        self.render.tile_sources[(tag >> 3) as usize].map(|source| TileSource {
+            column: (tag & 0x07) as u8,
+            ..source
+        })
+    }
+
+    fn sprite_source(&self, i: usize, x: usize, pixel: u8) -> Option<TileSource> {
+        let sprite = &self.render.sprites[i];
+        let offset = (x - sprite.x as usize) as u8;
+
+        sprite.source.map(|source| TileSource {
+            // The pattern bits were already flipped when fetched
+            column: if source.h_flip { 7 - offset } else { offset },
+            pixel: pixel & 0b11,
+            ..source
+        })
+    }
+
+    fn sprite_pixel(&self, x: usize) -> Option<(usize, u8)> {
+        if !self.mask.show_sprites() || (x < 8 && !self.mask.leftmost_8_sprite()) {
+            return None;
+        }
+
+        for (i, sprite) in self.render.sprites[..self.render.sprite_count]
+            .iter()
+            .enumerate()
+        {
+            let offset = x as isize - sprite.x as isize;
+            if !(0..8).contains(&offset) {
+                continue;
+            }
+
+            let pixel = pattern_pixel(sprite.low, sprite.high, offset as usize);
+            if pixel != 0 {
+                return Some((i, (sprite.attributes & 0b11) << 2 | pixel));
+            }
+        }
+
+        None
+    }
+
+    fn render_pixel(&mut self) {
+        let x = self.cycle - 1;
+        let background = self.background_pixel(x);
+        let sprite = self.sprite_pixel(x);
+
+        let color = match (background & 0b11 != 0, sprite) {
+            (false, None) => 0,
+            (false, Some((_, sprite))) => 0x10 | sprite,
+            (true, None) => background,
+            (true, Some((i, sprite))) => {
+                if i == 0 && self.render.sprite_0_on_line && x < 255 {
+                    self.status.set_sprite_0_hit(true);
+                }
+
+                // Sprites with the priority bit set draw behind the background
+                if self.render.sprites[i].attributes & 0x20 == 0 {
+                    0x10 | sprite
+                } else {
+                    background
+                }
+            }
+        };
+
+        let index = self.output_color(0x3F00 | color as u16);
+        self.frame_buffer.set_pixel(x, self.scanline, index);
+
+        if self.frame_buffer.sources.is_some() {
+            let source = PixelSource {
+                background: self.background_source(x).map(|source| TileSource {
+                    pixel: background & 0b11,
+                    ..source
+                }),
+                sprite: sprite.and_then(|(i, pixel)| self.sprite_source(i, x, pixel)),
+            };
+            self.frame_buffer.set_source(x, self.scanline, source);
+        }
+    }
+
+    /// Combines a palette entry with the greyscale and emphasis bits into the
+    /// PPU's 9 bit output color
+    fn output_color(&self, address: u16) -> u16 {
+        let color = self.read_palette(address) & 0x3F;
+
+        let mut emphasis = (self.mask.get() >> 5) as u16;
+        if self.region != Region::Ntsc {
+            // PAL and Dendy PPUs swap the red and green emphasis bits
+            emphasis = emphasis & 0b100 | (emphasis & 0b001) << 1 | (emphasis & 0b010) >> 1;
+        }
+
+        color as u16 | emphasis << 6
+    }
+}
@@ -0,0 +1,174 @@
+/// Per-dot model of the PPU's sprite evaluation, which scans primary OAM on
+/// every visible scanline and copies up to eight in-range sprites into
+/// secondary OAM for the following line. The value on the internal OAM bus is
+/// what the CPU sees when it reads $2004 during rendering.
+pub struct SpriteEvaluation {
+    pub secondary_oam: [u8; 32],
+    pub sprite_count: usize,
+    pub sprite_0_in_range: bool,
+    pub overflow: bool,
+
+    bus: u8,
+    n: usize,
+    m: usize,
+    index: usize,
+    done: bool,
+}
+
+impl SpriteEvaluation {
+    pub fn new() -> Self {
+        SpriteEvaluation {
+            secondary_oam: [0xFF; 32],
+            sprite_count: 0,
+            sprite_0_in_range: false,
+            overflow: false,
+            bus: 0xFF,
+            n: 0,
+            m: 0,
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// Value currently latched on the OAM bus
+    pub fn bus(&self) -> u8 {
+        self.bus
+    }
+
+    /// Runs one dot of evaluation for a visible scanline
+    pub fn tick(&mut self, dot: usize, scanline: usize, sprite_height: usize, oam: &[u8; 256]) {
+        match dot {
+            // Secondary OAM is cleared, with reads returning $FF
+            1..=64 => {
+                self.bus = 0xFF;
+                if dot & 1 == 0 {
+                    self.secondary_oam[dot / 2 - 1] = 0xFF;
+                }
+            }
+            65..=256 => {
+                if dot == 65 {
+                    self.start();
+                }
+
+                if dot & 1 == 1 {
+                    self.bus = oam[(self.n * 4 + self.m) & 0xFF];
+                } else {
+                    self.evaluate(scanline, sprite_height);
+                }
+            }
+            // Sprite tile fetches walk through secondary OAM
+            257..=320 => {
+                let sprite = (dot - 257) / 8;
+                let byte = ((dot - 257) % 8).min(3);
+                self.bus = self.secondary_oam[sprite * 4 + byte];
+            }
+            _ => self.bus = self.secondary_oam[0],
+        }
+    }
+
+    fn start(&mut self) {
+        self.n = 0;
+        self.m = 0;
+        self.index = 0;
+        self.done = false;
+        self.sprite_count = 0;
+        self.sprite_0_in_range = false;
+    }
+
+    fn in_range(value: u8, scanline: usize, sprite_height: usize) -> bool {
+        let y = value as usize;
+        scanline >= y && scanline < y + sprite_height
+    }
+
+    fn next_sprite(&mut self) {
+        self.n += 1;
+        if self.n == 64 {
+            self.done = true;
+        }
+    }
+
+    fn evaluate(&mut self, scanline: usize, sprite_height: usize) {
+        if self.done {
+            return;
+        }
+
+        let value = self.bus;
+
+        if self.index < 32 {
+            // The Y coordinate is always copied, even for sprites out of range
+            self.secondary_oam[self.index] = value;
+
+            if self.m == 0 {
+                if Self::in_range(value, scanline, sprite_height) {
+                    if self.n == 0 {
+                        self.sprite_0_in_range = true;
+                    }
+                    self.index += 1;
+                    self.m = 1;
+                } else {
+                    self.next_sprite();
+                }
+            } else {
+                self.index += 1;
+                self.m += 1;
+
+                if self.m == 4 {
+                    self.m = 0;
+                    self.sprite_count += 1;
+                    self.next_sprite();
+                }
+            }
+        } else if Self::in_range(value, scanline, sprite_height) {
+            // Ninth sprite found on the line
+            self.overflow = true;
+            self.done = true;
+        } else {
+            // Hardware bug: both n and m are incremented while searching for
+            // overflow, so later bytes are misinterpreted as Y coordinates
+            self.m = (self.m + 1) & 3;
+            self.next_sprite();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn evaluate(oam: &[u8; 256], scanline: usize) -> SpriteEvaluation {
+        let mut evaluation = SpriteEvaluation::new();
+        for dot in 1..=256 {
+            evaluation.tick(dot, scanline, 8, oam);
+        }
+
+        evaluation
+    }
+
+    #[test]
+    fn copies_sprites_in_range() {
+        let mut oam = [0xF0; 256];
+        oam[0..4].copy_from_slice(&[10, 1, 2, 3]);
+        oam[8..12].copy_from_slice(&[12, 4, 5, 6]);
+
+        let evaluation = evaluate(&oam, 15);
+
+        assert_eq!(evaluation.sprite_count, 2);
+        assert!(evaluation.sprite_0_in_range);
+        assert!(!evaluation.overflow);
+        assert_eq!(evaluation.secondary_oam[0..8], [10, 1, 2, 3, 12, 4, 5, 6]);
+        assert_eq!(evaluation.secondary_oam[9], 0xFF);
+    }
+
+    #[test]
+    fn flags_overflow_on_ninth_sprite() {
+        let mut oam = [0xF0; 256];
+        for sprite in 0..9 {
+            oam[sprite * 4] = 20;
+        }
+
+        let evaluation = evaluate(&oam, 20);
+
+        assert_eq!(evaluation.sprite_count, 8);
+        assert!(evaluation.overflow);
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, ErrorKind},
+    path::Path,
+};
+
+use crate::render::image::Image;
+
+use self::{avi::AviWriter, y4m::Y4mRecorder};
+
+pub(crate) mod avi;
+pub(crate) mod wav;
+pub(crate) mod y4m;
+
+/// Shape of the recorded streams
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StreamFormat {
+    pub width: usize,
+    pub height: usize,
+    pub frame_rate: (u32, u32), // Frames per second as numerator, denominator
+    pub pixel_aspect: (u32, u32),
+    pub sample_rate: u32,
+}
+
+/// Writes emulated video and audio losslessly. Frames and samples are added
+/// as the emulator produces them, so timing follows emulated time rather than
+/// wall clock time.
+pub trait Recorder {
+    fn add_frame(&mut self, image: &Image) -> io::Result<()>;
+
+    /// Adds mono samples from -1.0 to 1.0
+    fn add_audio(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Completes headers and indexes. Nothing may be added afterwards.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+pub struct RecorderFactory;
+
+impl RecorderFactory {
+    /// Picks the container from the file extension: `.avi` for uncompressed
+    /// AVI, or `.y4m` for YUV4MPEG2 video with the audio in a `.wav` beside it
+    pub fn create(filename: &str, format: StreamFormat) -> io::Result<Box<dyn Recorder>> {
+        let path = Path::new(filename);
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("avi") => Ok(Box::new(AviWriter::new(
+                BufWriter::new(File::create(path)?),
+                format,
+            )?)),
+            Some("y4m") => Ok(Box::new(Y4mRecorder::new(
+                BufWriter::new(File::create(path)?),
+                BufWriter::new(File::create(path.with_extension("wav"))?),
+                format,
+            )?)),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unsupported recording format {}", filename),
+            )),
+        }
+    }
+}
+
+/// Converts a sample to 16 bit PCM
+fn pcm(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
@@ -0,0 +1,309 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::{pcm, Recorder, StreamFormat};
+use crate::render::image::Image;
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+/// Uncompressed AVI with a 24 bit RGB video stream and a 16 bit mono PCM
+/// audio stream. Being a plain RIFF file, recordings are limited to 4 GiB.
+pub struct AviWriter<W: Write + Seek> {
+    writer: W,
+    format: StreamFormat,
+    frames: u32,
+    audio_samples: u32,
+    index: Vec<(&'static [u8; 4], u32, u32)>, // Chunk id, offset and size
+    movi: u64,
+
+    // Header fields only known once recording finishes
+    total_frames: u64,
+    video_length: u64,
+    audio_length: u64,
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    pub fn new(writer: W, format: StreamFormat) -> io::Result<Self> {
+        let mut avi = AviWriter {
+            writer,
+            format,
+            frames: 0,
+            audio_samples: 0,
+            index: Vec::new(),
+            movi: 0,
+            total_frames: 0,
+            video_length: 0,
+            audio_length: 0,
+        };
+
+        avi.write_headers()?;
+        Ok(avi)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn frame_size(&self) -> u32 {
+        (self.row_size() * self.format.height) as u32
+    }
+
+    // Bitmap rows are padded to a multiple of four bytes
+    fn row_size(&self) -> usize {
+        (self.format.width * 3 + 3) & !3
+    }
+
+    fn write_headers(&mut self) -> io::Result<()> {
+        let (rate, scale) = self.format.frame_rate;
+        let width = self.format.width as u32;
+        let height = self.format.height as u32;
+        let sample_rate = self.format.sample_rate;
+
+        self.begin_list(b"RIFF", b"AVI ")?;
+        let hdrl = self.begin_list(b"LIST", b"hdrl")?;
+
+        let avih = self.begin_chunk(b"avih")?;
+        let w = &mut self.writer;
+        w.write_u32::<LittleEndian>((1_000_000.0 * scale as f64 / rate as f64).round() as u32)?;
+        w.write_u32::<LittleEndian>(0)?; // Max bytes per second
+        w.write_u32::<LittleEndian>(0)?; // Padding granularity
+        w.write_u32::<LittleEndian>(AVIF_HASINDEX)?;
+        self.total_frames = w.stream_position()?;
+        w.write_u32::<LittleEndian>(0)?;
+        w.write_u32::<LittleEndian>(0)?; // Initial frames
+        w.write_u32::<LittleEndian>(2)?; // Streams
+        w.write_u32::<LittleEndian>(0)?; // Suggested buffer size
+        w.write_u32::<LittleEndian>(width)?;
+        w.write_u32::<LittleEndian>(height)?;
+        w.write_all(&[0; 16])?;
+        self.end_chunk(avih)?;
+
+        let strl = self.begin_list(b"LIST", b"strl")?;
+        self.video_length = self.write_stream_header(b"vids", scale, rate, 0)?;
+        let strf = self.begin_chunk(b"strf")?;
+        let frame_size = self.frame_size();
+        let w = &mut self.writer;
+        w.write_u32::<LittleEndian>(40)?;
+        w.write_i32::<LittleEndian>(width as i32)?;
+        w.write_i32::<LittleEndian>(height as i32)?; // Positive, so bottom up
+        w.write_u16::<LittleEndian>(1)?; // Planes
+        w.write_u16::<LittleEndian>(24)?;
+        w.write_u32::<LittleEndian>(0)?; // Uncompressed RGB
+        w.write_u32::<LittleEndian>(frame_size)?;
+        w.write_all(&[0; 16])?;
+        self.end_chunk(strf)?;
+        self.end_chunk(strl)?;
+
+        let strl = self.begin_list(b"LIST", b"strl")?;
+        self.audio_length = self.write_stream_header(b"auds", 2, sample_rate * 2, 2)?;
+        let strf = self.begin_chunk(b"strf")?;
+        let w = &mut self.writer;
+        w.write_u16::<LittleEndian>(1)?; // PCM
+        w.write_u16::<LittleEndian>(1)?; // Mono
+        w.write_u32::<LittleEndian>(sample_rate)?;
+        w.write_u32::<LittleEndian>(sample_rate * 2)?;
+        w.write_u16::<LittleEndian>(2)?; // Block align
+        w.write_u16::<LittleEndian>(16)?;
+        self.end_chunk(strf)?;
+        self.end_chunk(strl)?;
+
+        self.end_chunk(hdrl)?;
+
+        // Sizes of the open RIFF and movi lists are patched in by finish.
+        // Index offsets are relative to the movi fourcc.
+        self.movi = self.begin_list(b"LIST", b"movi")? + 8;
+
+        Ok(())
+    }
+
+    /// Writes a stream header and returns the position of its length field
+    fn write_stream_header(
+        &mut self,
+        kind: &[u8; 4],
+        scale: u32,
+        rate: u32,
+        sample_size: u32,
+    ) -> io::Result<u64> {
+        let strh = self.begin_chunk(b"strh")?;
+        let w = &mut self.writer;
+
+        w.write_all(kind)?;
+        w.write_all(&[0; 4])?; // Handler
+        w.write_u32::<LittleEndian>(0)?; // Flags
+        w.write_u16::<LittleEndian>(0)?; // Priority
+        w.write_u16::<LittleEndian>(0)?; // Language
+        w.write_u32::<LittleEndian>(0)?; // Initial frames
+        w.write_u32::<LittleEndian>(scale)?;
+        w.write_u32::<LittleEndian>(rate)?;
+        w.write_u32::<LittleEndian>(0)?; // Start
+        let length = w.stream_position()?;
+        w.write_u32::<LittleEndian>(0)?;
+        w.write_u32::<LittleEndian>(0)?; // Suggested buffer size
+        w.write_i32::<LittleEndian>(-1)?; // Default quality
+        w.write_u32::<LittleEndian>(sample_size)?;
+        w.write_all(&[0; 8])?; // Frame rectangle
+
+        self.end_chunk(strh)?;
+        Ok(length)
+    }
+
+    fn begin_chunk(&mut self, id: &[u8; 4]) -> io::Result<u64> {
+        let start = self.writer.stream_position()?;
+        self.writer.write_all(id)?;
+        self.writer.write_u32::<LittleEndian>(0)?;
+
+        Ok(start)
+    }
+
+    fn begin_list(&mut self, id: &[u8; 4], kind: &[u8; 4]) -> io::Result<u64> {
+        let start = self.begin_chunk(id)?;
+        self.writer.write_all(kind)?;
+
+        Ok(start)
+    }
+
+    /// Fills in the size of the chunk starting at `start`
+    fn end_chunk(&mut self, start: u64) -> io::Result<()> {
+        let end = self.writer.stream_position()?;
+        self.patch(start + 4, (end - start - 8) as u32)
+    }
+
+    fn patch(&mut self, position: u64, value: u32) -> io::Result<()> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(position))?;
+        self.writer.write_u32::<LittleEndian>(value)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+
+        Ok(())
+    }
+
+    fn write_data_chunk(&mut self, id: &'static [u8; 4], data: &[u8]) -> io::Result<()> {
+        let offset = (self.writer.stream_position()? - self.movi) as u32;
+        self.index.push((id, offset, data.len() as u32));
+
+        self.writer.write_all(id)?;
+        self.writer.write_u32::<LittleEndian>(data.len() as u32)?;
+        self.writer.write_all(data)?;
+        if data.len() & 1 != 0 {
+            self.writer.write_u8(0)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Recorder for AviWriter<W> {
+    fn add_frame(&mut self, image: &Image) -> io::Result<()> {
+        let row_size = self.row_size();
+        let mut data = vec![0; self.frame_size() as usize];
+
+        // Bottom up rows of BGR pixels
+        for y in 0..self.format.height {
+            let row = &mut data[(self.format.height - 1 - y) * row_size..];
+            for x in 0..self.format.width {
+                let (r, g, b) = image.pixel(x, y);
+                row[x * 3..x * 3 + 3].copy_from_slice(&[b, g, r]);
+            }
+        }
+
+        self.write_data_chunk(b"00db", &data)?;
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    fn add_audio(&mut self, samples: &[f32]) -> io::Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            data.write_i16::<LittleEndian>(pcm(sample))?;
+        }
+
+        self.write_data_chunk(b"01wb", &data)?;
+        self.audio_samples += samples.len() as u32;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.end_chunk(self.movi - 8)?;
+
+        let idx1 = self.begin_chunk(b"idx1")?;
+        for &(id, offset, size) in self.index.iter() {
+            self.writer.write_all(id)?;
+            self.writer.write_u32::<LittleEndian>(AVIIF_KEYFRAME)?;
+            self.writer.write_u32::<LittleEndian>(offset)?;
+            self.writer.write_u32::<LittleEndian>(size)?;
+        }
+        self.end_chunk(idx1)?;
+        self.end_chunk(0)?;
+
+        self.patch(self.total_frames, self.frames)?;
+        self.patch(self.video_length, self.frames)?;
+        self.patch(self.audio_length, self.audio_samples)?;
+
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn read_u32(data: &[u8], position: usize) -> u32 {
+        u32::from_le_bytes([
+            data[position],
+            data[position + 1],
+            data[position + 2],
+            data[position + 3],
+        ])
+    }
+
+    fn find(data: &[u8], id: &[u8]) -> usize {
+        data.windows(4).position(|window| window == id).unwrap()
+    }
+
+    #[test]
+    fn writes_interleaved_streams_with_index() {
+        let format = StreamFormat {
+            width: 3,
+            height: 2,
+            frame_rate: (60, 1),
+            pixel_aspect: (8, 7),
+            sample_rate: 44_100,
+        };
+        let mut avi = AviWriter::new(Cursor::new(Vec::new()), format).unwrap();
+
+        let mut image = Image::new(3, 2);
+        image.set_pixel(0, 0, (1, 2, 3));
+        for _ in 0..2 {
+            avi.add_frame(&image).unwrap();
+            avi.add_audio(&[0.0; 735]).unwrap();
+        }
+        avi.finish().unwrap();
+
+        let data = avi.into_inner().into_inner();
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(read_u32(&data, 4) as usize, data.len() - 8);
+
+        // Total frames in the main header
+        assert_eq!(read_u32(&data, find(&data, b"avih") + 24), 2);
+
+        // Rows are padded to 12 bytes and stored bottom up as BGR
+        let frame = find(&data, b"00db");
+        assert_eq!(read_u32(&data, frame + 4), 24);
+        assert_eq!(&data[frame + 20..frame + 23], &[3, 2, 1]);
+
+        let idx1 = find(&data, b"idx1");
+        assert_eq!(read_u32(&data, idx1 + 4), 4 * 16);
+        let movi = find(&data, b"movi");
+        assert_eq!(read_u32(&data, idx1 + 16) as usize, frame - movi);
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::pcm;
+
+const HEADER_SIZE: u32 = 44;
+
+/// 16 bit mono PCM WAV writer. The chunk sizes are filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LittleEndian>(HEADER_SIZE - 8)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LittleEndian>(16)?;
+        writer.write_u16::<LittleEndian>(1)?; // PCM
+        writer.write_u16::<LittleEndian>(1)?; // Mono
+        writer.write_u32::<LittleEndian>(sample_rate)?;
+        writer.write_u32::<LittleEndian>(sample_rate * 2)?;
+        writer.write_u16::<LittleEndian>(2)?; // Bytes per sample frame
+        writer.write_u16::<LittleEndian>(16)?;
+
+        writer.write_all(b"data")?;
+        writer.write_u32::<LittleEndian>(0)?;
+
+        Ok(WavWriter {
+            writer,
+            data_size: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            self.writer.write_i16::<LittleEndian>(pcm(sample))?;
+        }
+
+        self.data_size += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_u32::<LittleEndian>(HEADER_SIZE - 8 + self.data_size)?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_u32::<LittleEndian>(self.data_size)?;
+
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn writes_pcm_with_sizes() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44_100).unwrap();
+        wav.write_samples(&[0.0, 1.0, -1.0]).unwrap();
+        wav.finish().unwrap();
+
+        let data = wav.into_inner().into_inner();
+        assert_eq!(data.len(), 50);
+        assert_eq!(&data[4..8], &42u32.to_le_bytes());
+        assert_eq!(&data[40..44], &6u32.to_le_bytes());
+        assert_eq!(&data[46..48], &i16::MAX.to_le_bytes());
+    }
+}
@@ -0,0 +1,90 @@
+use std::io::{self, Seek, Write};
+
+use super::{wav::WavWriter, Recorder, StreamFormat};
+use crate::render::image::Image;
+
+/// YUV4MPEG2 video with full resolution chroma, plus the audio as a separate
+/// WAV file. The pixel aspect is recorded so players show the picture at
+/// the NES's display aspect.
+pub struct Y4mRecorder<V: Write, A: Write + Seek> {
+    video: V,
+    audio: WavWriter<A>,
+    planes: Vec<u8>,
+}
+
+impl<V: Write, A: Write + Seek> Y4mRecorder<V, A> {
+    pub fn new(mut video: V, audio: A, format: StreamFormat) -> io::Result<Self> {
+        let (numerator, denominator) = format.frame_rate;
+        let (aspect_x, aspect_y) = format.pixel_aspect;
+        writeln!(
+            video,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A{}:{} C444",
+            format.width, format.height, numerator, denominator, aspect_x, aspect_y
+        )?;
+
+        Ok(Y4mRecorder {
+            video,
+            audio: WavWriter::new(audio, format.sample_rate)?,
+            planes: vec![0; format.width * format.height * 3],
+        })
+    }
+}
+
+impl<V: Write, A: Write + Seek> Recorder for Y4mRecorder<V, A> {
+    fn add_frame(&mut self, image: &Image) -> io::Result<()> {
+        let size = image.width * image.height;
+
+        // BT.601 studio swing, which is what players assume for Y4M
+        for (i, rgb) in image.data.chunks_exact(3).enumerate() {
+            let (r, g, b) = (rgb[0] as f64, rgb[1] as f64, rgb[2] as f64);
+
+            self.planes[i] = (16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0).round() as u8;
+            self.planes[size + i] =
+                (128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0).round() as u8;
+            self.planes[size * 2 + i] =
+                (128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0).round() as u8;
+        }
+
+        self.video.write_all(b"FRAME\n")?;
+        self.video.write_all(&self.planes)
+    }
+
+    fn add_audio(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.audio.write_samples(samples)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.video.flush()?;
+        self.audio.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn writes_header_and_planes() {
+        let format = StreamFormat {
+            width: 2,
+            height: 1,
+            frame_rate: (39_375_000, 655_171),
+            pixel_aspect: (8, 7),
+            sample_rate: 44_100,
+        };
+        let mut video = Vec::new();
+        let mut recorder = Y4mRecorder::new(&mut video, Cursor::new(Vec::new()), format).unwrap();
+
+        let mut image = Image::new(2, 1);
+        image.set_pixel(1, 0, (255, 255, 255));
+        recorder.add_frame(&image).unwrap();
+        recorder.finish().unwrap();
+        drop(recorder);
+
+        let header = b"YUV4MPEG2 W2 H1 F39375000:655171 Ip A8:7 C444\nFRAME\n";
+        assert_eq!(&video[..header.len()], &header[..]);
+        assert_eq!(&video[header.len()..], &[16, 235, 128, 128, 128, 128]);
+    }
+}
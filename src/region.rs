@@ -0,0 +1,167 @@
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+#[rustfmt::skip]
+static NTSC_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+#[rustfmt::skip]
+static PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[rustfmt::skip]
+static NTSC_DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+#[rustfmt::skip]
+static PAL_DMC_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+// CPU cycles at which the APU frame sequencer steps
+static NTSC_FRAME_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+static PAL_FRAME_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+impl Region {
+    /// CPU clock rate in Hz
+    pub fn cpu_clock_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 21_477_272.0 / 12.0,
+            Region::Pal => 26_601_712.0 / 16.0,
+            Region::Dendy => 26_601_712.0 / 15.0,
+        }
+    }
+
+    /// Frames per second produced by the PPU
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    /// Exact frame rate as a (numerator, denominator) pair, from the master
+    /// clock and the number of master clocks per frame. NTSC frames average
+    /// 89341.5 dots of 4 master clocks because of the skipped dot.
+    pub fn frame_rate_ratio(&self) -> (u32, u32) {
+        match self {
+            Region::Ntsc => (39_375_000, 655_171),
+            Region::Pal | Region::Dendy => (3_325_214, 66_495),
+        }
+    }
+
+    /// PPU dots per CPU cycle as a (numerator, denominator) pair. PAL runs
+    /// 3.2 dots per cycle, so sixteen dots every five cycles.
+    pub fn ppu_clock_ratio(&self) -> (u32, u32) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    pub fn scanlines_per_frame(&self) -> usize {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Scanline on which the vblank flag is raised. PAL stretches vblank to
+    /// 70 lines, while Dendy keeps NTSC's 20 lines of vblank and pads the
+    /// frame with 51 post-render lines instead.
+    pub fn vblank_scanline(&self) -> usize {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn pre_render_scanline(&self) -> usize {
+        self.scanlines_per_frame() - 1
+    }
+
+    /// Only the NTSC PPU shortens the pre-render line on odd frames
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    pub fn noise_periods(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_NOISE_PERIODS,
+            Region::Pal => &PAL_NOISE_PERIODS,
+        }
+    }
+
+    pub fn dmc_rates(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_DMC_RATES,
+            Region::Pal => &PAL_DMC_RATES,
+        }
+    }
+
+    pub fn frame_counter_steps(&self) -> &'static [u32; 5] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_FRAME_STEPS,
+            Region::Pal => &PAL_FRAME_STEPS,
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Ntsc => write!(f, "NTSC"),
+            Region::Pal => write!(f, "PAL"),
+            Region::Dendy => write!(f, "Dendy"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_region_names() {
+        assert_eq!("pal".parse::<Region>(), Ok(Region::Pal));
+        assert_eq!("Dendy".parse::<Region>(), Ok(Region::Dendy));
+        assert!("secam".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn frame_lengths_match_frame_rate() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy].iter() {
+            let (dots, cycles) = region.ppu_clock_ratio();
+            let dots_per_frame = (region.scanlines_per_frame() * 341) as f64;
+            let frame_rate = region.cpu_clock_rate() * dots as f64 / cycles as f64 / dots_per_frame;
+
+            assert!((frame_rate - region.frame_rate()).abs() < 0.05);
+
+            let (numerator, denominator) = region.frame_rate_ratio();
+            let exact = numerator as f64 / denominator as f64;
+            assert!((exact - region.frame_rate()).abs() < 0.0001);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use gif::{DisposalMethod, Encoder, Repeat};
+
+use super::{
+    frame::{Frame, FRAME_HEIGHT, FRAME_WIDTH},
+    palette::Palette,
+};
+
+// The 64 colors without emphasis make up the global color table, followed by
+// the transparent entry used for unchanged pixels
+const TRANSPARENT: u8 = 64;
+
+// Colors a GIF frame can hold besides the transparent entry
+const MAX_COLORS: usize = 255;
+
+/// Writes an animated GIF of the PPU's frames. Every frame is stored with the
+/// exact colors of its 9 bit indices, so nothing is lost to quantization, and
+/// only the area that changed since the previous frame is encoded. Changes
+/// with more colors than a GIF frame holds are split over several frames
+/// that show together.
+pub struct GifRecorder<W: Write> {
+    encoder: Encoder<W>,
+    palette: Palette,
+    frame_skip: u64,
+    frame_rate: f64,
+    frames: u64,
+
+    previous: Option<Vec<u16>>,
+    // The last frame is held back until the next change so identical frames
+    // can extend its delay instead of being written again
+    pending: Option<(Vec<gif::Frame<'static>>, u64)>,
+}
+
+impl<W: Write> GifRecorder<W> {
+    /// Captures one frame out of every `frame_skip + 1`
+    pub fn new(writer: W, palette: &Palette, frame_rate: f64, frame_skip: u64) -> io::Result<Self> {
+        let mut global = Vec::with_capacity(65 * 3);
+        for index in 0..=64 {
+            let (r, g, b) = palette.color(index & 0x3F);
+            global.extend_from_slice(&[r, g, b]);
+        }
+
+        let mut encoder = Encoder::new(writer, FRAME_WIDTH as u16, FRAME_HEIGHT as u16, &global)
+            .map_err(io::Error::other)?;
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(io::Error::other)?;
+
+        Ok(GifRecorder {
+            encoder,
+            palette: palette.clone(),
+            frame_skip,
+            frame_rate,
+            frames: 0,
+            previous: None,
+            pending: None,
+        })
+    }
+
+    pub fn add_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let number = self.frames;
+        self.frames += 1;
+        if !number.is_multiple_of(self.frame_skip + 1) {
+            return Ok(());
+        }
+
+        let (left, top, right, bottom) = match &self.previous {
+            None => (0, 0, FRAME_WIDTH, FRAME_HEIGHT),
+            Some(previous) => match changed_area(previous, &frame.data) {
+                Some(area) => area,
+                None => return Ok(()),
+            },
+        };
+
+        self.flush(number)?;
+
+        let mut parts = Vec::new();
+        self.encode(frame, (left, top, right, bottom), &mut parts);
+        self.pending = Some((parts, number));
+        self.previous = Some(frame.data.to_vec());
+
+        Ok(())
+    }
+
+    /// Encodes an area of the frame, halving it until each part fits in a
+    /// GIF frame's color table
+    fn encode(
+        &self,
+        frame: &Frame,
+        area: (usize, usize, usize, usize),
+        parts: &mut Vec<gif::Frame<'static>>,
+    ) {
+        if let Some(part) = self.encode_area(frame, area) {
+            parts.push(part);
+            return;
+        }
+
+        let (left, top, right, bottom) = area;
+        let (first, second) = if bottom - top > 1 {
+            let middle = (top + bottom) / 2;
+            ((left, top, right, middle), (left, middle, right, bottom))
+        } else {
+            let middle = (left + right) / 2;
+            ((left, top, middle, bottom), (middle, top, right, bottom))
+        };
+        self.encode(frame, first, parts);
+        self.encode(frame, second, parts);
+    }
+
+    /// Encodes an area as one GIF frame, or None if it has too many colors
+    fn encode_area(
+        &self,
+        frame: &Frame,
+        (left, top, right, bottom): (usize, usize, usize, usize),
+    ) -> Option<gif::Frame<'static>> {
+        let mut colors: HashMap<u16, u8> = HashMap::new();
+        let mut buffer = Vec::with_capacity((right - left) * (bottom - top));
+
+        for y in top..bottom {
+            for x in left..right {
+                let i = y * FRAME_WIDTH + x;
+                let index = frame.data[i];
+
+                if self.previous.as_ref().map(|p| p[i]) == Some(index) {
+                    buffer.push(TRANSPARENT);
+                    continue;
+                }
+
+                if colors.len() == MAX_COLORS && !colors.contains_key(&index) {
+                    return None;
+                }
+
+                // Slots skip the transparent entry
+                let next = match colors.len() {
+                    n if n >= TRANSPARENT as usize => n + 1,
+                    n => n,
+                };
+                buffer.push(*colors.entry(index).or_insert(next as u8));
+            }
+        }
+
+        let mut gif_frame = gif::Frame {
+            left: left as u16,
+            top: top as u16,
+            width: (right - left) as u16,
+            height: (bottom - top) as u16,
+            dispose: DisposalMethod::Keep,
+            transparent: Some(TRANSPARENT),
+            ..gif::Frame::default()
+        };
+
+        if colors.keys().all(|&index| index < 64) {
+            // Plain colors index straight into the global color table
+            let mut remap = vec![0; colors.len()];
+            for (&index, &slot) in colors.iter() {
+                remap[slot as usize] = index as u8;
+            }
+            for value in buffer.iter_mut().filter(|value| **value != TRANSPARENT) {
+                *value = remap[*value as usize];
+            }
+        } else {
+            gif_frame.palette = Some(self.local_palette(&colors));
+        }
+
+        gif_frame.buffer = Cow::Owned(buffer);
+        Some(gif_frame)
+    }
+
+    /// Builds a color table for frames using emphasis. The transparent entry
+    /// stays at index 64, so the table always has at least 65 entries.
+    fn local_palette(&self, colors: &HashMap<u16, u8>) -> Vec<u8> {
+        let size = colors
+            .values()
+            .map(|&slot| slot as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let size = size.max(TRANSPARENT as usize + 1);
+        let mut table = vec![0; size * 3];
+
+        for (&index, &slot) in colors.iter() {
+            let (r, g, b) = self.palette.color(index);
+            table[slot as usize * 3..slot as usize * 3 + 3].copy_from_slice(&[r, g, b]);
+        }
+
+        table
+    }
+
+    /// Writes the held back frame, with its delay running up to `until`. The
+    /// parts of a split frame show at once, so only the last one waits.
+    fn flush(&mut self, until: u64) -> io::Result<()> {
+        if let Some((mut parts, start)) = self.pending.take() {
+            // Round the timestamps rather than the durations so rounding
+            // errors don't accumulate over a long clip
+            let centiseconds = |frame: u64| (frame as f64 * 100.0 / self.frame_rate).round() as u64;
+            if let Some(last) = parts.last_mut() {
+                last.delay =
+                    (centiseconds(until) - centiseconds(start)).min(u16::MAX as u64) as u16;
+            }
+
+            for part in parts.iter() {
+                self.encoder.write_frame(part).map_err(io::Error::other)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last frame and the GIF trailer
+    pub fn finish(mut self) -> io::Result<W> {
+        let end = self.frames;
+        self.flush(end)?;
+        self.encoder.into_inner()
+    }
+}
+
+/// Bounding box (left, top, right, bottom) of the pixels that differ
+fn changed_area(previous: &[u16], current: &[u16]) -> Option<(usize, usize, usize, usize)> {
+    let mut area: Option<(usize, usize, usize, usize)> = None;
+
+    for (i, (a, b)) in previous.iter().zip(current.iter()).enumerate() {
+        if a != b {
+            let (x, y) = (i % FRAME_WIDTH, i / FRAME_WIDTH);
+            area = Some(match area {
+                None => (x, y, x + 1, y + 1),
+                Some((left, top, right, bottom)) => {
+                    (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1))
+                }
+            });
+        }
+    }
+
+    area
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(data: &[u8]) -> Vec<(gif::Frame<'static>, Vec<u8>)> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(data).unwrap();
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.clone(), frame.buffer.to_vec()));
+        }
+        frames
+    }
+
+    #[test]
+    fn encodes_only_changes() {
+        let palette = Palette::default();
+        let mut recorder = GifRecorder::new(Vec::new(), &palette, 50.0, 0).unwrap();
+
+        let mut frame = Frame::new();
+        recorder.add_frame(&frame).unwrap();
+        recorder.add_frame(&frame).unwrap();
+        frame.set_pixel(10, 20, 0x16);
+        frame.set_pixel(12, 21, 0x16 | 0b001 << 6);
+        recorder.add_frame(&frame).unwrap();
+
+        let frames = decode(&recorder.finish().unwrap());
+        assert_eq!(frames.len(), 2);
+
+        // The unchanged second frame extends the first one's delay
+        assert_eq!(frames[0].0.delay, 4);
+        assert_eq!((frames[1].0.left, frames[1].0.top), (10, 20));
+        assert_eq!((frames[1].0.width, frames[1].0.height), (3, 2));
+
+        // Emphasized colors are kept exactly through a local color table
+        let (r, g, b) = palette.color(0x16 | 0b001 << 6);
+        assert_eq!(&frames[1].1[(3 + 2) * 4..(3 + 2) * 4 + 3], &[r, g, b]);
+    }
+
+    #[test]
+    fn splits_frames_with_too_many_colors() {
+        let palette = Palette::default();
+        let mut recorder = GifRecorder::new(Vec::new(), &palette, 50.0, 0).unwrap();
+
+        let mut frame = Frame::new();
+        recorder.add_frame(&frame).unwrap();
+        // 299 distinct colors, more than fit in one GIF frame
+        let color = |i: usize| i as u16;
+        for i in 1..300 {
+            frame.set_pixel(i % FRAME_WIDTH, i / FRAME_WIDTH, color(i));
+        }
+        recorder.add_frame(&frame).unwrap();
+
+        let frames = decode(&recorder.finish().unwrap());
+        let (last, parts) = frames[1..].split_last().unwrap();
+        assert!(!parts.is_empty());
+        assert_eq!(last.0.delay, 2);
+        assert!(parts.iter().all(|(part, _)| part.delay == 0));
+
+        for (part, buffer) in frames[1..].iter() {
+            for (n, rgba) in buffer.chunks_exact(4).enumerate() {
+                let x = part.left as usize + n % part.width as usize;
+                let y = part.top as usize + n / part.width as usize;
+                let i = y * FRAME_WIDTH + x;
+                if rgba[3] != 0 {
+                    let (r, g, b) = palette.color(color(i));
+                    assert_eq!(&rgba[..3], &[r, g, b], "pixel {}", i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skips_frames() {
+        let palette = Palette::default();
+        let mut recorder = GifRecorder::new(Vec::new(), &palette, 60.0, 2).unwrap();
+
+        let mut frame = Frame::new();
+        for i in 0..6 {
+            frame.set_pixel(0, 0, i);
+            recorder.add_frame(&frame).unwrap();
+        }
+
+        let frames = decode(&recorder.finish().unwrap());
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].0.delay, 5);
+        assert_eq!(frames[0].1[..3], {
+            let (r, g, b) = palette.color(0);
+            [r, g, b]
+        });
+    }
+}
@@ -0,0 +1,136 @@
+use std::{fmt, str::FromStr};
+
+use super::image::Image;
+
+pub const DEFAULT_DECAY: f32 = 0.5;
+
+/// How consecutive frames are combined to hide the flicker games use to show
+/// more sprites than the PPU can draw on a line
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlendMode {
+    Off,
+    Mix,   // Average of this frame and the last
+    Max,   // Brightest of this frame and the last
+    Ghost, // Trails fading by the decay each frame
+}
+
+impl BlendMode {
+    pub fn next(&self) -> BlendMode {
+        match self {
+            BlendMode::Off => BlendMode::Mix,
+            BlendMode::Mix => BlendMode::Max,
+            BlendMode::Max => BlendMode::Ghost,
+            BlendMode::Ghost => BlendMode::Off,
+        }
+    }
+}
+
+impl FromStr for BlendMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(BlendMode::Off),
+            "mix" => Ok(BlendMode::Mix),
+            "max" => Ok(BlendMode::Max),
+            "ghost" => Ok(BlendMode::Ghost),
+            _ => Err(format!("Unknown blend mode {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BlendMode::Off => "off",
+            BlendMode::Mix => "mix",
+            BlendMode::Max => "max",
+            BlendMode::Ghost => "ghost",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Blends each converted image with the ones before it
+pub struct FrameBlender {
+    pub mode: BlendMode,
+    pub decay: f32, // Share of the trail kept each frame in ghost mode
+    // The last unblended image, or the last output when ghosting
+    previous: Option<Image>,
+}
+
+impl FrameBlender {
+    pub fn new(mode: BlendMode, decay: f32) -> Self {
+        FrameBlender {
+            mode,
+            decay: decay.clamp(0.0, 1.0),
+            previous: None,
+        }
+    }
+
+    pub fn apply(&mut self, image: &mut Image) {
+        let previous = match &mut self.previous {
+            Some(previous) if previous.data.len() == image.data.len() => previous,
+            _ => {
+                // Nothing to blend with yet, or the image size changed
+                self.previous = Some(image.clone());
+                return;
+            }
+        };
+
+        for (pixel, previous) in image.data.iter_mut().zip(previous.data.iter_mut()) {
+            let current = *pixel;
+            match self.mode {
+                BlendMode::Off => *previous = current,
+                BlendMode::Mix => {
+                    *pixel = (current as u16 + *previous as u16).div_ceil(2) as u8;
+                    *previous = current;
+                }
+                BlendMode::Max => {
+                    *pixel = current.max(*previous);
+                    *previous = current;
+                }
+                BlendMode::Ghost => {
+                    let trail = *previous as f32 * self.decay;
+                    *pixel = (current as f32 * (1.0 - self.decay) + trail).round() as u8;
+                    *previous = *pixel;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs single pixel images through the blender
+    fn blend(mode: BlendMode, values: &[u8]) -> Vec<u8> {
+        let mut blender = FrameBlender::new(mode, DEFAULT_DECAY);
+
+        values
+            .iter()
+            .map(|&value| {
+                let mut image = Image::new(1, 1);
+                image.set_pixel(0, 0, (value, value, value));
+                blender.apply(&mut image);
+                image.pixel(0, 0).0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn blends_flickering_pixels() {
+        let flicker = [200, 0, 200, 0];
+
+        assert_eq!(blend(BlendMode::Off, &flicker), flicker);
+        assert_eq!(blend(BlendMode::Mix, &flicker), [200, 100, 100, 100]);
+        assert_eq!(blend(BlendMode::Max, &flicker), [200, 200, 200, 200]);
+        assert_eq!(blend(BlendMode::Ghost, &flicker), [200, 100, 150, 75]);
+    }
+
+    #[test]
+    fn ghost_trails_fade() {
+        assert_eq!(blend(BlendMode::Ghost, &[255, 0, 0, 0]), [255, 128, 64, 32]);
+    }
+}
@@ -0,0 +1,376 @@
+use std::{fmt, fs, io, path::Path};
+
+use super::{image::Image, palette::Palette};
+use crate::cartridge::rom::NESRom;
+use crate::ppu::{attribute_location, pattern_pixel, Ppu};
+
+const VIEWPORT_COLOR: (u8, u8, u8) = (0xFF, 0x00, 0xFF);
+
+// Each sprite in the OAM view gets a cell big enough for 8x16 sprites
+const SPRITE_CELL_WIDTH: usize = 16;
+const SPRITE_CELL_HEIGHT: usize = 20;
+const SWATCH_SIZE: usize = 16;
+
+/// Renders all four nametables, as seen through the cartridge's mirroring,
+/// with the scroll viewport outlined
+pub fn nametables(ppu: &Ppu, cartridge: &NESRom, palette: &Palette) -> Image {
+    let mut image = Image::new(512, 480);
+    let bank = ppu.ctrl.background_pattern_address();
+
+    for table in 0..4 {
+        let (left, top) = ((table & 1) * 256, (table >> 1) * 240);
+
+        for row in 0..30 {
+            for column in 0..32 {
+                // Laid out like the scroll address the renderer fetches with
+                let address = (table << 10 | row << 5 | column) as u16;
+                let tile = cartridge.ppu_peek(0x2000 | address, &ppu.vram);
+                let (location, shift) = attribute_location(address);
+                let attribute = cartridge.ppu_peek(location, &ppu.vram) >> shift & 0b11;
+                let colors = palette_colors(ppu, palette, attribute);
+
+                draw_tile(
+                    &mut image,
+                    &ppu.peek_tile(cartridge, bank + tile as u16 * 16),
+                    left + column * 8,
+                    top + row * 8,
+                    &colors,
+                    false,
+                );
+            }
+        }
+    }
+
+    // The viewport wraps around the edges just like scrolling does
+    let (x, y) = ppu.scroll();
+    for offset in 0..256 {
+        image.set_pixel((x + offset) % 512, y, VIEWPORT_COLOR);
+        image.set_pixel((x + offset) % 512, (y + 239) % 480, VIEWPORT_COLOR);
+    }
+    for offset in 0..240 {
+        image.set_pixel(x, (y + offset) % 480, VIEWPORT_COLOR);
+        image.set_pixel((x + 255) % 512, (y + offset) % 480, VIEWPORT_COLOR);
+    }
+
+    image
+}
+
+/// Renders both 4 KiB pattern table banks side by side using one of the eight
+/// palettes (0-3 background, 4-7 sprites)
+pub fn pattern_tables(
+    ppu: &Ppu,
+    cartridge: &NESRom,
+    palette: &Palette,
+    palette_index: usize,
+) -> Image {
+    let mut image = Image::new(256, 128);
+    let colors = palette_colors(ppu, palette, palette_index as u8 & 0b111);
+
+    for tile in 0..512 {
+        let bank = tile / 256;
+        let x = bank * 128 + (tile % 16) * 8;
+        let y = (tile % 256) / 16 * 8;
+
+        draw_tile(
+            &mut image,
+            &ppu.peek_tile(cartridge, tile as u16 * 16),
+            x,
+            y,
+            &colors,
+            false,
+        );
+    }
+
+    image
+}
+
+/// Renders all 64 sprites in OAM order on a grid over the backdrop color
+pub fn sprites(ppu: &Ppu, cartridge: &NESRom, palette: &Palette) -> Image {
+    let mut image = Image::new(SPRITE_CELL_WIDTH * 8, SPRITE_CELL_HEIGHT * 8);
+    let backdrop = palette.color(ppu.palette_table[0] as u16 & 0x3F);
+    let tall = ppu.ctrl.sprite_size();
+
+    for y in 0..image.height {
+        for x in 0..image.width {
+            image.set_pixel(x, y, backdrop);
+        }
+    }
+
+    for sprite in sprite_list(ppu) {
+        let left = (sprite.index % 8) * SPRITE_CELL_WIDTH + 4;
+        let top = (sprite.index / 8) * SPRITE_CELL_HEIGHT + 2;
+        let colors = palette_colors(ppu, palette, 4 + sprite.palette as u8);
+
+        // 8x16 sprites take their bank from bit 0 of the tile index, and
+        // vertical flipping swaps the two halves
+        let tiles = if tall {
+            let address = (sprite.tile as u16 & 1) * 0x1000 + (sprite.tile as u16 & 0xFE) * 16;
+            if sprite.flip_vertical {
+                vec![address + 16, address]
+            } else {
+                vec![address, address + 16]
+            }
+        } else {
+            vec![ppu.ctrl.sprite_pattern_address() + sprite.tile as u16 * 16]
+        };
+
+        for (half, &address) in tiles.iter().enumerate() {
+            let mut tile = ppu.peek_tile(cartridge, address);
+            if sprite.flip_vertical {
+                tile[..8].reverse();
+                tile[8..].reverse();
+            }
+            if sprite.flip_horizontal {
+                for byte in tile.iter_mut() {
+                    *byte = byte.reverse_bits();
+                }
+            }
+
+            draw_tile(&mut image, &tile, left, top + half * 8, &colors, true);
+        }
+    }
+
+    image
+}
+
+/// Renders the 32 bytes of palette RAM as swatches, background palettes on
+/// the top row and sprite palettes below
+pub fn palette_ram(ppu: &Ppu, palette: &Palette) -> Image {
+    let mut image = Image::new(SWATCH_SIZE * 16, SWATCH_SIZE * 2);
+
+    for (i, &entry) in ppu.palette_table.iter().enumerate() {
+        let color = palette.color(entry as u16 & 0x3F);
+        let (left, top) = ((i % 16) * SWATCH_SIZE, (i / 16) * SWATCH_SIZE);
+
+        for y in 0..SWATCH_SIZE {
+            for x in 0..SWATCH_SIZE {
+                image.set_pixel(left + x, top + y, color);
+            }
+        }
+    }
+
+    image
+}
+
+/// A decoded OAM entry
+#[derive(Debug, PartialEq)]
+pub struct SpriteInfo {
+    pub index: usize,
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    pub palette: usize,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl fmt::Display for SpriteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{:02} x:{:3} y:{:3} tile:${:02X} palette:{} {} {}{}",
+            self.index,
+            self.x,
+            self.y,
+            self.tile,
+            self.palette,
+            if self.behind_background {
+                "back "
+            } else {
+                "front"
+            },
+            if self.flip_horizontal { 'H' } else { '-' },
+            if self.flip_vertical { 'V' } else { '-' },
+        )
+    }
+}
+
+pub fn sprite_list(ppu: &Ppu) -> Vec<SpriteInfo> {
+    ppu.oam_data
+        .chunks_exact(4)
+        .enumerate()
+        .map(|(index, oam)| SpriteInfo {
+            index,
+            y: oam[0],
+            tile: oam[1],
+            palette: (oam[2] & 0b11) as usize,
+            behind_background: oam[2] & 0x20 != 0,
+            flip_horizontal: oam[2] & 0x40 != 0,
+            flip_vertical: oam[2] & 0x80 != 0,
+            x: oam[3],
+        })
+        .collect()
+}
+
+/// All of the debug views on a single image for the debug window
+pub struct DebugViews {
+    pub pattern_palette: usize,
+}
+
+impl DebugViews {
+    pub const WIDTH: usize = 768;
+    pub const HEIGHT: usize = 480;
+
+    pub fn new() -> Self {
+        DebugViews { pattern_palette: 0 }
+    }
+
+    /// Cycles the palette used to draw the pattern tables
+    pub fn next_pattern_palette(&mut self) {
+        self.pattern_palette = (self.pattern_palette + 1) % 8;
+    }
+
+    /// Nametables on the left, with pattern tables, palette RAM and sprites
+    /// stacked on the right
+    pub fn compose(&self, ppu: &Ppu, cartridge: &NESRom, palette: &Palette) -> Image {
+        let mut image = Image::new(DebugViews::WIDTH, DebugViews::HEIGHT);
+
+        image.blit(&nametables(ppu, cartridge, palette), 0, 0);
+        image.blit(
+            &pattern_tables(ppu, cartridge, palette, self.pattern_palette),
+            512,
+            0,
+        );
+        image.blit(&palette_ram(ppu, palette), 512, 136);
+        image.blit(&sprites(ppu, cartridge, palette), 512, 176);
+
+        image
+    }
+
+    /// Writes each view to a PNG in the directory, along with a text listing
+    /// of OAM
+    pub fn dump(
+        &self,
+        ppu: &Ppu,
+        cartridge: &NESRom,
+        palette: &Palette,
+        directory: &str,
+    ) -> io::Result<()> {
+        let path = |name: &str| {
+            Path::new(directory)
+                .join(name)
+                .to_string_lossy()
+                .into_owned()
+        };
+        fs::create_dir_all(directory)?;
+
+        nametables(ppu, cartridge, palette).save_png(&path("nametables.png"))?;
+        pattern_tables(ppu, cartridge, palette, self.pattern_palette)
+            .save_png(&path("pattern_tables.png"))?;
+        palette_ram(ppu, palette).save_png(&path("palette.png"))?;
+        sprites(ppu, cartridge, palette).save_png(&path("sprites.png"))?;
+
+        let listing: Vec<String> = sprite_list(ppu).iter().map(|s| s.to_string()).collect();
+        fs::write(path("oam.txt"), listing.join("\n") + "\n")
+    }
+}
+
+fn palette_colors(ppu: &Ppu, palette: &Palette, index: u8) -> [(u8, u8, u8); 4] {
+    let mut colors = [(0, 0, 0); 4];
+    for (color, &entry) in colors.iter_mut().zip(ppu.tile_palette(index).iter()) {
+        *color = palette.color(entry as u16);
+    }
+    colors
+}
+
+fn draw_tile(
+    image: &mut Image,
+    tile: &[u8; 16],
+    left: usize,
+    top: usize,
+    colors: &[(u8, u8, u8); 4],
+    transparent: bool,
+) {
+    for y in 0..8 {
+        for x in 0..8 {
+            let value = pattern_pixel(tile[y], tile[y + 8], x);
+
+            if value != 0 || !transparent {
+                image.set_pixel(left + x, top + y, colors[value as usize]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::region::Region;
+
+    fn cartridge() -> NESRom {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 0];
+        data.resize(16 + 0x4000, 0);
+
+        NESRom::new(&mut Cursor::new(data)).unwrap()
+    }
+
+    fn setup() -> (Ppu, NESRom, Palette) {
+        let mut cartridge = cartridge();
+        let mut ppu = Ppu::new(Region::Ntsc);
+
+        // Tile 1 is solid color 1
+        for address in 0x10..0x18 {
+            cartridge.ppu_write(address, 0xFF, &mut ppu.vram);
+        }
+        ppu.palette_table[0] = 0x0F;
+        ppu.palette_table[1] = 0x30;
+        ppu.palette_table[0x11] = 0x16;
+
+        (ppu, cartridge, Palette::default())
+    }
+
+    #[test]
+    fn nametables_follow_mirroring() {
+        let (mut ppu, cartridge, palette) = setup();
+        ppu.vram[33] = 1;
+
+        let image = nametables(&ppu, &cartridge, &palette);
+
+        let white = palette.color(0x30);
+        assert_eq!(image.pixel(9, 9), white);
+        // Horizontal mirroring repeats the first nametable on the right
+        assert_eq!(image.pixel(256 + 9, 9), white);
+        assert_eq!(image.pixel(9, 240 + 9), palette.color(0x0F));
+    }
+
+    #[test]
+    fn nametables_outline_the_viewport() {
+        let (mut ppu, mut cartridge, palette) = setup();
+        ppu.write(0x2005, 16, &mut cartridge);
+        ppu.write(0x2005, 8, &mut cartridge);
+
+        let image = nametables(&ppu, &cartridge, &palette);
+
+        assert_eq!(image.pixel(16, 8), VIEWPORT_COLOR);
+        assert_eq!(image.pixel(16 + 255, 8 + 239), VIEWPORT_COLOR);
+        assert_ne!(image.pixel(15, 7), VIEWPORT_COLOR);
+    }
+
+    #[test]
+    fn sprites_are_listed_and_drawn() {
+        let (mut ppu, cartridge, palette) = setup();
+        ppu.oam_data[4..8].copy_from_slice(&[10, 1, 0b0110_0000, 20]);
+
+        let list = sprite_list(&ppu);
+        assert_eq!(list.len(), 64);
+        assert_eq!(list[1].x, 20);
+        assert!(list[1].behind_background && list[1].flip_horizontal);
+        assert!(list[1].to_string().starts_with("#01 x: 20 y: 10 tile:$01"));
+
+        let image = sprites(&ppu, &cartridge, &palette);
+        assert_eq!(image.pixel(SPRITE_CELL_WIDTH + 4, 2), palette.color(0x16));
+    }
+
+    #[test]
+    fn palette_ram_swatches() {
+        let (ppu, _, palette) = setup();
+
+        let image = palette_ram(&ppu, &palette);
+
+        assert_eq!(image.pixel(SWATCH_SIZE, 0), palette.color(0x30));
+        assert_eq!(image.pixel(SWATCH_SIZE, SWATCH_SIZE), palette.color(0x16));
+    }
+}
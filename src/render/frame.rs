@@ -1,25 +1,97 @@
-const FRAME_WIDTH: usize = 256;
-const FRAME_HEIGHT: usize = 240;
+pub const FRAME_WIDTH: usize = 256;
+pub const FRAME_HEIGHT: usize = 240;
 
-const FRAME_SIZE: usize = FRAME_WIDTH * FRAME_HEIGHT * 3;
+const FRAME_SIZE: usize = FRAME_WIDTH * FRAME_HEIGHT;
 
+/// Identifies a tile's graphics: its index in CHR-ROM, or its pattern data
+/// when the board has CHR-RAM
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TileId {
+    Rom(u32),
+    Ram([u8; 16]),
+}
+
+/// The tile a layer drew a pixel from
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TileSource {
+    pub tile: TileId,
+    pub palette: [u8; 4], // Palette entries, with the backdrop first
+    pub column: u8,       // Position of the pixel in the tile's pattern
+    pub row: u8,
+    pub pixel: u8, // Pattern value, 0 being transparent
+    pub h_flip: bool,
+    pub v_flip: bool,
+    pub behind: bool, // Sprite drawn behind the background
+}
+
+impl TileSource {
+    /// Screen position of the tile's top left corner, given where the pixel is
+    pub fn origin(&self, x: usize, y: usize) -> (isize, isize) {
+        let column = if self.h_flip {
+            7 - self.column
+        } else {
+            self.column
+        };
+        let row = if self.v_flip { 7 - self.row } else { self.row };
+
+        (x as isize - column as isize, y as isize - row as isize)
+    }
+}
+
+/// What the background and sprite layers had at a pixel
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct PixelSource {
+    pub background: Option<TileSource>,
+    pub sprite: Option<TileSource>, // The front-most opaque sprite pixel
+}
+
+/// A frame as emitted by the PPU. Each pixel is a 9 bit color index: the 6 bit
+/// palette entry in the low bits and the three emphasis bits from PPUMASK in
+/// bits 6-8. Use a `Palette` to turn it into RGB.
 pub struct Frame {
-    pub data: [u8; FRAME_SIZE],
+    pub data: [u16; FRAME_SIZE],
+    // Where each pixel came from, only tracked when something needs it
+    pub sources: Option<Vec<PixelSource>>,
 }
 
 impl Frame {
     pub fn new() -> Self {
         Frame {
             data: [0; FRAME_SIZE],
+            sources: None,
         }
     }
 
-    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
-        let base = y * 3 * FRAME_WIDTH + x * 3;
-        if base + 2 < self.data.len() {
-            self.data[base] = rgb.0;
-            self.data[base + 1] = rgb.1;
-            self.data[base + 2] = rgb.2;
+    /// Starts tracking which tile produced each pixel
+    pub fn track_sources(&mut self) {
+        self.sources
+            .get_or_insert_with(|| vec![PixelSource::default(); FRAME_SIZE]);
+    }
+
+    pub fn source(&self, x: usize, y: usize) -> Option<&PixelSource> {
+        match &self.sources {
+            Some(sources) if x < FRAME_WIDTH && y < FRAME_HEIGHT => {
+                Some(&sources[y * FRAME_WIDTH + x])
+            }
+            _ => None,
+        }
+    }
+
+    pub fn set_source(&mut self, x: usize, y: usize, source: PixelSource) {
+        if let Some(sources) = &mut self.sources {
+            if x < FRAME_WIDTH && y < FRAME_HEIGHT {
+                sources[y * FRAME_WIDTH + x] = source;
+            }
         }
     }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, index: u16) {
+        if x < FRAME_WIDTH && y < FRAME_HEIGHT {
+            self.data[y * FRAME_WIDTH + x] = index;
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.data[y * FRAME_WIDTH + x]
+    }
 }
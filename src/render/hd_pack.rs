@@ -0,0 +1,565 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader, ErrorKind},
+    path::Path,
+};
+
+use self::condition::{parse_palette, parse_tile, Condition, Context};
+use super::{
+    frame::{Frame, TileId, TileSource, FRAME_HEIGHT, FRAME_WIDTH},
+    image::Image,
+    palette::Palette,
+};
+
+pub(crate) mod condition;
+
+/// A PNG sheet of replacement graphics, as RGBA
+pub struct Sheet {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Sheet {
+    pub fn from_file(filename: &Path) -> io::Result<Sheet> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(filename)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(io::Error::other)?;
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(io::Error::other)?;
+        let pixels = &buffer[..info.buffer_size()];
+
+        let data = match info.color_type {
+            png::ColorType::Rgba => pixels.to_vec(),
+            png::ColorType::Rgb => pixels
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => pixels
+                .chunks_exact(2)
+                .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            _ => pixels.iter().flat_map(|&g| [g, g, g, 0xFF]).collect(),
+        };
+
+        Ok(Sheet {
+            width: info.width as usize,
+            height: info.height as usize,
+            data,
+        })
+    }
+
+    /// RGBA at a position, transparent outside the sheet
+    fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        if x >= self.width || y >= self.height {
+            return [0; 4];
+        }
+
+        let base = (y * self.width + x) * 4;
+        [
+            self.data[base],
+            self.data[base + 1],
+            self.data[base + 2],
+            self.data[base + 3],
+        ]
+    }
+}
+
+/// A condition as used on a replacement line, possibly negated
+#[derive(Debug, Clone, PartialEq)]
+struct Check {
+    condition: Condition,
+    negate: bool,
+}
+
+fn all_pass(checks: &[Check], context: &Context) -> bool {
+    checks
+        .iter()
+        .all(|check| check.condition.check(context) != check.negate)
+}
+
+/// Tiles are matched by their graphics and palette
+type TileKey = (TileId, [u8; 4]);
+
+#[derive(Debug, Clone, PartialEq)]
+struct HdTile {
+    sheet: usize,
+    x: usize,
+    y: usize,
+    brightness: f32,
+    checks: Vec<Check>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct HdBackground {
+    sheet: usize,
+    brightness: f32,
+    left: usize,
+    top: usize,
+    checks: Vec<Check>,
+}
+
+/// A Mesen format HD pack: a `hires.txt` describing replacements for 8x8
+/// tiles, matched by their graphics and palette, with higher resolution
+/// artwork from PNG sheets. Replacements and background images may depend on
+/// conditions about nearby tiles, memory and the frame number.
+///
+/// Background images are drawn behind the background layer without
+/// parallax scrolling. `<bgm>` and `<sfx>` sound replacements are skipped,
+/// as there's no way to decode and mix in OGG files.
+pub struct HdPack {
+    scale: usize,
+    sheets: Vec<Sheet>,
+    tiles: HashMap<TileKey, Vec<HdTile>>,
+    // Tiles marked as the default for any palette without its own match
+    default_tiles: HashMap<TileId, Vec<HdTile>>,
+    backgrounds: Vec<HdBackground>,
+    addresses: Vec<u16>,
+    memory: HashMap<u16, u8>,
+}
+
+impl HdPack {
+    /// Loads `hires.txt` and the images it names from a pack's directory
+    pub fn load(directory: &str) -> io::Result<HdPack> {
+        let directory = Path::new(directory);
+        let text = fs::read_to_string(directory.join("hires.txt"))?;
+
+        HdPack::parse(&text, |name| Sheet::from_file(&directory.join(name)))
+    }
+
+    /// Parses a `hires.txt`, loading images through `load_sheet`
+    pub fn parse<F>(text: &str, mut load_sheet: F) -> io::Result<HdPack>
+    where
+        F: FnMut(&str) -> io::Result<Sheet>,
+    {
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+
+        let mut pack = HdPack {
+            scale: 1,
+            sheets: Vec::new(),
+            tiles: HashMap::new(),
+            default_tiles: HashMap::new(),
+            backgrounds: Vec::new(),
+            addresses: Vec::new(),
+            memory: HashMap::new(),
+        };
+        // Packs without a <ver> line are from before versioning
+        let mut version = 0;
+        let mut conditions: HashMap<String, Condition> = HashMap::new();
+        let mut background_sheets: HashMap<String, usize> = HashMap::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // Replacements may start with conditions: [a&!b]<tile>...
+            let (checks, line) = match line.strip_prefix('[') {
+                Some(rest) => {
+                    let end = rest
+                        .find(']')
+                        .ok_or_else(|| invalid(format!("Unclosed conditions in {}", line)))?;
+                    let checks = parse_checks(&rest[..end], &conditions).map_err(invalid)?;
+                    (checks, &rest[end + 1..])
+                }
+                None => (Vec::new(), line),
+            };
+
+            let (tag, value) = match line.strip_prefix('<').and_then(|rest| rest.split_once('>')) {
+                Some((tag, value)) => (tag, value.trim()),
+                None => return Err(invalid(format!("Invalid line {}", line))),
+            };
+            let args: Vec<&str> = value.split(',').map(str::trim).collect();
+
+            match tag {
+                "ver" => version = value.parse().map_err(|_| invalid(line.to_string()))?,
+                "scale" => match value.parse() {
+                    Ok(scale @ 1..=10) => pack.scale = scale,
+                    _ => return Err(invalid(format!("Invalid scale {}", value))),
+                },
+                "img" => pack.sheets.push(load_sheet(value)?),
+                "condition" => {
+                    if args.len() < 2 {
+                        return Err(invalid(format!("Invalid condition {}", value)));
+                    }
+                    let condition =
+                        Condition::parse(args[1], &args[2..], version).map_err(invalid)?;
+                    conditions.insert(args[0].to_string(), condition);
+                }
+                "tile" => {
+                    let (key, default, tile) =
+                        parse_tile_line(&args, version, checks).map_err(invalid)?;
+                    if tile.sheet >= pack.sheets.len() {
+                        return Err(invalid(format!("Unknown image in {}", value)));
+                    }
+
+                    if default {
+                        pack.default_tiles
+                            .entry(key.0)
+                            .or_default()
+                            .push(tile.clone());
+                    }
+                    pack.tiles.entry(key).or_default().push(tile);
+                }
+                "background" => {
+                    let sheet = match background_sheets.get(args[0]) {
+                        Some(&sheet) => sheet,
+                        None => {
+                            pack.sheets.push(load_sheet(args[0])?);
+                            background_sheets.insert(args[0].to_string(), pack.sheets.len() - 1);
+                            pack.sheets.len() - 1
+                        }
+                    };
+                    let number = |index: usize| args.get(index).and_then(|arg| arg.parse().ok());
+
+                    pack.backgrounds.push(HdBackground {
+                        sheet,
+                        brightness: number(1).unwrap_or(1.0),
+                        left: number(5).map(|left: f32| left as usize).unwrap_or(0),
+                        top: number(6).map(|top: f32| top as usize).unwrap_or(0),
+                        checks,
+                    });
+                }
+                // Sound replacements and options this renderer doesn't use
+                _ => (),
+            }
+        }
+
+        // Conditional replacements take precedence over unconditional ones
+        for tiles in pack
+            .tiles
+            .values_mut()
+            .chain(pack.default_tiles.values_mut())
+        {
+            tiles.sort_by_key(|tile| tile.checks.is_empty());
+        }
+
+        let addresses: HashSet<u16> = conditions
+            .values()
+            .flat_map(|condition| condition.addresses())
+            .collect();
+        pack.addresses = addresses.into_iter().collect();
+
+        Ok(pack)
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    /// Reads the memory that conditions check. Call once per frame, before
+    /// the emulator runs it.
+    pub fn update_memory<F: Fn(u16) -> u8>(&mut self, read: F) {
+        for &address in self.addresses.iter() {
+            self.memory.insert(address, read(address));
+        }
+    }
+
+    fn find_tile(&self, context: &Context) -> Option<&HdTile> {
+        let tile = context.tile?;
+        let exact = self.tiles.get(&(tile.tile, tile.palette));
+        let default = self.default_tiles.get(&tile.tile);
+
+        exact
+            .into_iter()
+            .chain(default)
+            .flatten()
+            .find(|hd| all_pass(&hd.checks, context))
+    }
+
+    /// Renders a frame at the pack's scale. The frame must have been drawn
+    /// with pixel sources tracked.
+    pub fn render(&self, frame: &Frame, frame_number: u64, palette: &Palette, output: &mut Image) {
+        let scale = self.scale;
+        if output.width != FRAME_WIDTH * scale || output.height != FRAME_HEIGHT * scale {
+            *output = Image::new(FRAME_WIDTH * scale, FRAME_HEIGHT * scale);
+        }
+
+        let context = Context {
+            frame,
+            frame_number,
+            memory: &self.memory,
+            x: 0,
+            y: 0,
+            tile: None,
+        };
+        let background = self
+            .backgrounds
+            .iter()
+            .find(|background| all_pass(&background.checks, &context));
+
+        let mut block = vec![[0.0f32; 3]; scale * scale];
+
+        for y in 0..FRAME_HEIGHT {
+            for x in 0..FRAME_WIDTH {
+                let index = frame.pixel(x, y);
+                let source = frame.source(x, y).copied().unwrap_or_default();
+                let emphasis = index & 0x1C0;
+                let layers = [source.background, source.sprite];
+
+                // The backdrop, or whatever the PPU showed with rendering off
+                let backdrop = match layers.iter().flatten().next() {
+                    Some(tile) => tile.palette[0] as u16 | emphasis,
+                    None => index,
+                };
+                let (r, g, b) = palette.color(backdrop);
+                block
+                    .iter_mut()
+                    .for_each(|pixel| *pixel = [r as f32, g as f32, b as f32]);
+
+                if let Some(background) = background {
+                    let sheet = &self.sheets[background.sheet];
+                    for (i, pixel) in block.iter_mut().enumerate() {
+                        let rgba = sheet.pixel(
+                            background.left + x * scale + i % scale,
+                            background.top + y * scale + i / scale,
+                        );
+                        blend(pixel, rgba, background.brightness);
+                    }
+                }
+
+                let sprite_behind = source.sprite.filter(|sprite| sprite.behind);
+                let sprite_front = source.sprite.filter(|sprite| !sprite.behind);
+
+                for tile in [sprite_behind, source.background, sprite_front]
+                    .iter()
+                    .flatten()
+                {
+                    let context = Context {
+                        x,
+                        y,
+                        tile: Some(tile),
+                        ..context
+                    };
+                    self.draw_tile(&context, tile, emphasis, palette, &mut block);
+                }
+
+                for (i, pixel) in block.iter().enumerate() {
+                    output.set_pixel(
+                        x * scale + i % scale,
+                        y * scale + i / scale,
+                        (pixel[0] as u8, pixel[1] as u8, pixel[2] as u8),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Draws one layer's tile over a pixel's block, from its replacement when
+    /// there is one
+    fn draw_tile(
+        &self,
+        context: &Context,
+        tile: &TileSource,
+        emphasis: u16,
+        palette: &Palette,
+        block: &mut [[f32; 3]],
+    ) {
+        let scale = self.scale;
+
+        match self.find_tile(context) {
+            Some(hd) => {
+                let sheet = &self.sheets[hd.sheet];
+                for (i, pixel) in block.iter_mut().enumerate() {
+                    let (mut sx, mut sy) = (i % scale, i / scale);
+                    if tile.h_flip {
+                        sx = scale - 1 - sx;
+                    }
+                    if tile.v_flip {
+                        sy = scale - 1 - sy;
+                    }
+
+                    let rgba = sheet.pixel(
+                        hd.x + tile.column as usize * scale + sx,
+                        hd.y + tile.row as usize * scale + sy,
+                    );
+                    blend(pixel, rgba, hd.brightness);
+                }
+            }
+            None if tile.pixel != 0 => {
+                let (r, g, b) = palette.color(tile.palette[tile.pixel as usize] as u16 | emphasis);
+                block
+                    .iter_mut()
+                    .for_each(|pixel| *pixel = [r as f32, g as f32, b as f32]);
+            }
+            None => (),
+        }
+    }
+}
+
+/// Draws a replacement pixel over another with its alpha and brightness
+fn blend(pixel: &mut [f32; 3], rgba: [u8; 4], brightness: f32) {
+    let alpha = rgba[3] as f32 / 255.0;
+    for (channel, &value) in pixel.iter_mut().zip(rgba.iter()) {
+        let value = (value as f32 * brightness).min(255.0);
+        *channel += (value - *channel) * alpha;
+    }
+}
+
+fn parse_checks(text: &str, conditions: &HashMap<String, Condition>) -> Result<Vec<Check>, String> {
+    text.split('&')
+        .map(|name| {
+            let name = name.trim();
+            let (negate, name) = match name.strip_prefix('!') {
+                Some(name) => (true, name),
+                None => (false, name),
+            };
+
+            let condition = conditions
+                .get(name)
+                .cloned()
+                .or_else(|| Condition::built_in(name))
+                .ok_or_else(|| format!("Unknown condition {}", name))?;
+            Ok(Check { condition, negate })
+        })
+        .collect()
+}
+
+/// Parses `<tile>image,tile,palette,x,y,brightness,default`
+fn parse_tile_line(
+    args: &[&str],
+    version: u32,
+    checks: Vec<Check>,
+) -> Result<(TileKey, bool, HdTile), String> {
+    let invalid = || format!("Invalid tile {}", args.join(","));
+    if args.len() < 7 {
+        return Err(invalid());
+    }
+    let number = |text: &str| text.parse::<usize>().map_err(|_| invalid());
+
+    let key = (parse_tile(args[1], version)?, parse_palette(args[2])?);
+    let tile = HdTile {
+        sheet: number(args[0])?,
+        x: number(args[3])?,
+        y: number(args[4])?,
+        brightness: args[5].parse().map_err(|_| invalid())?,
+        checks,
+    };
+
+    Ok((key, args[6].eq_ignore_ascii_case("y"), tile))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::render::frame::PixelSource;
+
+    const PALETTE: [u8; 4] = [0x0F, 0x16, 0x27, 0x38];
+
+    /// A 32x16 sheet: a red 2x scaled tile, then a half transparent green one
+    fn sheet() -> Sheet {
+        let mut data = Vec::new();
+        for _ in 0..16 {
+            for x in 0..32 {
+                data.extend_from_slice(if x < 16 {
+                    &[255, 0, 0, 255]
+                } else {
+                    &[0, 255, 0, 128]
+                });
+            }
+        }
+        Sheet {
+            width: 32,
+            height: 16,
+            data,
+        }
+    }
+
+    fn pack(text: &str) -> HdPack {
+        HdPack::parse(text, |_| Ok(sheet())).unwrap()
+    }
+
+    fn frame_with(tile: u32, palette: [u8; 4]) -> Frame {
+        let mut frame = Frame::new();
+        frame.track_sources();
+        for y in 0..8 {
+            for x in 0..8 {
+                frame.set_pixel(x, y, palette[1] as u16);
+                frame.set_source(
+                    x,
+                    y,
+                    PixelSource {
+                        background: Some(TileSource {
+                            tile: TileId::Rom(tile),
+                            palette,
+                            column: x as u8,
+                            row: y as u8,
+                            pixel: 1,
+                            h_flip: false,
+                            v_flip: false,
+                            behind: false,
+                        }),
+                        sprite: None,
+                    },
+                );
+            }
+        }
+        frame
+    }
+
+    fn render(pack: &HdPack, frame: &Frame, frame_number: u64) -> Image {
+        let mut image = Image::new(0, 0);
+        pack.render(frame, frame_number, &Palette::default(), &mut image);
+        image
+    }
+
+    #[test]
+    fn replaces_matching_tiles() {
+        let pack = pack("<ver>106\n<scale>2\n<img>tiles.png\n<tile>0,1A,0F162738,0,0,1,N\n");
+        let image = render(&pack, &frame_with(0x1A, PALETTE), 0);
+
+        assert_eq!((image.width, image.height), (512, 480));
+        assert_eq!(image.pixel(0, 0), (255, 0, 0));
+        assert_eq!(image.pixel(15, 15), (255, 0, 0));
+        // Everything else is drawn as usual, scaled up
+        assert_eq!(image.pixel(16, 0), Palette::default().color(0));
+
+        // A different palette isn't replaced
+        let other = render(&pack, &frame_with(0x1A, [0x0F, 0x01, 0x02, 0x03]), 0);
+        assert_eq!(other.pixel(0, 0), Palette::default().color(0x01));
+    }
+
+    #[test]
+    fn default_tiles_match_any_palette_and_blend() {
+        // Unversioned packs number tiles in decimal
+        let pack = pack("<scale>2\n<img>tiles.png\n<tile>0,26,0F000000,16,0,1,Y\n");
+        let image = render(&pack, &frame_with(0x1A, PALETTE), 0);
+
+        // Half transparent green over the backdrop
+        let (r, g, b) = Palette::default().color(0x0F);
+        let mix =
+            |value: u8, over: f32| (value as f32 + (over - value as f32) * 128.0 / 255.0) as u8;
+        assert_eq!(image.pixel(3, 3), (mix(r, 0.0), mix(g, 255.0), mix(b, 0.0)));
+    }
+
+    #[test]
+    fn conditions_select_replacements() {
+        let pack = pack(
+            "<ver>106\n<scale>2\n<img>tiles.png\n\
+             <condition>odd,frameRange,2,1\n\
+             <condition>flag,memoryCheckConstant,0010,==,1\n\
+             [odd&!flag]<tile>0,1A,0F162738,16,0,1,N\n\
+             <tile>0,1A,0F162738,0,0,1,N\n",
+        );
+        let frame = frame_with(0x1A, PALETTE);
+
+        assert_eq!(render(&pack, &frame, 0).pixel(0, 0), (255, 0, 0));
+        assert_ne!(render(&pack, &frame, 1).pixel(0, 0), (255, 0, 0));
+
+        let mut pack = pack;
+        pack.update_memory(|address| if address == 0x10 { 1 } else { 0 });
+        assert_eq!(render(&pack, &frame, 1).pixel(0, 0), (255, 0, 0));
+    }
+
+    #[test]
+    fn rejects_invalid_packs() {
+        let parse = |text: &str| HdPack::parse(text, |_| Ok(sheet()));
+
+        assert!(parse("<scale>0").is_err());
+        assert!(parse("<tile>0,1A,0F162738,0,0,1,N").is_err());
+        assert!(parse("<img>a.png\n[missing]<tile>0,1A,0F162738,0,0,1,N").is_err());
+        assert!(parse("<img>a.png\n<bgm>0,0,song.ogg\n<options>disableSpriteLimit").is_ok());
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use crate::render::frame::{Frame, PixelSource, TileId, TileSource};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterOrEqual,
+    LessOrEqual,
+}
+
+impl Operator {
+    fn parse(text: &str) -> Result<Operator, String> {
+        match text {
+            "==" => Ok(Operator::Equal),
+            "!=" => Ok(Operator::NotEqual),
+            ">" => Ok(Operator::Greater),
+            "<" => Ok(Operator::Less),
+            ">=" => Ok(Operator::GreaterOrEqual),
+            "<=" => Ok(Operator::LessOrEqual),
+            _ => Err(format!("Unknown operator {}", text)),
+        }
+    }
+
+    fn compare(&self, a: u8, b: u8) -> bool {
+        match self {
+            Operator::Equal => a == b,
+            Operator::NotEqual => a != b,
+            Operator::Greater => a > b,
+            Operator::Less => a < b,
+            Operator::GreaterOrEqual => a >= b,
+            Operator::LessOrEqual => a <= b,
+        }
+    }
+}
+
+/// A test that decides whether a replacement applies
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    // A background or sprite tile at a screen position
+    TileAtPosition(usize, usize, TileId, [u8; 4]),
+    SpriteAtPosition(usize, usize, TileId, [u8; 4]),
+    // The same, at an offset from the tile being replaced
+    TileNearby(isize, isize, TileId, [u8; 4]),
+    SpriteNearby(isize, isize, TileId, [u8; 4]),
+    MemoryCheck(u16, Operator, u16, u8),
+    MemoryCheckConstant(u16, Operator, u8, u8),
+    // Frame number modulo the first value is at least the second
+    FrameRange(u64, u64),
+    HorizontalMirror,
+    VerticalMirror,
+    BackgroundPriority,
+}
+
+/// What conditions are checked against
+pub struct Context<'a> {
+    pub frame: &'a Frame,
+    pub frame_number: u64,
+    pub memory: &'a HashMap<u16, u8>,
+    pub x: usize,
+    pub y: usize,
+    pub tile: Option<&'a TileSource>, // None when choosing a background image
+}
+
+impl Condition {
+    /// The built-in conditions, which need no definition
+    pub fn built_in(name: &str) -> Option<Condition> {
+        match name {
+            "hmirror" => Some(Condition::HorizontalMirror),
+            "vmirror" => Some(Condition::VerticalMirror),
+            "bgpriority" => Some(Condition::BackgroundPriority),
+            _ => None,
+        }
+    }
+
+    /// Parses the type and arguments of a `<condition>` line
+    pub fn parse(kind: &str, args: &[&str], version: u32) -> Result<Condition, String> {
+        let invalid = || format!("Invalid {} condition {}", kind, args.join(","));
+        let number = |text: &str| text.trim().parse::<isize>().map_err(|_| invalid());
+        let hex = |text: &str| u16::from_str_radix(text.trim(), 16).map_err(|_| invalid());
+        let mask = |index: usize| match args.get(index) {
+            Some(text) => hex(text).map(|mask| mask as u8),
+            None => Ok(0xFF),
+        };
+
+        match (kind, args.len()) {
+            ("tileAtPosition", 4)
+            | ("spriteAtPosition", 4)
+            | ("tileNearby", 4)
+            | ("spriteNearby", 4) => {
+                let (x, y) = (number(args[0])?, number(args[1])?);
+                let tile = parse_tile(args[2], version)?;
+                let palette = parse_palette(args[3])?;
+
+                match kind {
+                    "tileNearby" => Ok(Condition::TileNearby(x, y, tile, palette)),
+                    "spriteNearby" => Ok(Condition::SpriteNearby(x, y, tile, palette)),
+                    _ if x < 0 || y < 0 => Err(invalid()),
+                    "tileAtPosition" => Ok(Condition::TileAtPosition(
+                        x as usize, y as usize, tile, palette,
+                    )),
+                    _ => Ok(Condition::SpriteAtPosition(
+                        x as usize, y as usize, tile, palette,
+                    )),
+                }
+            }
+            ("memoryCheck", 3) | ("memoryCheck", 4) => Ok(Condition::MemoryCheck(
+                hex(args[0])?,
+                Operator::parse(args[1].trim())?,
+                hex(args[2])?,
+                mask(3)?,
+            )),
+            ("memoryCheckConstant", 3) | ("memoryCheckConstant", 4) => {
+                Ok(Condition::MemoryCheckConstant(
+                    hex(args[0])?,
+                    Operator::parse(args[1].trim())?,
+                    hex(args[2])? as u8,
+                    mask(3)?,
+                ))
+            }
+            ("frameRange", 2) => {
+                let (divisor, compare) = (number(args[0])?, number(args[1])?);
+                if divisor <= 0 || compare < 0 {
+                    return Err(invalid());
+                }
+                Ok(Condition::FrameRange(divisor as u64, compare as u64))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// CPU addresses the condition reads
+    pub fn addresses(&self) -> Vec<u16> {
+        match *self {
+            Condition::MemoryCheck(a, _, b, _) => vec![a, b],
+            Condition::MemoryCheckConstant(address, _, _, _) => vec![address],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn check(&self, context: &Context) -> bool {
+        let read = |address: u16| context.memory.get(&address).copied().unwrap_or(0);
+        let nearby = |dx: isize, dy: isize| {
+            context.tile.map(|tile| {
+                let (x, y) = tile.origin(context.x, context.y);
+                (x + dx, y + dy)
+            })
+        };
+
+        match self {
+            Condition::TileAtPosition(x, y, tile, palette) => {
+                background_at(context.frame, *x as isize, *y as isize, tile, palette)
+            }
+            Condition::SpriteAtPosition(x, y, tile, palette) => {
+                sprite_at(context.frame, *x as isize, *y as isize, tile, palette)
+            }
+            Condition::TileNearby(dx, dy, tile, palette) => nearby(*dx, *dy)
+                .is_some_and(|(x, y)| background_at(context.frame, x, y, tile, palette)),
+            Condition::SpriteNearby(dx, dy, tile, palette) => {
+                nearby(*dx, *dy).is_some_and(|(x, y)| sprite_at(context.frame, x, y, tile, palette))
+            }
+            Condition::MemoryCheck(a, operator, b, mask) => {
+                operator.compare(read(*a) & mask, read(*b) & mask)
+            }
+            Condition::MemoryCheckConstant(address, operator, value, mask) => {
+                operator.compare(read(*address) & mask, *value)
+            }
+            Condition::FrameRange(divisor, compare) => context.frame_number % divisor >= *compare,
+            Condition::HorizontalMirror => context.tile.is_some_and(|tile| tile.h_flip),
+            Condition::VerticalMirror => context.tile.is_some_and(|tile| tile.v_flip),
+            Condition::BackgroundPriority => context.tile.is_some_and(|tile| tile.behind),
+        }
+    }
+}
+
+fn background_at(frame: &Frame, x: isize, y: isize, tile: &TileId, palette: &[u8; 4]) -> bool {
+    source_at(frame, x, y, |source| source.background)
+        .is_some_and(|source| source.tile == *tile && source.palette == *palette)
+}
+
+fn sprite_at(frame: &Frame, x: isize, y: isize, tile: &TileId, palette: &[u8; 4]) -> bool {
+    source_at(frame, x, y, |source| source.sprite)
+        .is_some_and(|source| source.tile == *tile && source.palette == *palette)
+}
+
+fn source_at<F>(frame: &Frame, x: isize, y: isize, layer: F) -> Option<TileSource>
+where
+    F: Fn(&PixelSource) -> Option<TileSource>,
+{
+    if x < 0 || y < 0 {
+        return None;
+    }
+    frame.source(x as usize, y as usize).and_then(layer)
+}
+
+/// Tiles are a CHR-ROM index, decimal before version 3 and hex after, or 32
+/// hex digits of CHR-RAM pattern data
+pub fn parse_tile(text: &str, version: u32) -> Result<TileId, String> {
+    let text = text.trim();
+    let invalid = || format!("Invalid tile {}", text);
+
+    if text.len() == 32 {
+        let mut data = [0; 16];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(TileId::Ram(data))
+    } else if version <= 2 {
+        text.parse().map(TileId::Rom).map_err(|_| invalid())
+    } else {
+        u32::from_str_radix(text, 16)
+            .map(TileId::Rom)
+            .map_err(|_| invalid())
+    }
+}
+
+/// Palettes are the four colors as eight hex digits, backdrop first
+pub fn parse_palette(text: &str) -> Result<[u8; 4], String> {
+    let text = text.trim();
+    let value = u32::from_str_radix(text, 16).map_err(|_| format!("Invalid palette {}", text))?;
+
+    Ok(value.to_be_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tile(id: u32, x: usize, y: usize) -> TileSource {
+        TileSource {
+            tile: TileId::Rom(id),
+            palette: [0x0F, 0x16, 0x27, 0x38],
+            column: (x % 8) as u8,
+            row: (y % 8) as u8,
+            pixel: 1,
+            h_flip: false,
+            v_flip: false,
+            behind: false,
+        }
+    }
+
+    #[test]
+    fn parses_conditions() {
+        assert_eq!(
+            Condition::parse("memoryCheckConstant", &["00A0", "==", "3"], 106),
+            Ok(Condition::MemoryCheckConstant(
+                0xA0,
+                Operator::Equal,
+                3,
+                0xFF
+            ))
+        );
+        assert_eq!(
+            Condition::parse("tileNearby", &["-8", "0", "1A", "0F162738"], 106),
+            Ok(Condition::TileNearby(
+                -8,
+                0,
+                TileId::Rom(0x1A),
+                [0x0F, 0x16, 0x27, 0x38]
+            ))
+        );
+        assert_eq!(parse_tile("26", 2), Ok(TileId::Rom(26)));
+        assert!(Condition::parse("memoryCheck", &["00A0", "=", "00A1"], 106).is_err());
+        assert!(Condition::parse("tileAtPosition", &["-1", "0", "1", "0F162738"], 106).is_err());
+    }
+
+    #[test]
+    fn checks_against_frame_and_memory() {
+        let mut frame = Frame::new();
+        frame.track_sources();
+        for x in 0..16 {
+            let id = if x < 8 { 1 } else { 2 };
+            frame.set_source(
+                x,
+                0,
+                PixelSource {
+                    background: Some(tile(id, x, 0)),
+                    sprite: None,
+                },
+            );
+        }
+
+        let memory = [(0xA0, 0x13)].iter().copied().collect();
+        let current = tile(1, 3, 0);
+        let context = Context {
+            frame: &frame,
+            frame_number: 10,
+            memory: &memory,
+            x: 3,
+            y: 0,
+            tile: Some(&current),
+        };
+        let palette = [0x0F, 0x16, 0x27, 0x38];
+
+        assert!(Condition::TileNearby(8, 0, TileId::Rom(2), palette).check(&context));
+        assert!(!Condition::TileNearby(-8, 0, TileId::Rom(2), palette).check(&context));
+        assert!(Condition::TileAtPosition(9, 0, TileId::Rom(2), palette).check(&context));
+        assert!(Condition::MemoryCheckConstant(0xA0, Operator::Equal, 3, 0x0F).check(&context));
+        assert!(Condition::MemoryCheck(0xA0, Operator::Greater, 0xA1, 0xFF).check(&context));
+        assert!(Condition::FrameRange(4, 2).check(&context));
+        assert!(!Condition::HorizontalMirror.check(&context));
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+/// An RGB24 image, the output of palette conversion and every stage after it
+#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            data: vec![0; width * height * 3],
+        }
+    }
+
+    /// Bytes per row
+    pub fn pitch(&self) -> usize {
+        self.width * 3
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        if x < self.width && y < self.height {
+            let base = (y * self.width + x) * 3;
+            self.data[base] = rgb.0;
+            self.data[base + 1] = rgb.1;
+            self.data[base + 2] = rgb.2;
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * self.width + x) * 3;
+        (self.data[base], self.data[base + 1], self.data[base + 2])
+    }
+
+    /// Copies another image into this one with its top left corner at (x, y)
+    pub fn blit(&mut self, image: &Image, x: usize, y: usize) {
+        for row in 0..image.height {
+            for column in 0..image.width {
+                self.set_pixel(x + column, y + row, image.pixel(column, row));
+            }
+        }
+    }
+
+    /// Copies out the `width` x `height` area with its top left corner at (x, y)
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize, output: &mut Image) {
+        if output.width != width || output.height != height {
+            *output = Image::new(width, height);
+        }
+
+        for row in 0..height {
+            let start = ((y + row) * self.width + x) * 3;
+            output.data[row * width * 3..(row + 1) * width * 3]
+                .copy_from_slice(&self.data[start..start + width * 3]);
+        }
+    }
+
+    /// Resamples the image with bilinear filtering
+    pub fn scale_to(&self, width: usize, height: usize) -> Image {
+        let mut image = Image::new(width, height);
+        let x_ratio = self.width as f64 / width as f64;
+        let y_ratio = self.height as f64 / height as f64;
+
+        for y in 0..height {
+            let source_y = ((y as f64 + 0.5) * y_ratio - 0.5).max(0.0);
+            let y0 = (source_y as usize).min(self.height - 1);
+            let y1 = (y0 + 1).min(self.height - 1);
+            let fy = source_y - y0 as f64;
+
+            for x in 0..width {
+                let source_x = ((x as f64 + 0.5) * x_ratio - 0.5).max(0.0);
+                let x0 = (source_x as usize).min(self.width - 1);
+                let x1 = (x0 + 1).min(self.width - 1);
+                let fx = source_x - x0 as f64;
+
+                let base = (y * width + x) * 3;
+                for channel in 0..3 {
+                    let sample =
+                        |x: usize, y: usize| self.data[(y * self.width + x) * 3 + channel] as f64;
+                    let top = sample(x0, y0) * (1.0 - fx) + sample(x1, y0) * fx;
+                    let bottom = sample(x0, y1) * (1.0 - fx) + sample(x1, y1) * fx;
+
+                    image.data[base + channel] = (top * (1.0 - fy) + bottom * fy).round() as u8;
+                }
+            }
+        }
+
+        image
+    }
+
+    pub fn save_png(&self, filename: &str) -> io::Result<()> {
+        self.write_png(BufWriter::new(File::create(filename)?), &[])
+    }
+
+    /// Encodes the image as PNG, with text chunks for any metadata. Values
+    /// that don't fit in Latin-1 go in UTF-8 iTXt chunks instead.
+    pub fn write_png<W: Write>(&self, writer: W, text: &[(&str, String)]) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        for (keyword, value) in text {
+            let latin1 = value.chars().all(|c| (c as u32) < 0x100);
+            let result = if latin1 {
+                encoder.add_text_chunk(keyword.to_string(), value.clone())
+            } else {
+                encoder.add_itxt_chunk(keyword.to_string(), value.clone())
+            };
+            result.map_err(io::Error::other)?;
+        }
+
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.data))
+            .map_err(io::Error::other)
+    }
+}
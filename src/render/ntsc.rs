@@ -0,0 +1,287 @@
+use std::f64::consts::PI;
+
+use super::{
+    frame::{Frame, FRAME_HEIGHT, FRAME_WIDTH},
+    image::Image,
+};
+
+/// Width of the filtered image, matching blargg's nes_ntsc
+pub const NTSC_WIDTH: usize = 602;
+
+// The PPU emits 8 signal samples per pixel, and a color subcarrier cycle is
+// 12 samples long
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_CYCLE: usize = 12;
+const LINE_SAMPLES: usize = FRAME_WIDTH * SAMPLES_PER_PIXEL;
+
+// Room around the visible samples so decode windows never run off the line
+const PADDING: usize = 16;
+
+// Composite voltage levels relative to sync, for low and high halves of the
+// square wave at each of the four luma levels
+const LOW_LEVELS: [f64; 4] = [0.350, 0.518, 0.962, 1.550];
+const HIGH_LEVELS: [f64; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f64 = 0.518;
+const WHITE: f64 = 1.962;
+const EMPHASIS_ATTENUATION: f64 = 0.746;
+
+// Phase shift of the colorburst relative to the PPU's color generator
+const BURST_PHASE: f64 = 3.9;
+
+pub const DISPLAY_GAMMA: f64 = 1.8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NtscSettings {
+    pub sharpness: f64,  // -1.0 (soft) to 1.0 (sharp)
+    pub hue: f64,        // Degrees
+    pub saturation: f64, // 0.0 (greyscale) to 2.0
+    pub merge_fields: bool,
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        NtscSettings {
+            sharpness: 0.0,
+            hue: 0.0,
+            saturation: 1.0,
+            merge_fields: false,
+        }
+    }
+}
+
+/// Software NTSC composite video filter. Each scanline of palette indices is
+/// turned into the PPU's composite signal and decoded back to RGB, giving the
+/// dot crawl, color fringing and chroma blur of a real TV.
+pub struct NtscFilter {
+    pub settings: NtscSettings,
+    signal: Vec<f64>,
+    luma: Vec<f64>,
+    in_phase: Vec<f64>,
+    quadrature: Vec<f64>,
+    line: Vec<(f64, f64, f64)>,
+}
+
+impl NtscFilter {
+    pub fn new(settings: NtscSettings) -> Self {
+        let length = LINE_SAMPLES + PADDING * 2;
+
+        NtscFilter {
+            settings,
+            signal: vec![0.0; length],
+            luma: vec![0.0; length + 1],
+            in_phase: vec![0.0; length + 1],
+            quadrature: vec![0.0; length + 1],
+            line: vec![(0.0, 0.0, 0.0); NTSC_WIDTH],
+        }
+    }
+
+    /// Creates an image sized for the filter's output
+    pub fn output_image() -> Image {
+        Image::new(NTSC_WIDTH, FRAME_HEIGHT)
+    }
+
+    /// Filters a frame. The frame number selects the colorburst phase, which
+    /// alternates between frames and produces dot crawl unless fields are
+    /// merged.
+    pub fn apply(&mut self, frame: &Frame, frame_number: u64, image: &mut Image) {
+        let frame_phase = (frame_number % 2) as usize * 4;
+
+        for y in 0..FRAME_HEIGHT {
+            let pixels = &frame.data[y * FRAME_WIDTH..(y + 1) * FRAME_WIDTH];
+            let line_phase = (frame_phase + y * 4) % SAMPLES_PER_CYCLE;
+
+            for rgb in self.line.iter_mut() {
+                *rgb = (0.0, 0.0, 0.0);
+            }
+
+            self.decode_line(pixels, line_phase);
+            if self.settings.merge_fields {
+                // Averaging both burst phases cancels out the dot crawl
+                self.decode_line(pixels, (line_phase + 4) % SAMPLES_PER_CYCLE);
+            }
+
+            let fields = if self.settings.merge_fields { 2.0 } else { 1.0 };
+            for (x, &(r, g, b)) in self.line.iter().enumerate() {
+                image.set_pixel(
+                    x,
+                    y,
+                    (
+                        to_byte(r / fields),
+                        to_byte(g / fields),
+                        to_byte(b / fields),
+                    ),
+                );
+            }
+        }
+    }
+
+    fn decode_line(&mut self, pixels: &[u16], phase: usize) {
+        self.encode_line(pixels, phase);
+
+        // Prefix sums of the demodulated signal let each output pixel average
+        // over its window in constant time
+        let hue = BURST_PHASE + self.settings.hue / 30.0;
+        for (n, &level) in self.signal.iter().enumerate() {
+            let angle = PI * ((phase + n) as f64 - PADDING as f64 + hue) / 6.0;
+
+            self.luma[n + 1] = self.luma[n] + level;
+            self.in_phase[n + 1] = self.in_phase[n] + level * angle.cos();
+            self.quadrature[n + 1] = self.quadrature[n] + level * angle.sin();
+        }
+
+        // Sharpness narrows the luma window, letting chroma leak into luma as
+        // fringing. The chroma window spans two cycles for a soft blur.
+        let luma_width = (12.0 - 6.0 * self.settings.sharpness.clamp(-1.0, 1.0)).round() as usize;
+        let chroma_width = SAMPLES_PER_CYCLE * 2;
+        let saturation = self.settings.saturation;
+
+        for (x, rgb) in self.line.iter_mut().enumerate() {
+            let center = PADDING + (x * LINE_SAMPLES + LINE_SAMPLES / 2) / NTSC_WIDTH;
+
+            let y = window(&self.luma, center, luma_width);
+            let i = window(&self.in_phase, center, chroma_width) * saturation;
+            let q = window(&self.quadrature, center, chroma_width) * saturation;
+
+            let (r, g, b) = yiq_to_rgb(y, i, q);
+            rgb.0 += gamma(r, DISPLAY_GAMMA);
+            rgb.1 += gamma(g, DISPLAY_GAMMA);
+            rgb.2 += gamma(b, DISPLAY_GAMMA);
+        }
+    }
+
+    /// Generates the normalized composite signal for a line of pixels
+    fn encode_line(&mut self, pixels: &[u16], phase: usize) {
+        for level in self.signal.iter_mut() {
+            *level = 0.0;
+        }
+
+        for (x, &pixel) in pixels.iter().enumerate() {
+            for sample in 0..SAMPLES_PER_PIXEL {
+                let n = x * SAMPLES_PER_PIXEL + sample;
+                self.signal[PADDING + n] = signal_level(pixel, phase + n);
+            }
+        }
+    }
+}
+
+/// Voltage of the PPU's square wave for a color at the given sample phase
+pub fn signal_level(pixel: u16, phase: usize) -> f64 {
+    let color = (pixel & 0x0F) as usize;
+    let mut level = ((pixel >> 4) & 0x03) as usize;
+    let emphasis = pixel >> 6;
+
+    // Colors $xE and $xF are always black
+    if color > 13 {
+        level = 1;
+    }
+
+    let in_color_phase = |hue: usize| (hue + phase) % SAMPLES_PER_CYCLE < 6;
+
+    let low = LOW_LEVELS[level];
+    let high = HIGH_LEVELS[level];
+    let mut signal = match color {
+        0 => high,
+        13..=15 => low,
+        _ if in_color_phase(color) => high,
+        _ => low,
+    };
+
+    if (emphasis & 1 != 0 && in_color_phase(0))
+        || (emphasis & 2 != 0 && in_color_phase(4))
+        || (emphasis & 4 != 0 && in_color_phase(8))
+    {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+/// Decodes a color's signal over one full subcarrier cycle, which is what a
+/// TV shows for a large area of a single color. Hue is in degrees.
+pub fn decode_color(pixel: u16, hue: f64) -> (f64, f64, f64) {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+
+    for phase in 0..SAMPLES_PER_CYCLE {
+        let level = signal_level(pixel, phase);
+        let angle = PI * (phase as f64 + BURST_PHASE + hue / 30.0) / 6.0;
+
+        y += level;
+        i += level * angle.cos();
+        q += level * angle.sin();
+    }
+
+    let samples = SAMPLES_PER_CYCLE as f64;
+    (y / samples, i / samples, q / samples)
+}
+
+pub fn yiq_to_rgb(y: f64, i: f64, q: f64) -> (f64, f64, f64) {
+    (
+        y + 0.946882 * i + 0.623557 * q,
+        y - 0.274788 * i - 0.635691 * q,
+        y - 1.108545 * i + 1.709007 * q,
+    )
+}
+
+/// Corrects from the TV's 2.2 gamma to the display's
+pub fn gamma(value: f64, display_gamma: f64) -> f64 {
+    if value <= 0.0 {
+        0.0
+    } else {
+        value.powf(2.2 / display_gamma)
+    }
+}
+
+fn window(sums: &[f64], center: usize, width: usize) -> f64 {
+    let start = center - width / 2;
+    (sums[start + width] - sums[start]) / width as f64
+}
+
+fn to_byte(value: f64) -> u8 {
+    (value * 255.0).clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter_solid(index: u16) -> (u8, u8, u8) {
+        let mut frame = Frame::new();
+        for pixel in frame.data.iter_mut() {
+            *pixel = index;
+        }
+
+        let mut filter = NtscFilter::new(NtscSettings::default());
+        let mut image = NtscFilter::output_image();
+        filter.apply(&frame, 0, &mut image);
+
+        image.pixel(NTSC_WIDTH / 2, FRAME_HEIGHT / 2)
+    }
+
+    #[test]
+    fn greys_have_no_chroma() {
+        let (r, g, b) = filter_solid(0x20);
+
+        assert!(r > 240 && g > 240 && b > 240);
+        assert_eq!(filter_solid(0x0F), (0, 0, 0));
+    }
+
+    #[test]
+    fn decodes_primary_hues() {
+        let (r, g, b) = filter_solid(0x16);
+        assert!(r > g && r > b);
+
+        let (r, g, b) = filter_solid(0x1A);
+        assert!(g > r && g > b);
+
+        let (r, g, b) = filter_solid(0x12);
+        assert!(b > r && b > g);
+    }
+
+    #[test]
+    fn emphasis_dims_the_signal() {
+        let plain = filter_solid(0x20);
+        let emphasized = filter_solid(0x20 | 0b111 << 6);
+
+        assert!(emphasized.0 < plain.0);
+    }
+}
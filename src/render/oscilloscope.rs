@@ -0,0 +1,217 @@
+use super::image::Image;
+use crate::apu::{Apu, Channel, SCOPE_LENGTH};
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 400;
+
+const PIANO_HEIGHT: usize = 10;
+const LANE_GAP: usize = 6;
+
+// The 88 keys of a piano, A0 to C8, as MIDI note numbers
+const LOWEST_KEY: i32 = 21;
+const HIGHEST_KEY: i32 = 108;
+const KEY_WIDTH: usize = 5;
+const PIANO_LEFT: usize = (WIDTH - (HIGHEST_KEY - LOWEST_KEY + 1) as usize * KEY_WIDTH) / 2;
+
+// Points of history shown across the lane, after the trigger
+const VIEW_LENGTH: usize = SCOPE_LENGTH / 2;
+
+const CENTER_COLOR: (u8, u8, u8) = (0x20, 0x20, 0x20);
+const WHITE_KEY_COLOR: (u8, u8, u8) = (0xE0, 0xE0, 0xE0);
+const BLACK_KEY_COLOR: (u8, u8, u8) = (0x30, 0x30, 0x30);
+
+static NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+fn channel_color(channel: Channel) -> (u8, u8, u8) {
+    match channel {
+        Channel::Pulse1 => (0xF8, 0x38, 0x00),
+        Channel::Pulse2 => (0xFC, 0xA0, 0x44),
+        Channel::Triangle => (0x3C, 0xBC, 0xFC),
+        Channel::Noise => (0xBC, 0xBC, 0xBC),
+        Channel::Dmc => (0x58, 0xD8, 0x54),
+        Channel::Vrc6Pulse1 => (0xF8, 0x78, 0xF8),
+        Channel::Vrc6Pulse2 => (0xD8, 0x00, 0xCC),
+        Channel::Vrc6Saw => (0xF8, 0xD8, 0x78),
+        Channel::Vrc7Fm1 => (0x00, 0xE8, 0xD8),
+        Channel::Vrc7Fm2 => (0x78, 0xF8, 0x58),
+        Channel::Vrc7Fm3 => (0x98, 0x78, 0xF8),
+        Channel::Vrc7Fm4 => (0xF8, 0x58, 0x98),
+        Channel::Vrc7Fm5 => (0xB8, 0xF8, 0x18),
+        Channel::Vrc7Fm6 => (0x68, 0x88, 0xFC),
+    }
+}
+
+/// The nearest MIDI note number to a frequency, where A4 (440Hz) is 69
+pub fn note_number(frequency: f64) -> i32 {
+    (69.0 + 12.0 * (frequency / 440.0).log2()).round() as i32
+}
+
+/// The nearest note to a frequency in scientific pitch notation, like "C#4"
+pub fn note_name(frequency: f64) -> String {
+    let note = note_number(frequency);
+    format!(
+        "{}{}",
+        NOTE_NAMES[note.rem_euclid(12) as usize],
+        note.div_euclid(12) - 1
+    )
+}
+
+/// The notes the pitched channels are playing, for the window title
+pub fn describe_notes(apu: &Apu) -> String {
+    apu.channels()
+        .iter()
+        .filter_map(|&channel| {
+            let frequency = apu.frequency(channel)?;
+            Some(format!("{} {}", channel, note_name(frequency)))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Height of each channel's lane and of the waveform in it, when the lanes
+/// share the image between them
+fn lane_size(lanes: usize) -> (usize, usize) {
+    let lane_height = HEIGHT / lanes;
+    (lane_height, lane_height - PIANO_HEIGHT - LANE_GAP)
+}
+
+/// Draws a lane per channel: its recent waveform, lined up on a rising edge
+/// so periodic waves hold still, above a piano with the current note lit.
+/// Muted channels are drawn dimmed. Expansion audio adds lanes, which makes
+/// them all narrower.
+pub fn compose(apu: &Apu) -> Image {
+    let mut image = Image::new(WIDTH, HEIGHT);
+    let channels = apu.channels();
+    let (lane_height, wave_height) = lane_size(channels.len());
+
+    for (lane, &channel) in channels.iter().enumerate() {
+        let top = lane * lane_height;
+        let mut color = channel_color(channel);
+        if apu.mixer().gain(channel) == 0.0 {
+            color = (color.0 / 3, color.1 / 3, color.2 / 3);
+        }
+
+        let points: Vec<f32> = apu.scope().trace(channel.index()).collect();
+        draw_waveform(&mut image, &points, top, wave_height, color);
+
+        let note = apu.frequency(channel).map(note_number);
+        draw_piano(&mut image, top + wave_height + 2, note, color);
+    }
+
+    image
+}
+
+/// Where to start showing the trace from: the latest rising edge through the
+/// middle of its range that leaves a full view after it
+fn trigger(points: &[f32]) -> usize {
+    let latest = points.len() - VIEW_LENGTH;
+    let high = points.iter().copied().fold(f32::MIN, f32::max);
+    let low = points.iter().copied().fold(f32::MAX, f32::min);
+    let middle = (high + low) / 2.0;
+
+    (1..=latest)
+        .rev()
+        .find(|&i| points[i - 1] < middle && points[i] >= middle)
+        .unwrap_or(latest)
+}
+
+fn draw_waveform(
+    image: &mut Image,
+    points: &[f32],
+    top: usize,
+    height: usize,
+    color: (u8, u8, u8),
+) {
+    for x in 0..WIDTH {
+        image.set_pixel(x, top + height / 2, CENTER_COLOR);
+    }
+
+    let start = trigger(points);
+    let mut previous = None;
+    for x in 0..WIDTH {
+        let level = points[start + x * VIEW_LENGTH / WIDTH].clamp(0.0, 1.0);
+        let y = top + ((1.0 - level) * (height - 1) as f32).round() as usize;
+
+        // Join up with the last column so edges are drawn as lines
+        let (from, to) = match previous {
+            Some(previous) if previous < y => (previous, y),
+            Some(previous) => (y, previous),
+            None => (y, y),
+        };
+        for y in from..=to {
+            image.set_pixel(x, y, color);
+        }
+        previous = Some(y);
+    }
+}
+
+fn draw_piano(image: &mut Image, top: usize, note: Option<i32>, color: (u8, u8, u8)) {
+    for key in LOWEST_KEY..=HIGHEST_KEY {
+        let left = PIANO_LEFT + (key - LOWEST_KEY) as usize * KEY_WIDTH;
+        let key_color = if note == Some(key) {
+            color
+        } else if [1, 3, 6, 8, 10].contains(&key.rem_euclid(12)) {
+            BLACK_KEY_COLOR
+        } else {
+            WHITE_KEY_COLOR
+        };
+
+        for y in top..top + PIANO_HEIGHT {
+            for x in left..left + KEY_WIDTH - 1 {
+                image.set_pixel(x, y, key_color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::region::Region;
+
+    #[test]
+    fn names_notes() {
+        assert_eq!(note_number(440.0), 69);
+        assert_eq!(note_name(440.0), "A4");
+        assert_eq!(note_name(261.63), "C4");
+        assert_eq!(note_name(27.5), "A0");
+        assert_eq!(note_name(454.0), "A#4");
+    }
+
+    fn key_pixel(image: &Image, lane: usize, key: i32) -> (u8, u8, u8) {
+        let (lane_height, wave_height) = lane_size(5);
+        let x = PIANO_LEFT + (key - LOWEST_KEY) as usize * KEY_WIDTH;
+        image.pixel(x, lane * lane_height + wave_height + 4)
+    }
+
+    #[test]
+    fn draws_waveforms_and_lights_the_note() {
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.write(0x4015, 0x01);
+        apu.write(0x4000, 0x9F);
+        apu.write(0x4002, 0xFD); // About 440Hz
+        apu.write(0x4003, 0x08);
+        for _ in 0..SCOPE_LENGTH * 32 {
+            apu.tick();
+        }
+
+        let image = compose(&apu);
+        let pulse1 = channel_color(Channel::Pulse1);
+        assert_eq!(key_pixel(&image, 0, 69), pulse1);
+        assert_eq!(key_pixel(&image, 0, 70), BLACK_KEY_COLOR);
+        assert_eq!(key_pixel(&image, 1, 69), WHITE_KEY_COLOR);
+        assert_eq!(describe_notes(&apu), "pulse1 A4");
+
+        // The square wave reaches both the top and the bottom of its lane,
+        // starting on the rising edge
+        let (_, wave_height) = lane_size(5);
+        let column = |x| (0..wave_height).find(|&y| image.pixel(x, y) == pulse1);
+        assert_eq!(column(1), Some(0));
+        assert!((0..WIDTH).any(|x| column(x) == Some(wave_height - 1)));
+
+        apu.mixer_mut().set_muted(Channel::Pulse1, true);
+        assert_ne!(key_pixel(&compose(&apu), 0, 69), pulse1);
+    }
+}
@@ -1,3 +1,11 @@
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind},
+    str::FromStr,
+};
+
+use super::{frame::Frame, image::Image, ntsc};
+
 #[rustfmt::skip]
 
 pub static SYSTEM_PALETTE: [(u8,u8,u8); 64] = [
@@ -18,3 +26,370 @@ pub static SYSTEM_PALETTE: [(u8,u8,u8); 64] = [
     (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA), 
     (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
 ];
+
+// Colors of the RGB PPUs used in Vs. System and PlayChoice-10 hardware, as
+// 3 bit red, green and blue levels
+#[rustfmt::skip]
+static RGB_PPU_PALETTE: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420,
+    0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630,
+    0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750,
+    0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772,
+    0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+// How much the composite PPU dims the channels that aren't emphasized
+const EMPHASIS_ATTENUATION: f64 = 0.816328;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PaletteKind {
+    Rp2c02, // Composite NTSC PPU
+    Rp2c03, // RGB PPU (Vs. System, PlayChoice-10)
+    Rp2c05, // RGB PPU with the 2C03 palette and swapped registers
+}
+
+impl FromStr for PaletteKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "2c02" => Ok(PaletteKind::Rp2c02),
+            "2c03" => Ok(PaletteKind::Rp2c03),
+            "2c05" => Ok(PaletteKind::Rp2c05),
+            _ => Err(format!("Unknown palette {}", s)),
+        }
+    }
+}
+
+/// Adjustments for palettes generated from the composite signal
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PaletteSettings {
+    pub hue: f64, // Degrees
+    pub saturation: f64,
+    pub contrast: f64,
+    pub brightness: f64,
+    pub gamma: f64,
+}
+
+impl Default for PaletteSettings {
+    fn default() -> Self {
+        PaletteSettings {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: ntsc::DISPLAY_GAMMA,
+        }
+    }
+}
+
+impl PaletteSettings {
+    pub fn get(&self, control: PaletteControl) -> f64 {
+        match control {
+            PaletteControl::Hue => self.hue,
+            PaletteControl::Saturation => self.saturation,
+            PaletteControl::Contrast => self.contrast,
+            PaletteControl::Brightness => self.brightness,
+            PaletteControl::Gamma => self.gamma,
+        }
+    }
+
+    /// Nudges a setting up or down by a number of steps, keeping it in a
+    /// range that still gives a usable picture
+    pub fn adjust(&mut self, control: PaletteControl, steps: i32) {
+        let (step, min, max) = match control {
+            PaletteControl::Hue => (5.0, -180.0, 180.0),
+            PaletteControl::Saturation => (0.1, 0.0, 3.0),
+            PaletteControl::Contrast => (0.1, 0.1, 3.0),
+            PaletteControl::Brightness => (0.05, -1.0, 1.0),
+            PaletteControl::Gamma => (0.1, 1.0, 3.0),
+        };
+        // Rounded to the step so repeated nudges don't drift
+        let value = ((self.get(control) / step).round() + steps as f64) * step;
+        let value = value.clamp(min, max);
+
+        match control {
+            PaletteControl::Hue => self.hue = value,
+            PaletteControl::Saturation => self.saturation = value,
+            PaletteControl::Contrast => self.contrast = value,
+            PaletteControl::Brightness => self.brightness = value,
+            PaletteControl::Gamma => self.gamma = value,
+        }
+    }
+}
+
+/// The palette setting the hotkeys adjust while the game runs
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PaletteControl {
+    Hue,
+    Saturation,
+    Contrast,
+    Brightness,
+    Gamma,
+}
+
+impl PaletteControl {
+    pub fn next(&self) -> PaletteControl {
+        match self {
+            PaletteControl::Hue => PaletteControl::Saturation,
+            PaletteControl::Saturation => PaletteControl::Contrast,
+            PaletteControl::Contrast => PaletteControl::Brightness,
+            PaletteControl::Brightness => PaletteControl::Gamma,
+            PaletteControl::Gamma => PaletteControl::Hue,
+        }
+    }
+}
+
+impl fmt::Display for PaletteControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PaletteControl::Hue => "hue",
+            PaletteControl::Saturation => "saturation",
+            PaletteControl::Contrast => "contrast",
+            PaletteControl::Brightness => "brightness",
+            PaletteControl::Gamma => "gamma",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Maps the PPU's 9 bit color indices to RGB
+#[derive(Clone)]
+pub struct Palette {
+    colors: Vec<(u8, u8, u8)>,
+}
+
+impl Palette {
+    pub fn builtin(kind: PaletteKind) -> Palette {
+        match kind {
+            PaletteKind::Rp2c02 => Palette::from_base_colors(&SYSTEM_PALETTE),
+            PaletteKind::Rp2c03 | PaletteKind::Rp2c05 => {
+                let mut colors = Vec::with_capacity(512);
+                for emphasis in 0..8 {
+                    for &color in RGB_PPU_PALETTE.iter() {
+                        colors.push(rgb_ppu_color(color, emphasis));
+                    }
+                }
+
+                Palette { colors }
+            }
+        }
+    }
+
+    /// Expands 64 base colors to all 512 entries by attenuating the channels
+    /// that aren't emphasized, like the composite PPU does
+    pub fn from_base_colors(base: &[(u8, u8, u8)]) -> Palette {
+        let mut colors = Vec::with_capacity(512);
+
+        for emphasis in 0..8 {
+            for &(r, g, b) in base.iter().take(64) {
+                colors.push((
+                    attenuate(r, emphasis & 0b110 != 0),
+                    attenuate(g, emphasis & 0b101 != 0),
+                    attenuate(b, emphasis & 0b011 != 0),
+                ));
+            }
+        }
+
+        Palette { colors }
+    }
+
+    /// Computes all 512 colors by decoding the 2C02's composite signal. The
+    /// emphasis bits attenuate the signal during their part of the color
+    /// cycle, so they dim and tint colors as on a real TV.
+    pub fn generate(settings: &PaletteSettings) -> Palette {
+        let colors = (0..512)
+            .map(|index| {
+                let (y, i, q) = ntsc::decode_color(index, settings.hue);
+                let (r, g, b) = ntsc::yiq_to_rgb(
+                    y * settings.contrast + settings.brightness,
+                    i * settings.saturation,
+                    q * settings.saturation,
+                );
+
+                let channel = |value: f64| {
+                    (ntsc::gamma(value, settings.gamma) * 255.0)
+                        .round()
+                        .clamp(0.0, 255.0) as u8
+                };
+                (channel(r), channel(g), channel(b))
+            })
+            .collect();
+
+        Palette { colors }
+    }
+
+    /// Parses a `.pal` file with either 64 or 512 RGB triplets
+    pub fn from_bytes(data: &[u8]) -> io::Result<Palette> {
+        let colors: Vec<(u8, u8, u8)> = data
+            .chunks_exact(3)
+            .map(|rgb| (rgb[0], rgb[1], rgb[2]))
+            .collect();
+
+        match data.len() {
+            192 => Ok(Palette::from_base_colors(&colors)),
+            1536 => Ok(Palette { colors }),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Palette files must hold 64 or 512 colors",
+            )),
+        }
+    }
+
+    pub fn from_file(filename: &str) -> io::Result<Palette> {
+        Palette::from_bytes(&fs::read(filename)?)
+    }
+
+    /// Loads a built-in palette by name, or a `.pal` file from disk
+    pub fn load(name: &str) -> io::Result<Palette> {
+        match name.parse::<PaletteKind>() {
+            Ok(kind) => Ok(Palette::builtin(kind)),
+            Err(_) => Palette::from_file(name),
+        }
+    }
+
+    /// Serializes all 512 colors in the `.pal` format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.colors
+            .iter()
+            .flat_map(|&(r, g, b)| vec![r, g, b])
+            .collect()
+    }
+
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        fs::write(filename, self.to_bytes())
+    }
+
+    pub fn color(&self, index: u16) -> (u8, u8, u8) {
+        self.colors[(index & 0x1FF) as usize]
+    }
+
+    /// Converts a frame of color indices into an RGB image
+    pub fn convert(&self, frame: &Frame, image: &mut Image) {
+        for (rgb, &index) in image.data.chunks_exact_mut(3).zip(frame.data.iter()) {
+            let (r, g, b) = self.color(index);
+            rgb[0] = r;
+            rgb[1] = g;
+            rgb[2] = b;
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::builtin(PaletteKind::Rp2c02)
+    }
+}
+
+fn attenuate(channel: u8, dim: bool) -> u8 {
+    if dim {
+        (channel as f64 * EMPHASIS_ATTENUATION).round() as u8
+    } else {
+        channel
+    }
+}
+
+// The RGB PPUs drive an emphasized channel at full strength instead of dimming
+// the others
+fn rgb_ppu_color(color: u16, emphasis: u16) -> (u8, u8, u8) {
+    let level = |shift: u16, bit: u16| {
+        if emphasis & bit != 0 {
+            0xFF
+        } else {
+            (((color >> shift) & 7) * 255 / 7) as u8
+        }
+    };
+
+    (level(6, 0b001), level(3, 0b010), level(0, 0b100))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expands_64_color_files() {
+        let mut data = vec![0; 192];
+        data[3..6].copy_from_slice(&[200, 100, 50]);
+
+        let palette = Palette::from_bytes(&data).unwrap();
+
+        assert_eq!(palette.color(0x001), (200, 100, 50));
+        // Emphasize red dims green and blue
+        assert_eq!(palette.color(0x041), (200, 82, 41));
+    }
+
+    #[test]
+    fn loads_512_color_files() {
+        let mut data = vec![0; 1536];
+        data[1533..].copy_from_slice(&[1, 2, 3]);
+
+        let palette = Palette::from_bytes(&data).unwrap();
+
+        assert_eq!(palette.color(0x1FF), (1, 2, 3));
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        assert!(Palette::from_bytes(&[0; 100]).is_err());
+    }
+
+    #[test]
+    fn rgb_ppu_emphasis_saturates_channels() {
+        let palette = Palette::builtin(PaletteKind::Rp2c03);
+
+        assert_eq!(palette.color(0x00), (0x6D, 0x6D, 0x6D));
+        assert_eq!(palette.color(0x100), (0x6D, 0x6D, 0xFF));
+    }
+
+    #[test]
+    fn generated_palette_round_trips() {
+        let palette = Palette::generate(&PaletteSettings::default());
+        let loaded = Palette::from_bytes(&palette.to_bytes()).unwrap();
+
+        assert_eq!(loaded.color(0x1AB), palette.color(0x1AB));
+        assert_eq!(palette.color(0x0F), (0, 0, 0));
+    }
+
+    #[test]
+    fn generated_palette_follows_settings() {
+        let default = Palette::generate(&PaletteSettings::default());
+        let grey = Palette::generate(&PaletteSettings {
+            saturation: 0.0,
+            ..PaletteSettings::default()
+        });
+        let bright = Palette::generate(&PaletteSettings {
+            brightness: 0.2,
+            ..PaletteSettings::default()
+        });
+
+        let (r, g, b) = default.color(0x16);
+        assert!(r > g && r > b);
+
+        let (r, g, b) = grey.color(0x16);
+        assert!(r == g && g == b);
+
+        assert!(bright.color(0x00).0 > default.color(0x00).0);
+        // Emphasizing red dims blue
+        assert!(default.color(0x20 | 0b001 << 6).2 < default.color(0x20).2);
+    }
+
+    #[test]
+    fn settings_adjust_in_steps_within_limits() {
+        let mut settings = PaletteSettings::default();
+
+        settings.adjust(PaletteControl::Hue, -3);
+        assert_eq!(settings.hue, -15.0);
+        settings.adjust(PaletteControl::Saturation, 2);
+        assert!((settings.saturation - 1.2).abs() < 1e-9);
+        settings.adjust(PaletteControl::Brightness, -100);
+        assert_eq!(settings.brightness, -1.0);
+
+        let control = PaletteControl::Gamma;
+        assert_eq!(control.next(), PaletteControl::Hue);
+        assert_eq!(control.to_string(), "gamma");
+    }
+}
@@ -0,0 +1,177 @@
+use super::{
+    blend::{BlendMode, FrameBlender, DEFAULT_DECAY},
+    frame::{Frame, FRAME_HEIGHT, FRAME_WIDTH},
+    hd_pack::HdPack,
+    image::Image,
+    ntsc::{NtscFilter, NtscSettings},
+    palette::Palette,
+    presentation::{AspectMode, Overscan},
+    scale::ScaleFilter,
+};
+
+/// Turns the PPU's frames into the displayed image: palette conversion, the
+/// NTSC filter or an HD pack, frame blending, then the scale filter and
+/// overscan cropping. The window, screenshots and recordings all take their
+/// image from here so they match.
+pub struct Pipeline {
+    palette: Palette,
+    ntsc: Option<NtscFilter>,
+    hd_pack: Option<HdPack>, // Takes precedence over the NTSC filter
+    pub blender: FrameBlender,
+    pub filter: ScaleFilter,
+    pub overscan: Overscan,
+    pub aspect: AspectMode,
+    converted: Image,
+    scaled: Image,
+    output: Image,
+}
+
+impl Pipeline {
+    pub fn new(palette: Palette, ntsc: Option<NtscSettings>, filter: ScaleFilter) -> Self {
+        let ntsc = ntsc.map(NtscFilter::new);
+        let converted = match ntsc {
+            Some(_) => NtscFilter::output_image(),
+            None => Image::new(FRAME_WIDTH, FRAME_HEIGHT),
+        };
+
+        let mut pipeline = Pipeline {
+            palette,
+            ntsc,
+            hd_pack: None,
+            blender: FrameBlender::new(BlendMode::Off, DEFAULT_DECAY),
+            filter,
+            overscan: Overscan::default(),
+            aspect: AspectMode::Pixel,
+            converted,
+            scaled: Image::new(0, 0),
+            output: Image::new(0, 0),
+        };
+        let (width, height) = pipeline.output_size();
+        pipeline.output = Image::new(width, height);
+
+        pipeline
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Swaps in a new palette, such as one regenerated with new settings
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Draws frames with an HD pack's replacement graphics, at its scale
+    pub fn set_hd_pack(&mut self, hd_pack: HdPack) {
+        let scale = hd_pack.scale();
+        self.converted = Image::new(FRAME_WIDTH * scale, FRAME_HEIGHT * scale);
+        self.hd_pack = Some(hd_pack);
+    }
+
+    pub fn hd_pack_mut(&mut self) -> Option<&mut HdPack> {
+        self.hd_pack.as_mut()
+    }
+
+    /// Size of the output image with the current settings
+    pub fn output_size(&self) -> (usize, usize) {
+        let (_, _, width, height) = self.crop_area();
+        (width, height)
+    }
+
+    /// Area of the scaled image left after cropping, as (x, y, width,
+    /// height). The overscan is in NES pixels, so it's scaled to match
+    /// whatever size the filters produced.
+    fn crop_area(&self) -> (usize, usize, usize, usize) {
+        let scale = self.filter.scale();
+        let (width, height) = (self.converted.width * scale, self.converted.height * scale);
+        let horizontal = |pixels: usize| pixels * width / FRAME_WIDTH;
+        let vertical = |lines: usize| lines * height / FRAME_HEIGHT;
+
+        let (left, top) = (horizontal(self.overscan.left), vertical(self.overscan.top));
+        (
+            left,
+            top,
+            width - left - horizontal(self.overscan.right),
+            height - top - vertical(self.overscan.bottom),
+        )
+    }
+
+    /// The shape the output is shown at, as a (width, height) fraction
+    pub fn display_aspect(&self) -> (usize, usize) {
+        let (width, height) = self.overscan.visible_size();
+        self.aspect.display_aspect(width, height)
+    }
+
+    pub fn process(&mut self, frame: &Frame, frame_number: u64) -> &Image {
+        match (&self.hd_pack, &mut self.ntsc) {
+            (Some(hd_pack), _) => {
+                hd_pack.render(frame, frame_number, &self.palette, &mut self.converted)
+            }
+            (None, Some(ntsc)) => ntsc.apply(frame, frame_number, &mut self.converted),
+            (None, None) => self.palette.convert(frame, &mut self.converted),
+        }
+        self.blender.apply(&mut self.converted);
+        self.filter.apply(&self.converted, &mut self.scaled);
+
+        let (x, y, width, height) = self.crop_area();
+        self.scaled.crop(x, y, width, height, &mut self.output);
+
+        &self.output
+    }
+
+    /// The image from the last processed frame
+    pub fn output(&self) -> &Image {
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn converts_then_scales() {
+        let mut pipeline = Pipeline::new(Palette::default(), None, ScaleFilter::Scale3x);
+        assert_eq!(pipeline.output_size(), (768, 720));
+
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, 0x30);
+        let image = pipeline.process(&frame, 0);
+
+        assert_eq!((image.width, image.height), (768, 720));
+        assert_eq!(image.pixel(4, 2), Palette::default().color(0x30));
+
+        pipeline.filter = ScaleFilter::None;
+        assert_eq!(pipeline.process(&frame, 1).width, 256);
+    }
+
+    #[test]
+    fn crops_overscan_after_scaling() {
+        let mut pipeline = Pipeline::new(Palette::default(), None, ScaleFilter::Scale2x);
+        pipeline.overscan = "8,8,0,0".parse().unwrap();
+        assert_eq!(pipeline.output_size(), (512, 448));
+        assert_eq!(pipeline.display_aspect(), (2048, 1568));
+
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 8, 0x30);
+        let image = pipeline.process(&frame, 0);
+
+        assert_eq!((image.width, image.height), (512, 448));
+        assert_eq!(image.pixel(1, 1), Palette::default().color(0x30));
+    }
+
+    #[test]
+    fn hd_packs_scale_the_output() {
+        let mut pipeline = Pipeline::new(Palette::default(), None, ScaleFilter::None);
+        let hd_pack = HdPack::parse("<scale>2", |_| unreachable!()).unwrap();
+        pipeline.set_hd_pack(hd_pack);
+        pipeline.overscan = "8,8,0,0".parse().unwrap();
+        assert_eq!(pipeline.output_size(), (512, 448));
+
+        // Frames without sources are scaled up as they are
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 8, 0x30);
+        let image = pipeline.process(&frame, 0);
+        assert_eq!(image.pixel(1, 1), Palette::default().color(0x30));
+    }
+}
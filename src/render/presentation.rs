@@ -0,0 +1,303 @@
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind},
+    str::FromStr,
+};
+
+use super::frame::{FRAME_HEIGHT, FRAME_WIDTH};
+use crate::region::Region;
+
+/// How the picture's shape is corrected for display
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AspectMode {
+    Square,  // 1:1 pixels
+    Pixel,   // 8:7 pixels, as on an NTSC TV
+    Display, // The visible picture fills a 4:3 screen
+}
+
+impl AspectMode {
+    /// Display aspect of a picture `width` x `height` NES pixels in size, as
+    /// a (width, height) fraction
+    pub fn display_aspect(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            AspectMode::Square => (width, height),
+            AspectMode::Pixel => (width * 8, height * 7),
+            AspectMode::Display => (4, 3),
+        }
+    }
+
+    pub fn next(&self) -> AspectMode {
+        match self {
+            AspectMode::Square => AspectMode::Pixel,
+            AspectMode::Pixel => AspectMode::Display,
+            AspectMode::Display => AspectMode::Square,
+        }
+    }
+}
+
+impl FromStr for AspectMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1:1" => Ok(AspectMode::Square),
+            "8:7" => Ok(AspectMode::Pixel),
+            "4:3" => Ok(AspectMode::Display),
+            _ => Err(format!("Unknown aspect ratio {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AspectMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AspectMode::Square => "1:1",
+            AspectMode::Pixel => "8:7",
+            AspectMode::Display => "4:3",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// NES pixels cropped from each edge of the frame. TVs hid a varying amount
+/// of the picture, which games often leave messy.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    pub fn for_region(region: Region) -> Overscan {
+        match region {
+            Region::Ntsc => Overscan {
+                top: 8,
+                bottom: 8,
+                ..Overscan::default()
+            },
+            Region::Pal | Region::Dendy => Overscan::default(),
+        }
+    }
+
+    /// Size of what's left of the frame
+    pub fn visible_size(&self) -> (usize, usize) {
+        (
+            FRAME_WIDTH - self.left - self.right,
+            FRAME_HEIGHT - self.top - self.bottom,
+        )
+    }
+}
+
+impl FromStr for Overscan {
+    type Err = String;
+
+    /// Parses "top,bottom,left,right"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let edges = s
+            .split(',')
+            .map(|edge| edge.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Invalid overscan {}", s))?;
+
+        match edges[..] {
+            [top, bottom, left, right]
+                if top + bottom < FRAME_HEIGHT && left + right < FRAME_WIDTH =>
+            {
+                Ok(Overscan {
+                    top,
+                    bottom,
+                    left,
+                    right,
+                })
+            }
+            _ => Err(format!("Invalid overscan {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Overscan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.top, self.bottom, self.left, self.right
+        )
+    }
+}
+
+/// Display settings that persist between runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Presentation {
+    pub aspect: AspectMode,
+    pub integer_scaling: bool,
+    pub overscan: Option<Overscan>, // None uses the region's default
+    pub fullscreen: bool,
+}
+
+impl Default for Presentation {
+    fn default() -> Self {
+        Presentation {
+            aspect: AspectMode::Pixel,
+            integer_scaling: false,
+            overscan: None,
+            fullscreen: false,
+        }
+    }
+}
+
+impl Presentation {
+    pub fn overscan(&self, region: Region) -> Overscan {
+        self.overscan
+            .unwrap_or_else(|| Overscan::for_region(region))
+    }
+
+    /// Parses `key = value` lines. Unknown keys are ignored so older
+    /// versions can read newer files.
+    pub fn parse(text: &str) -> Result<Presentation, String> {
+        let mut presentation = Presentation::default();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("Invalid setting {}", line)),
+            };
+            let flag = |value: &str| {
+                value
+                    .parse::<bool>()
+                    .map_err(|_| format!("Invalid value {} for {}", value, key))
+            };
+
+            match key {
+                "aspect" => presentation.aspect = value.parse()?,
+                "integer_scaling" => presentation.integer_scaling = flag(value)?,
+                "overscan" => presentation.overscan = Some(value.parse()?),
+                "fullscreen" => presentation.fullscreen = flag(value)?,
+                _ => (),
+            }
+        }
+
+        Ok(presentation)
+    }
+
+    /// Loads the settings, or the defaults when the file doesn't exist yet
+    pub fn load(filename: &str) -> io::Result<Presentation> {
+        match fs::read_to_string(filename) {
+            Ok(text) => Presentation::parse(&text)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Presentation::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        fs::write(filename, self.to_string())
+    }
+}
+
+impl fmt::Display for Presentation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "aspect = {}", self.aspect)?;
+        writeln!(f, "integer_scaling = {}", self.integer_scaling)?;
+        if let Some(overscan) = &self.overscan {
+            writeln!(f, "overscan = {}", overscan)?;
+        }
+        writeln!(f, "fullscreen = {}", self.fullscreen)
+    }
+}
+
+/// Where to draw a picture of `lines` NES lines with the given display
+/// aspect in a window, as (x, y, width, height). The rest of the window is
+/// letterboxed. Integer scaling applies to the lines, since the width is
+/// rarely a whole multiple once the aspect is corrected.
+pub fn viewport(
+    window: (u32, u32),
+    aspect: (usize, usize),
+    lines: usize,
+    integer_scaling: bool,
+) -> (i32, i32, u32, u32) {
+    let (window_width, window_height) = (window.0 as f64, window.1 as f64);
+    let aspect = aspect.0 as f64 / aspect.1 as f64;
+
+    let mut height = window_height.min(window_width / aspect);
+    if integer_scaling {
+        height = ((height / lines as f64).floor() * lines as f64).max(lines as f64);
+    }
+    let width = (height * aspect).round();
+
+    (
+        ((window_width - width) / 2.0).round() as i32,
+        ((window_height - height) / 2.0).round() as i32,
+        width as u32,
+        height as u32,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overscan_defaults_by_region() {
+        let presentation = Presentation::default();
+
+        assert_eq!(
+            presentation.overscan(Region::Ntsc).visible_size(),
+            (256, 224)
+        );
+        assert_eq!(
+            presentation.overscan(Region::Pal).visible_size(),
+            (256, 240)
+        );
+        assert_eq!(
+            "0,0,8,8".parse::<Overscan>().unwrap().visible_size(),
+            (240, 240)
+        );
+        assert!("0,0,8".parse::<Overscan>().is_err());
+        assert!("200,40,0,0".parse::<Overscan>().is_err());
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let presentation = Presentation {
+            aspect: AspectMode::Display,
+            integer_scaling: true,
+            overscan: Some("8,8,4,4".parse().unwrap()),
+            fullscreen: true,
+        };
+
+        let text = presentation.to_string();
+        assert_eq!(Presentation::parse(&text).unwrap(), presentation);
+        assert_eq!(
+            Presentation::parse("# comment\nfuture = 1\n").unwrap(),
+            Presentation::default()
+        );
+        assert!(Presentation::parse("aspect = 16:9").is_err());
+    }
+
+    #[test]
+    fn letterboxes_viewport() {
+        let aspect = AspectMode::Pixel.display_aspect(256, 224);
+
+        // Wide windows get bars at the sides
+        assert_eq!(
+            viewport((1920, 1080), aspect, 224, false),
+            (255, 0, 1411, 1080)
+        );
+        // Integer scaling rounds down to 4 times 224 lines
+        assert_eq!(
+            viewport((1920, 1080), aspect, 224, true),
+            (375, 92, 1170, 896)
+        );
+        // Tall windows get bars at the top and bottom
+        assert_eq!(
+            viewport((800, 1000), (4, 3), 240, false),
+            (0, 200, 800, 600)
+        );
+    }
+}
@@ -0,0 +1,841 @@
+use std::{fmt, str::FromStr};
+
+use super::image::Image;
+
+type Rgb = (u8, u8, u8);
+
+/// CPU upscaling and display effects applied to the converted image
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScaleFilter {
+    None,
+    Scale2x,
+    Scale3x,
+    Hq2x, // Maxim Stepin's hqx, blending by a table of neighbor patterns
+    Hq3x,
+    Xbr2x,
+    Xbr3x,
+    Scanlines, // 2x with darkened odd lines
+    CrtMask,   // 3x with an aperture grille and scanlines
+}
+
+const FILTERS: [ScaleFilter; 9] = [
+    ScaleFilter::None,
+    ScaleFilter::Scale2x,
+    ScaleFilter::Scale3x,
+    ScaleFilter::Hq2x,
+    ScaleFilter::Hq3x,
+    ScaleFilter::Xbr2x,
+    ScaleFilter::Xbr3x,
+    ScaleFilter::Scanlines,
+    ScaleFilter::CrtMask,
+];
+
+impl ScaleFilter {
+    pub fn scale(&self) -> usize {
+        match self {
+            ScaleFilter::None => 1,
+            ScaleFilter::Scale2x | ScaleFilter::Hq2x | ScaleFilter::Xbr2x => 2,
+            ScaleFilter::Scanlines => 2,
+            ScaleFilter::Scale3x | ScaleFilter::Hq3x | ScaleFilter::Xbr3x => 3,
+            ScaleFilter::CrtMask => 3,
+        }
+    }
+
+    /// The filter after this one, for cycling through them with a hotkey
+    pub fn next(&self) -> ScaleFilter {
+        let position = FILTERS.iter().position(|filter| filter == self).unwrap();
+        FILTERS[(position + 1) % FILTERS.len()]
+    }
+
+    pub fn apply(&self, source: &Image, output: &mut Image) {
+        let scale = self.scale();
+        if output.width != source.width * scale || output.height != source.height * scale {
+            *output = Image::new(source.width * scale, source.height * scale);
+        }
+
+        match self {
+            ScaleFilter::None => output.data.copy_from_slice(&source.data),
+            ScaleFilter::Scale2x => each_pixel(source, output, 2, scale2x),
+            ScaleFilter::Scale3x => each_pixel(source, output, 3, scale3x),
+            ScaleFilter::Hq2x => each_pixel(source, output, 2, hq2x),
+            ScaleFilter::Hq3x => each_pixel(source, output, 3, hq3x),
+            ScaleFilter::Xbr2x => each_pixel(source, output, 2, xbr2x),
+            ScaleFilter::Xbr3x => each_pixel(source, output, 3, xbr3x),
+            ScaleFilter::Scanlines => each_pixel(source, output, 2, |n, out| {
+                let e = n.get(0, 0);
+                out[0] = e;
+                out[1] = e;
+                out[2] = dim(e, 160);
+                out[3] = dim(e, 160);
+            }),
+            ScaleFilter::CrtMask => each_pixel(source, output, 3, crt_mask),
+        }
+    }
+}
+
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FILTERS
+            .iter()
+            .find(|filter| filter.to_string() == s.to_ascii_lowercase())
+            .copied()
+            .ok_or_else(|| format!("Unknown filter {}", s))
+    }
+}
+
+impl fmt::Display for ScaleFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ScaleFilter::None => "none",
+            ScaleFilter::Scale2x => "scale2x",
+            ScaleFilter::Scale3x => "scale3x",
+            ScaleFilter::Hq2x => "hq2x",
+            ScaleFilter::Hq3x => "hq3x",
+            ScaleFilter::Xbr2x => "xbr2x",
+            ScaleFilter::Xbr3x => "xbr3x",
+            ScaleFilter::Scanlines => "scanlines",
+            ScaleFilter::CrtMask => "crt",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The 5x5 neighborhood of a source pixel, clamped at the image edges
+struct Neighborhood<'a> {
+    image: &'a Image,
+    x: usize,
+    y: usize,
+}
+
+impl<'a> Neighborhood<'a> {
+    fn get(&self, dx: isize, dy: isize) -> Rgb {
+        let clamp = |value: isize, limit: usize| value.max(0).min(limit as isize - 1) as usize;
+        self.image.pixel(
+            clamp(self.x as isize + dx, self.image.width),
+            clamp(self.y as isize + dy, self.image.height),
+        )
+    }
+}
+
+/// Runs a kernel that turns each source pixel into a scale x scale block,
+/// given in row order
+fn each_pixel<F>(source: &Image, output: &mut Image, scale: usize, kernel: F)
+where
+    F: Fn(&Neighborhood, &mut [Rgb]),
+{
+    let mut block = vec![(0, 0, 0); scale * scale];
+
+    for y in 0..source.height {
+        for x in 0..source.width {
+            kernel(
+                &Neighborhood {
+                    image: source,
+                    x,
+                    y,
+                },
+                &mut block,
+            );
+
+            for (i, &rgb) in block.iter().enumerate() {
+                output.set_pixel(x * scale + i % scale, y * scale + i / scale, rgb);
+            }
+        }
+    }
+}
+
+//   A B C
+//   D E F
+//   G H I
+fn scale2x(n: &Neighborhood, out: &mut [Rgb]) {
+    let (b, d, e, f, h) = (
+        n.get(0, -1),
+        n.get(-1, 0),
+        n.get(0, 0),
+        n.get(1, 0),
+        n.get(0, 1),
+    );
+
+    if b != h && d != f {
+        out[0] = if d == b { d } else { e };
+        out[1] = if b == f { f } else { e };
+        out[2] = if d == h { d } else { e };
+        out[3] = if h == f { f } else { e };
+    } else {
+        out.iter_mut().for_each(|pixel| *pixel = e);
+    }
+}
+
+fn scale3x(n: &Neighborhood, out: &mut [Rgb]) {
+    let (a, b, c) = (n.get(-1, -1), n.get(0, -1), n.get(1, -1));
+    let (d, e, f) = (n.get(-1, 0), n.get(0, 0), n.get(1, 0));
+    let (g, h, i) = (n.get(-1, 1), n.get(0, 1), n.get(1, 1));
+
+    out.iter_mut().for_each(|pixel| *pixel = e);
+    if b == h || d == f {
+        return;
+    }
+
+    if d == b {
+        out[0] = d;
+    }
+    if (d == b && e != c) || (b == f && e != a) {
+        out[1] = b;
+    }
+    if b == f {
+        out[2] = f;
+    }
+    if (d == b && e != g) || (d == h && e != a) {
+        out[3] = d;
+    }
+    if (b == f && e != i) || (h == f && e != c) {
+        out[5] = f;
+    }
+    if d == h {
+        out[6] = d;
+    }
+    if (d == h && e != i) || (h == f && e != g) {
+        out[7] = h;
+    }
+    if h == f {
+        out[8] = f;
+    }
+}
+
+fn yuv((r, g, b): Rgb) -> (i32, i32, i32) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+
+    (
+        (299 * r + 587 * g + 114 * b) / 1000,
+        (-169 * r - 331 * g + 500 * b) / 1000 + 128,
+        (500 * r - 419 * g - 81 * b) / 1000 + 128,
+    )
+}
+
+/// The test hqx uses for colors that are visibly different
+fn differs(a: Rgb, b: Rgb) -> bool {
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+
+    (ya - yb).abs() > 48 || (ua - ub).abs() > 7 || (va - vb).abs() > 6
+}
+
+/// Weighted average of colors
+fn mix(colors: &[(Rgb, u32)]) -> Rgb {
+    let total: u32 = colors.iter().map(|&(_, weight)| weight).sum();
+    let channel = |get: fn(Rgb) -> u8| {
+        (colors
+            .iter()
+            .map(|&(rgb, weight)| get(rgb) as u32 * weight)
+            .sum::<u32>()
+            / total) as u8
+    };
+
+    (channel(|c| c.0), channel(|c| c.1), channel(|c| c.2))
+}
+
+/// hqx's view of a 3x3 neighborhood, turned or mirrored so that the output
+/// pixel being worked out sits in the top left corner. `w` holds the pixels
+/// in row order and `pattern` has a bit for each of the eight around the
+/// center, set when that one differs from it.
+struct HqView {
+    w: [Rgb; 9],
+    pattern: u8,
+}
+
+/// Bit in a hqx pattern for neighbor `i`, skipping the center
+fn hq_bit(i: usize) -> u8 {
+    1 << if i > 4 { i - 1 } else { i }
+}
+
+impl HqView {
+    fn new(n: &Neighborhood) -> HqView {
+        let mut w = [(0, 0, 0); 9];
+        for (i, pixel) in w.iter_mut().enumerate() {
+            *pixel = n.get(i as isize % 3 - 1, i as isize / 3 - 1);
+        }
+
+        let pattern = (0..9)
+            .filter(|&i| i != 4 && w[i] != w[4] && differs(w[i], w[4]))
+            .fold(0, |pattern, i| pattern | hq_bit(i));
+
+        HqView { w, pattern }
+    }
+
+    /// The same neighborhood with pixel `p[i]` moved to position `i`
+    fn turn(&self, p: &[usize; 9]) -> HqView {
+        let mut w = [(0, 0, 0); 9];
+        let mut pattern = 0;
+        for i in 0..9 {
+            w[i] = self.w[p[i]];
+            if i != 4 && self.pattern & hq_bit(p[i]) != 0 {
+                pattern |= hq_bit(i);
+            }
+        }
+
+        HqView { w, pattern }
+    }
+
+    /// Whether the neighbors in `mask` differ from the center exactly where
+    /// `result` has bits set
+    fn is(&self, mask: u8, result: u8) -> bool {
+        self.pattern & mask == result
+    }
+
+    fn any(&self, cases: &[(u8, u8)]) -> bool {
+        cases.iter().any(|&(mask, result)| self.is(mask, result))
+    }
+
+    fn differs(&self, a: usize, b: usize) -> bool {
+        differs(self.w[a], self.w[b])
+    }
+}
+
+/// Mirror images of the neighborhood that bring each corner of a 2x block to
+/// the top left
+const HQ2X_VIEWS: [[usize; 9]; 4] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8],
+    [2, 1, 0, 5, 4, 3, 8, 7, 6],
+    [6, 7, 8, 3, 4, 5, 0, 1, 2],
+    [8, 7, 6, 5, 4, 3, 2, 1, 0],
+];
+
+/// Quarter turns of the neighborhood that bring each corner of a 3x block to
+/// the top left, with the output positions of that corner and the edge pixel
+/// clockwise from it
+const HQ3X_VIEWS: [([usize; 9], usize, usize); 4] = [
+    ([0, 1, 2, 3, 4, 5, 6, 7, 8], 0, 1),
+    ([2, 5, 8, 1, 4, 7, 0, 3, 6], 2, 5),
+    ([6, 3, 0, 7, 4, 1, 8, 5, 2], 6, 3),
+    ([8, 7, 6, 5, 4, 3, 2, 1, 0], 8, 7),
+];
+
+/// The top left pixel of a hq2x block. These are hq2x's 256 pattern cases,
+/// grouped by the interpolation each one picks for this corner.
+fn hq2x_corner(v: &HqView) -> Rgb {
+    let w = &v.w;
+
+    if v.any(&[(0xbf, 0x37), (0xdb, 0x13)]) && v.differs(1, 5) {
+        mix(&[(w[4], 3), (w[3], 1)])
+    } else if v.any(&[(0xdb, 0x49), (0xef, 0x6d)]) && v.differs(7, 3) {
+        mix(&[(w[4], 3), (w[1], 1)])
+    } else if v.any(&[(0x0b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && v.differs(3, 1) {
+        w[4]
+    } else if v.any(&[
+        (0x6f, 0x2a),
+        (0x5b, 0x0a),
+        (0xbf, 0x3a),
+        (0xdf, 0x5a),
+        (0x9f, 0x8a),
+        (0xcf, 0x8a),
+        (0xef, 0x4e),
+        (0x3f, 0x0e),
+        (0xfb, 0x5a),
+        (0xbb, 0x8a),
+        (0x7f, 0x5a),
+        (0xaf, 0x8a),
+        (0xeb, 0x8a),
+    ]) && v.differs(3, 1)
+    {
+        mix(&[(w[4], 3), (w[0], 1)])
+    } else if v.is(0x0b, 0x08) {
+        mix(&[(w[4], 2), (w[0], 1), (w[1], 1)])
+    } else if v.is(0x0b, 0x02) {
+        mix(&[(w[4], 2), (w[0], 1), (w[3], 1)])
+    } else if v.is(0x2f, 0x2f) {
+        mix(&[(w[4], 14), (w[3], 1), (w[1], 1)])
+    } else if v.any(&[(0xbf, 0x37), (0xdb, 0x13)]) {
+        mix(&[(w[4], 5), (w[1], 2), (w[3], 1)])
+    } else if v.any(&[(0xdb, 0x49), (0xef, 0x6d)]) {
+        mix(&[(w[4], 5), (w[3], 2), (w[1], 1)])
+    } else if v.any(&[(0x1b, 0x03), (0x4f, 0x43), (0x8b, 0x83), (0x6b, 0x43)]) {
+        mix(&[(w[4], 3), (w[3], 1)])
+    } else if v.any(&[(0x4b, 0x09), (0x8b, 0x89), (0x1f, 0x19), (0x3b, 0x19)]) {
+        mix(&[(w[4], 3), (w[1], 1)])
+    } else if v.any(&[(0x7e, 0x2a), (0xef, 0xab), (0xbf, 0x8f), (0x7e, 0x0e)]) {
+        mix(&[(w[4], 2), (w[3], 3), (w[1], 3)])
+    } else if v.any(&[
+        (0xfb, 0x6a),
+        (0x6f, 0x6e),
+        (0x3f, 0x3e),
+        (0xfb, 0xfa),
+        (0xdf, 0xde),
+        (0xdf, 0x1e),
+    ]) {
+        mix(&[(w[4], 3), (w[0], 1)])
+    } else if v.any(&[
+        (0x0a, 0x00),
+        (0x4f, 0x4b),
+        (0x9f, 0x1b),
+        (0x2f, 0x0b),
+        (0xbe, 0x0a),
+        (0xee, 0x0a),
+        (0x7e, 0x0a),
+        (0xeb, 0x4b),
+        (0x3b, 0x1b),
+    ]) {
+        mix(&[(w[4], 2), (w[3], 1), (w[1], 1)])
+    } else {
+        mix(&[(w[4], 6), (w[3], 1), (w[1], 1)])
+    }
+}
+
+/// The top left corner of a hq3x block and the edge pixel to its right, from
+/// hq3x's 256 pattern cases
+fn hq3x_corner(v: &HqView) -> (Rgb, Rgb) {
+    let w = &v.w;
+
+    let corner = if v.any(&[(0xbf, 0x37), (0xdb, 0x13)]) && v.differs(1, 5) {
+        mix(&[(w[4], 3), (w[3], 1)])
+    } else if v.any(&[(0xdb, 0x49), (0xef, 0x6d)]) && v.differs(7, 3) {
+        mix(&[(w[4], 3), (w[1], 1)])
+    } else if v.any(&[(0x0b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && v.differs(3, 1) {
+        w[4]
+    } else if v.any(&[
+        (0x6f, 0x2a),
+        (0x5b, 0x0a),
+        (0xbf, 0x3a),
+        (0xdf, 0x5a),
+        (0x9f, 0x8a),
+        (0xcf, 0x8a),
+        (0xef, 0x4e),
+        (0x3f, 0x0e),
+        (0xfb, 0x5a),
+        (0xbb, 0x8a),
+        (0x7f, 0x5a),
+        (0xaf, 0x8a),
+        (0xeb, 0x8a),
+    ]) && v.differs(3, 1)
+    {
+        mix(&[(w[4], 3), (w[0], 1)])
+    } else if v.any(&[(0x4b, 0x09), (0x8b, 0x89), (0x1f, 0x19), (0x3b, 0x19)]) {
+        mix(&[(w[4], 3), (w[1], 1)])
+    } else if v.any(&[(0x1b, 0x03), (0x4f, 0x43), (0x8b, 0x83), (0x6b, 0x43)]) {
+        mix(&[(w[4], 3), (w[3], 1)])
+    } else if v.any(&[(0x7e, 0x2a), (0xef, 0xab), (0xbf, 0x8f), (0x7e, 0x0e)]) {
+        mix(&[(w[3], 1), (w[1], 1)])
+    } else if v.any(&[
+        (0x4f, 0x4b),
+        (0x9f, 0x1b),
+        (0x2f, 0x0b),
+        (0xbe, 0x0a),
+        (0xee, 0x0a),
+        (0x7e, 0x0a),
+        (0xeb, 0x4b),
+        (0x3b, 0x1b),
+    ]) {
+        mix(&[(w[4], 2), (w[3], 7), (w[1], 7)])
+    } else if v.any(&[
+        (0x0b, 0x08),
+        (0xf9, 0x68),
+        (0xf3, 0x62),
+        (0x6d, 0x6c),
+        (0x67, 0x66),
+        (0x3d, 0x3c),
+        (0x37, 0x36),
+        (0xf9, 0xf8),
+        (0xdd, 0xdc),
+        (0xf3, 0xf2),
+        (0xd7, 0xd6),
+        (0xdd, 0x1c),
+        (0xd7, 0x16),
+        (0x0b, 0x02),
+    ]) {
+        mix(&[(w[4], 3), (w[0], 1)])
+    } else {
+        mix(&[(w[4], 2), (w[3], 1), (w[1], 1)])
+    };
+
+    let edge = if (v.any(&[
+        (0xfe, 0xde),
+        (0x9e, 0x16),
+        (0xda, 0x12),
+        (0x17, 0x16),
+        (0x5b, 0x12),
+        (0xbb, 0x12),
+    ]) && v.differs(1, 5))
+        || (v.any(&[
+            (0x0f, 0x0b),
+            (0x5e, 0x0a),
+            (0xfb, 0x7b),
+            (0x3b, 0x0b),
+            (0xbe, 0x0a),
+            (0x7a, 0x0a),
+        ]) && v.differs(3, 1))
+    {
+        w[4]
+    } else if v.any(&[(0xbf, 0x8f), (0x7e, 0x0e), (0xbf, 0x37), (0xdb, 0x13)]) {
+        mix(&[(w[1], 3), (w[4], 1)])
+    } else if v.any(&[
+        (0x02, 0x00),
+        (0x7c, 0x28),
+        (0xed, 0xa9),
+        (0xf5, 0xb4),
+        (0xd9, 0x90),
+    ]) {
+        mix(&[(w[4], 3), (w[1], 1)])
+    } else if v.any(&[
+        (0x4f, 0x4b),
+        (0xfb, 0x7b),
+        (0xfe, 0x7e),
+        (0x9f, 0x1b),
+        (0x2f, 0x0b),
+        (0xbe, 0x0a),
+        (0x7e, 0x0a),
+        (0xfb, 0x4b),
+        (0xfb, 0xdb),
+        (0xfe, 0xde),
+        (0xfe, 0x56),
+        (0x57, 0x56),
+        (0x97, 0x16),
+        (0x3f, 0x1e),
+        (0xdb, 0x12),
+        (0xbb, 0x12),
+    ]) {
+        mix(&[(w[4], 7), (w[1], 1)])
+    } else {
+        w[4]
+    };
+
+    (corner, edge)
+}
+
+fn hq2x(n: &Neighborhood, out: &mut [Rgb]) {
+    let view = HqView::new(n);
+
+    for (pixel, p) in out.iter_mut().zip(HQ2X_VIEWS.iter()) {
+        *pixel = hq2x_corner(&view.turn(p));
+    }
+}
+
+fn hq3x(n: &Neighborhood, out: &mut [Rgb]) {
+    let view = HqView::new(n);
+
+    for (p, corner, edge) in HQ3X_VIEWS.iter() {
+        let pixels = hq3x_corner(&view.turn(p));
+        out[*corner] = pixels.0;
+        out[*edge] = pixels.1;
+    }
+    out[4] = view.w[4];
+}
+
+/// Color distance used by xBR
+fn distance(a: Rgb, b: Rgb) -> i32 {
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+
+    (ya - yb).abs() + (ua - ub).abs() + (va - vb).abs()
+}
+
+fn similar(a: Rgb, b: Rgb) -> bool {
+    distance(a, b) < 155
+}
+
+/// Moves `dst` towards `src` by weight/256
+fn blend(dst: &mut Rgb, src: Rgb, weight: i32) {
+    let channel = |d: u8, s: u8| (d as i32 + (s as i32 - d as i32) * weight / 256) as u8;
+    *dst = (
+        channel(dst.0, src.0),
+        channel(dst.1, src.1),
+        channel(dst.2, src.2),
+    );
+}
+
+/// How xBR blends a block corner that an edge runs through
+enum XbrEdge {
+    Shallow, // Closer to horizontal
+    Steep,   // Closer to vertical
+    Both,
+    Diagonal,
+    Soft, // Where the corner only ties with the other diagonal
+}
+
+// The 5x5 neighborhood of xBR, without its corners
+//
+//      A1 B1 C1
+//   A0 PA PB PC C4
+//   D0 PD PE PF F4
+//   G0 PG PH PI I4
+//      G5 H5 I5
+//
+// Each call looks at the corner of the block towards PI, and returns the
+// kind of edge there and the color to blend in.
+fn xbr_edge(
+    [pe, pi, ph, pf, pg, pc, pd, pb]: [Rgb; 8],
+    [f4, i4, h5, i5]: [Rgb; 4],
+) -> Option<(XbrEdge, Rgb)> {
+    if pe == ph || pe == pf {
+        return None;
+    }
+
+    let e = distance(pe, pc)
+        + distance(pe, pg)
+        + distance(pi, h5)
+        + distance(pi, f4)
+        + 4 * distance(ph, pf);
+    let i = distance(ph, pd)
+        + distance(ph, i5)
+        + distance(pf, i4)
+        + distance(pf, pb)
+        + 4 * distance(pe, pi);
+
+    let px = if distance(pe, pf) <= distance(pe, ph) {
+        pf
+    } else {
+        ph
+    };
+
+    if e < i
+        && ((!similar(pf, pb) && !similar(ph, pd))
+            || (similar(pe, pi) && !similar(pf, i4) && !similar(ph, i5))
+            || similar(pe, pg)
+            || similar(pe, pc))
+    {
+        let ke = distance(pf, pg);
+        let ki = distance(ph, pc);
+        let ex2 = pe != pc && pb != pc;
+        let ex3 = pe != pg && pd != pg;
+
+        let edge = match (2 * ke <= ki && ex3, ke >= 2 * ki && ex2) {
+            (true, true) => XbrEdge::Both,
+            (true, false) => XbrEdge::Shallow,
+            (false, true) => XbrEdge::Steep,
+            (false, false) => XbrEdge::Diagonal,
+        };
+        Some((edge, px))
+    } else if e <= i {
+        Some((XbrEdge::Soft, px))
+    } else {
+        None
+    }
+}
+
+/// The neighborhood xBR looks at, rotated to face each of the block's four
+/// corners in turn: bottom right, top right, top left, then bottom left
+fn xbr_rotations(n: &Neighborhood) -> [([Rgb; 8], [Rgb; 4]); 4] {
+    let (pa, pb, pc) = (n.get(-1, -1), n.get(0, -1), n.get(1, -1));
+    let (pd, pe, pf) = (n.get(-1, 0), n.get(0, 0), n.get(1, 0));
+    let (pg, ph, pi) = (n.get(-1, 1), n.get(0, 1), n.get(1, 1));
+    let (a1, b1, c1) = (n.get(-1, -2), n.get(0, -2), n.get(1, -2));
+    let (a0, d0, g0) = (n.get(-2, -1), n.get(-2, 0), n.get(-2, 1));
+    let (c4, f4, i4) = (n.get(2, -1), n.get(2, 0), n.get(2, 1));
+    let (g5, h5, i5) = (n.get(-1, 2), n.get(0, 2), n.get(1, 2));
+
+    [
+        ([pe, pi, ph, pf, pg, pc, pd, pb], [f4, i4, h5, i5]),
+        ([pe, pc, pf, pb, pi, pa, ph, pd], [b1, c1, f4, c4]),
+        ([pe, pa, pb, pd, pc, pg, pf, ph], [d0, a0, b1, a1]),
+        ([pe, pg, pd, ph, pa, pi, pb, pf], [h5, g5, d0, g0]),
+    ]
+}
+
+fn xbr2x(n: &Neighborhood, out: &mut [Rgb]) {
+    out.iter_mut().for_each(|pixel| *pixel = n.get(0, 0));
+
+    // For each rotation, the block pixels below and right of the corner as
+    // it faces, and the corner itself
+    let corners = [(1, 2, 3), (0, 3, 1), (2, 1, 0), (3, 0, 2)];
+
+    for (&(neighbors, outer), &(n1, n2, n3)) in xbr_rotations(n).iter().zip(corners.iter()) {
+        match xbr_edge(neighbors, outer) {
+            Some((XbrEdge::Both, px)) => {
+                blend(&mut out[n3], px, 224);
+                blend(&mut out[n2], px, 64);
+                out[n1] = out[n2];
+            }
+            Some((XbrEdge::Shallow, px)) => {
+                blend(&mut out[n3], px, 192);
+                blend(&mut out[n2], px, 64);
+            }
+            Some((XbrEdge::Steep, px)) => {
+                blend(&mut out[n3], px, 192);
+                blend(&mut out[n1], px, 64);
+            }
+            Some((XbrEdge::Diagonal, px)) | Some((XbrEdge::Soft, px)) => {
+                blend(&mut out[n3], px, 128)
+            }
+            None => (),
+        }
+    }
+}
+
+fn xbr3x(n: &Neighborhood, out: &mut [Rgb]) {
+    out.iter_mut().for_each(|pixel| *pixel = n.get(0, 0));
+
+    // For each rotation, as it faces: the far end of the right side, the
+    // middle of the right side, the far end of the bottom, the middle of the
+    // bottom, and the corner itself
+    let corners = [
+        (2, 5, 6, 7, 8),
+        (0, 1, 8, 5, 2),
+        (6, 3, 2, 1, 0),
+        (8, 7, 0, 3, 6),
+    ];
+
+    for (&(neighbors, outer), &(n2, n5, n6, n7, n8)) in xbr_rotations(n).iter().zip(corners.iter())
+    {
+        match xbr_edge(neighbors, outer) {
+            Some((XbrEdge::Both, px)) => {
+                blend(&mut out[n7], px, 192);
+                blend(&mut out[n6], px, 64);
+                out[n5] = out[n7];
+                out[n2] = out[n6];
+                out[n8] = px;
+            }
+            Some((XbrEdge::Shallow, px)) => {
+                blend(&mut out[n7], px, 192);
+                blend(&mut out[n5], px, 64);
+                blend(&mut out[n6], px, 64);
+                out[n8] = px;
+            }
+            Some((XbrEdge::Steep, px)) => {
+                blend(&mut out[n5], px, 192);
+                blend(&mut out[n7], px, 64);
+                blend(&mut out[n2], px, 64);
+                out[n8] = px;
+            }
+            Some((XbrEdge::Diagonal, px)) => {
+                blend(&mut out[n8], px, 224);
+                blend(&mut out[n5], px, 32);
+                blend(&mut out[n7], px, 32);
+            }
+            Some((XbrEdge::Soft, px)) => blend(&mut out[n8], px, 128),
+            None => (),
+        }
+    }
+}
+
+fn dim((r, g, b): Rgb, level: u32) -> Rgb {
+    let scale = |channel: u8| (channel as u32 * level / 255) as u8;
+    (scale(r), scale(g), scale(b))
+}
+
+fn crt_mask(n: &Neighborhood, out: &mut [Rgb]) {
+    let (r, g, b) = n.get(0, 0);
+    let mask = |channel: u8| (channel as u32 * 180 / 255) as u8;
+
+    // Each column of the block lets one phosphor through at full strength
+    let columns = [
+        (r, mask(g), mask(b)),
+        (mask(r), g, mask(b)),
+        (mask(r), mask(g), b),
+    ];
+    for (i, pixel) in out.iter_mut().enumerate() {
+        let column = columns[i % 3];
+        *pixel = if i / 3 == 2 { dim(column, 140) } else { column };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BLACK: Rgb = (0, 0, 0);
+    const WHITE: Rgb = (255, 255, 255);
+
+    /// A white staircase diagonal on black, which every filter should smooth
+    fn diagonal() -> Image {
+        let mut image = Image::new(4, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                image.set_pixel(x, y, if x <= y { WHITE } else { BLACK });
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn output_sizes() {
+        let source = Image::new(4, 3);
+        let mut output = Image::new(1, 1);
+
+        for filter in FILTERS.iter() {
+            filter.apply(&source, &mut output);
+            assert_eq!(output.width, 4 * filter.scale());
+            assert_eq!(output.height, 3 * filter.scale());
+        }
+    }
+
+    #[test]
+    fn scale2x_fills_diagonal_steps() {
+        let mut output = Image::new(8, 8);
+        ScaleFilter::Scale2x.apply(&diagonal(), &mut output);
+
+        // The black pixel above the diagonal gets its lower left filled
+        assert_eq!(output.pixel(2, 0), BLACK);
+        assert_eq!(output.pixel(2, 1), WHITE);
+        assert_eq!(output.pixel(3, 1), BLACK);
+    }
+
+    #[test]
+    fn smoothing_filters_blend_edges() {
+        for filter in [ScaleFilter::Hq2x, ScaleFilter::Xbr2x].iter() {
+            let mut output = Image::new(8, 8);
+            filter.apply(&diagonal(), &mut output);
+
+            let (r, _, _) = output.pixel(2, 1);
+            assert!(r > 0 && r < 255, "{} left a hard edge", filter);
+            // Flat areas are untouched
+            assert_eq!(output.pixel(0, 6), WHITE);
+            assert_eq!(output.pixel(7, 0), BLACK);
+        }
+
+        for filter in [ScaleFilter::Hq3x, ScaleFilter::Xbr3x].iter() {
+            let mut output = Image::new(12, 12);
+            filter.apply(&diagonal(), &mut output);
+
+            let (r, _, _) = output.pixel(3, 1);
+            assert!(r > 0 && r < 255, "{} left a hard edge", filter);
+            assert_eq!(output.pixel(0, 10), WHITE);
+            assert_eq!(output.pixel(11, 0), BLACK);
+        }
+    }
+
+    #[test]
+    fn hqx_follows_neighbor_patterns() {
+        // Only the pixel above differs from the center. The top left one is
+        // close enough to count as the same color, but still gets blended in.
+        let mut source = Image::new(3, 3);
+        for y in 0..3 {
+            for x in 0..3 {
+                source.set_pixel(x, y, (100, 100, 100));
+            }
+        }
+        source.set_pixel(0, 0, (104, 100, 100));
+        source.set_pixel(1, 0, WHITE);
+        let mut output = Image::new(1, 1);
+
+        ScaleFilter::Hq2x.apply(&source, &mut output);
+        assert_eq!(output.pixel(2, 2), (101, 100, 100));
+        assert_eq!(output.pixel(3, 2), (100, 100, 100));
+
+        ScaleFilter::Hq3x.apply(&source, &mut output);
+        assert_eq!(output.pixel(3, 3), (101, 100, 100));
+        assert_eq!(output.pixel(4, 3), (100, 100, 100));
+        assert_eq!(output.pixel(4, 4), (100, 100, 100));
+    }
+
+    #[test]
+    fn crt_and_scanlines_darken_lines() {
+        let mut source = Image::new(1, 1);
+        source.set_pixel(0, 0, WHITE);
+        let mut output = Image::new(1, 1);
+
+        ScaleFilter::Scanlines.apply(&source, &mut output);
+        assert_eq!(output.pixel(0, 0), WHITE);
+        assert_eq!(output.pixel(0, 1), (160, 160, 160));
+
+        ScaleFilter::CrtMask.apply(&source, &mut output);
+        assert_eq!(output.pixel(0, 0), (255, 180, 180));
+        assert_eq!(output.pixel(2, 2), (98, 98, 140));
+    }
+
+    #[test]
+    fn parses_and_cycles() {
+        assert_eq!("HQ3X".parse::<ScaleFilter>(), Ok(ScaleFilter::Hq3x));
+        assert_eq!("hq2x".parse::<ScaleFilter>(), Ok(ScaleFilter::Hq2x));
+        assert!("smooth2x".parse::<ScaleFilter>().is_err());
+        assert_eq!(ScaleFilter::CrtMask.next(), ScaleFilter::None);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    str::FromStr,
+};
+
+use super::{
+    frame::{Frame, FRAME_HEIGHT, FRAME_WIDTH},
+    image::Image,
+    palette::Palette,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScreenshotMode {
+    Raw,             // The PPU's 256x240 frame through the palette
+    Filtered,        // The image as displayed, after any filters
+    AspectCorrected, // The filtered image stretched to the display aspect
+}
+
+impl FromStr for ScreenshotMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "raw" => Ok(ScreenshotMode::Raw),
+            "filtered" => Ok(ScreenshotMode::Filtered),
+            "aspect" => Ok(ScreenshotMode::AspectCorrected),
+            _ => Err(format!("Unknown screenshot mode {}", s)),
+        }
+    }
+}
+
+/// Details embedded in a screenshot's text chunks
+pub struct Metadata {
+    pub rom_name: String,
+    pub rom_crc32: u32,
+    pub frame: u64,
+}
+
+impl Metadata {
+    fn text(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Software", format!("nesrs {}", env!("CARGO_PKG_VERSION"))),
+            ("ROM", self.rom_name.clone()),
+            ("ROM CRC32", format!("{:08X}", self.rom_crc32)),
+            ("Frame", self.frame.to_string()),
+        ]
+    }
+}
+
+/// Pixel aspect ratio that shows an image of this size at the display aspect,
+/// as a reduced fraction
+pub fn pixel_aspect(width: usize, height: usize, display_aspect: (usize, usize)) -> (u32, u32) {
+    let numerator = display_aspect.0 * height;
+    let denominator = display_aspect.1 * width;
+    let divisor = gcd(numerator, denominator);
+
+    ((numerator / divisor) as u32, (denominator / divisor) as u32)
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Builds the screenshot image. `filtered` is the image being displayed and
+/// `display_aspect` the (width, height) shape it's shown at.
+pub fn capture(
+    mode: ScreenshotMode,
+    frame: &Frame,
+    palette: &Palette,
+    filtered: &Image,
+    display_aspect: (usize, usize),
+) -> Image {
+    match mode {
+        ScreenshotMode::Raw => {
+            let mut image = Image::new(FRAME_WIDTH, FRAME_HEIGHT);
+            palette.convert(frame, &mut image);
+            image
+        }
+        ScreenshotMode::Filtered => filtered.clone(),
+        ScreenshotMode::AspectCorrected => {
+            // Stretch whichever side is short so no detail is lost
+            let display_aspect = display_aspect.0 as f64 / display_aspect.1 as f64;
+            let aspect = filtered.width as f64 / filtered.height as f64;
+            if aspect > display_aspect {
+                let height = (filtered.width as f64 / display_aspect).round() as usize;
+                filtered.scale_to(filtered.width, height)
+            } else {
+                let width = (filtered.height as f64 * display_aspect).round() as usize;
+                filtered.scale_to(width, filtered.height)
+            }
+        }
+    }
+}
+
+pub fn write<W: Write>(image: &Image, metadata: &Metadata, writer: W) -> io::Result<()> {
+    image.write_png(writer, &metadata.text())
+}
+
+pub fn save(image: &Image, metadata: &Metadata, filename: &str) -> io::Result<()> {
+    write(image, metadata, BufWriter::new(File::create(filename)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            rom_name: "game".to_string(),
+            rom_crc32: 0xDEADBEEF,
+            frame: 42,
+        }
+    }
+
+    #[test]
+    fn capture_modes() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, 0x30);
+        let palette = Palette::default();
+        let filtered = Image::new(512, 480);
+        let pixel = (8 * 256, 7 * 240);
+
+        let raw = capture(ScreenshotMode::Raw, &frame, &palette, &filtered, pixel);
+        assert_eq!((raw.width, raw.height), (256, 240));
+        assert_eq!(raw.pixel(0, 0), palette.color(0x30));
+
+        let aspect = capture(
+            ScreenshotMode::AspectCorrected,
+            &frame,
+            &palette,
+            &filtered,
+            pixel,
+        );
+        assert_eq!((aspect.width, aspect.height), (585, 480));
+
+        let wide = Image::new(602, 240);
+        let aspect = capture(
+            ScreenshotMode::AspectCorrected,
+            &frame,
+            &palette,
+            &wide,
+            pixel,
+        );
+        assert_eq!((aspect.width, aspect.height), (602, 494));
+
+        let aspect = capture(
+            ScreenshotMode::AspectCorrected,
+            &frame,
+            &palette,
+            &filtered,
+            (4, 3),
+        );
+        assert_eq!((aspect.width, aspect.height), (640, 480));
+    }
+
+    #[test]
+    fn pixel_aspects() {
+        let pixel = (8 * 256, 7 * 240);
+        assert_eq!(pixel_aspect(256, 240, pixel), (8, 7));
+        assert_eq!(pixel_aspect(768, 720, pixel), (8, 7));
+        assert_eq!(pixel_aspect(602, 240, pixel), (1024, 2107));
+        assert_eq!(pixel_aspect(256, 224, (4, 3)), (7, 6));
+    }
+
+    #[test]
+    fn embeds_metadata() {
+        let mut data = Vec::new();
+        write(&Image::new(4, 4), &metadata(), &mut data).unwrap();
+
+        let decoder = png::Decoder::new(&data[..]);
+        let reader = decoder.read_info().unwrap();
+        let text = &reader.info().uncompressed_latin1_text;
+
+        let value = |keyword: &str| {
+            text.iter()
+                .find(|chunk| chunk.keyword == keyword)
+                .map(|chunk| chunk.text.clone())
+        };
+        assert_eq!(value("ROM CRC32"), Some("DEADBEEF".to_string()));
+        assert_eq!(value("Frame"), Some("42".to_string()));
+        assert!(value("Software").unwrap().starts_with("nesrs"));
+    }
+
+    #[test]
+    fn embeds_names_outside_latin1() {
+        let mut data = Vec::new();
+        let metadata = Metadata {
+            rom_name: "ロックマン".to_string(),
+            ..metadata()
+        };
+        write(&Image::new(4, 4), &metadata, &mut data).unwrap();
+
+        let decoder = png::Decoder::new(&data[..]);
+        let reader = decoder.read_info().unwrap();
+        let chunk = reader
+            .info()
+            .utf8_text
+            .iter()
+            .find(|chunk| chunk.keyword == "ROM")
+            .unwrap();
+
+        assert_eq!(chunk.get_text().unwrap(), "ロックマン");
+    }
+}